@@ -68,7 +68,9 @@ let
     inherit src;
 
     buildInputs =
-      lib.optionals pkgs.stdenv.isLinux (
+      # Linked by the voice chat codec, found with pkg-config.
+      (with pkgs; [ libopus ])
+      ++ lib.optionals pkgs.stdenv.isLinux (
         with pkgs;
         [
           alsa-lib
@@ -13,12 +13,20 @@ use avian3d::prelude::*;
 use dwn::{actor::Actor, store::SurrealStore, DWN};
 use surrealdb::{engine::local::Db, Surreal};
 use unavi_dwn::UserActor;
+use unavi_networking::SimulatedConditions;
+use xwt_netsim::Conditions;
 
 mod unavi_system;
 
 pub struct StartOptions {
     pub debug_physics: bool,
     pub log_level: Level,
+    /// Logs network stats at this interval, in seconds.
+    pub log_network_stats: Option<f32>,
+    /// Opens the microphone for voice chat.
+    pub microphone: bool,
+    /// Network conditions to simulate, for testing under bad networks.
+    pub network_conditions: Conditions,
 }
 
 impl Default for StartOptions {
@@ -26,6 +34,9 @@ impl Default for StartOptions {
         Self {
             debug_physics: false,
             log_level: Level::INFO,
+            log_network_stats: None,
+            microphone: false,
+            network_conditions: Conditions::default(),
         }
     }
 }
@@ -43,7 +54,8 @@ pub async fn start(db: Surreal<Db>, opts: StartOptions) {
 
     let mut app = App::new();
 
-    app.insert_resource(UserActor(actor))
+    app.insert_resource(SimulatedConditions(opts.network_conditions))
+        .insert_resource(UserActor(actor))
         .add_plugins((
             DefaultPlugins
                 .set(AssetPlugin {
@@ -65,6 +77,7 @@ pub async fn start(db: Surreal<Db>, opts: StartOptions) {
             PhysicsPlugins::default(),
             unavi_dwn::DwnPlugin,
             unavi_networking::NetworkingPlugin,
+            unavi_networking::voice::VoicePlugin,
             unavi_player::PlayerPlugin,
             unavi_scripting::ScriptingPlugin,
             unavi_settings::SettingsPlugin,
@@ -72,6 +85,18 @@ pub async fn start(db: Surreal<Db>, opts: StartOptions) {
         ))
         .add_systems(Startup, unavi_system::spawn_unavi_system);
 
+    if opts.microphone {
+        app.world_mut()
+            .resource_mut::<unavi_networking::voice::VoiceSettings>()
+            .microphone = true;
+    }
+
+    if let Some(interval) = opts.log_network_stats {
+        app.world_mut()
+            .resource_mut::<unavi_networking::stats::NetworkStatsSettings>()
+            .log_interval = Some(interval);
+    }
+
     if opts.debug_physics {
         app.add_plugins(PhysicsDebugPlugin::default());
     }
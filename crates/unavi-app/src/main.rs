@@ -1,9 +1,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::time::Duration;
+
 use clap::{Parser, ValueEnum};
 use surrealdb::Surreal;
 use tracing::Level;
 use unavi_app::StartOptions;
+use xwt_netsim::Conditions;
 
 #[cfg(target_family = "wasm")]
 #[wasm_bindgen::prelude::wasm_bindgen(start)]
@@ -17,6 +20,13 @@ pub async fn start() {
     let mut args = Args {
         debug_physics: false,
         log_level: LogLevel::default(),
+        log_network_stats: None,
+        microphone: false,
+        sim_latency: 0,
+        sim_jitter: 0,
+        sim_loss: 0.0,
+        sim_reorder: 0.0,
+        sim_bandwidth: None,
     };
 
     if let Some(value) = params.get("debug-physics") {
@@ -34,6 +44,12 @@ pub async fn start() {
         }
     }
 
+    if let Some(value) = params.get("log-network-stats") {
+        if let Ok(value) = value.parse() {
+            args.log_network_stats = Some(value);
+        }
+    }
+
     let db = Surreal::new::<surrealdb::engine::local::IndxDb>("unavi")
         .await
         .expect("Failed to create SurrealDB.");
@@ -55,6 +71,27 @@ struct Args {
     /// Sets the log level.
     #[arg(long, default_value_t, value_enum)]
     log_level: LogLevel,
+    /// Logs network stats at this interval, in seconds.
+    #[arg(long, value_name = "SECONDS")]
+    log_network_stats: Option<f32>,
+    /// Opens the microphone for voice chat, sending while the push-to-talk key (V) is held.
+    #[arg(long)]
+    microphone: bool,
+    /// Simulated one-way latency, in milliseconds.
+    #[arg(long, default_value_t, value_name = "MS")]
+    sim_latency: u64,
+    /// Simulated random variation in latency, in milliseconds.
+    #[arg(long, default_value_t, value_name = "MS")]
+    sim_jitter: u64,
+    /// Simulated datagram loss, as a percentage.
+    #[arg(long, default_value_t, value_name = "PERCENT")]
+    sim_loss: f32,
+    /// Simulated datagram reordering, as a percentage.
+    #[arg(long, default_value_t, value_name = "PERCENT")]
+    sim_reorder: f32,
+    /// Simulated bandwidth cap, in kilobytes per second.
+    #[arg(long, value_name = "KB/S")]
+    sim_bandwidth: Option<u32>,
 }
 
 #[derive(ValueEnum, Clone, Debug, Default)]
@@ -96,5 +133,14 @@ fn args_to_options(args: Args) -> StartOptions {
     StartOptions {
         debug_physics: args.debug_physics,
         log_level,
+        log_network_stats: args.log_network_stats,
+        microphone: args.microphone,
+        network_conditions: Conditions {
+            latency: Duration::from_millis(args.sim_latency),
+            jitter: Duration::from_millis(args.sim_jitter),
+            loss: args.sim_loss / 100.0,
+            reorder: args.sim_reorder / 100.0,
+            bandwidth: args.sim_bandwidth.map(|kb| kb.saturating_mul(1000)),
+        },
     }
 }
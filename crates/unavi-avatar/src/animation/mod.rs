@@ -9,6 +9,8 @@ use self::load::AvatarAnimationNodes;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub enum AnimationName {
+    /// An emote, identified by a non-zero id.
+    Emote(u8),
     Falling,
     #[default]
     Idle,
@@ -18,6 +20,41 @@ pub enum AnimationName {
     WalkRight,
 }
 
+impl AnimationName {
+    /// Whether the animation is driven by the avatar's velocity.
+    pub fn is_locomotion(&self) -> bool {
+        matches!(
+            self,
+            AnimationName::Idle
+                | AnimationName::Walk
+                | AnimationName::WalkLeft
+                | AnimationName::WalkRight
+        )
+    }
+}
+
+/// Discrete animation state of an avatar, blended over its locomotion.
+/// Kept separate from the animation player so it can be published over the network.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AvatarAnimationState {
+    pub menu_open: bool,
+    pub falling: bool,
+    /// Id of the playing [AnimationName::Emote], 0 if not emoting.
+    pub emote: u8,
+}
+
+impl AvatarAnimationState {
+    /// Whether the state requests an animation to be played.
+    pub fn is_playing(&self, name: AnimationName) -> bool {
+        match name {
+            AnimationName::Emote(id) => id != 0 && id == self.emote,
+            AnimationName::Falling => self.falling,
+            AnimationName::Menu => self.menu_open,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Component, Clone, Default, Deref, DerefMut)]
 pub struct AnimationWeights(pub HashMap<AnimationName, f32>);
 
@@ -61,7 +98,12 @@ const WEIGHT_THRESHOLD: f32 = 0.02;
 
 pub(crate) fn play_avatar_animations(
     time: Res<Time>,
-    mut avatars: Query<(&AvatarAnimationNodes, &AverageVelocity, &Transform)>,
+    mut avatars: Query<(
+        &AvatarAnimationNodes,
+        &AvatarAnimationState,
+        &AverageVelocity,
+        &Transform,
+    )>,
     mut animation_players: Query<(
         &mut AnimationWeights,
         &TargetAnimationWeights,
@@ -72,7 +114,7 @@ pub(crate) fn play_avatar_animations(
     let alpha = (time.delta_seconds() * ALPHA_FACTOR).min(0.9);
 
     for (mut weights, targets, mut player, parent) in animation_players.iter_mut() {
-        if let Ok((nodes, avg, transform)) = avatars.get_mut(**parent) {
+        if let Ok((nodes, state, avg, transform)) = avatars.get_mut(**parent) {
             for (name, node) in nodes.0.iter() {
                 if player.animation(*node).is_none() {
                     let animation = player.play(*node).repeat();
@@ -81,6 +123,32 @@ pub(crate) fn play_avatar_animations(
                 }
             }
 
+            // Overlays, such as the menu or falling.
+            // Avatars without a clip for a requested animation ignore it.
+            let overlays = nodes
+                .0
+                .keys()
+                .filter(|name| !name.is_locomotion())
+                .copied()
+                .collect::<Vec<_>>();
+
+            let mut overlay_weight = 0.0;
+
+            for name in overlays {
+                let mut weight = if state.is_playing(name) {
+                    1.0
+                } else {
+                    *targets.get(&name).unwrap_or(&0.0)
+                };
+
+                apply_weight(name, &mut weight, alpha, &mut player, nodes, &mut weights);
+
+                overlay_weight += weight;
+            }
+
+            // Overlays take precedence over locomotion.
+            let locomotion_scale = (1.0 - overlay_weight).max(0.0);
+
             let dir_forward = transform.rotation.mul_vec3(Vec3 {
                 x: 0.0,
                 y: 0.0,
@@ -95,8 +163,8 @@ pub(crate) fn play_avatar_animations(
             let vel_forward = avg.velocity * dir_forward;
             let vel_left = avg.velocity * dir_left;
 
-            let forward = vel_forward.element_sum() * VELOCITY_FACTOR;
-            let left = vel_left.element_sum() * VELOCITY_FACTOR;
+            let forward = vel_forward.element_sum() * VELOCITY_FACTOR * locomotion_scale;
+            let left = vel_left.element_sum() * VELOCITY_FACTOR * locomotion_scale;
 
             // Left walk.
             let mut l_walk_weight = left.max(0.0);
@@ -144,25 +212,12 @@ pub(crate) fn play_avatar_animations(
                 walk.set_speed(-1.0);
             }
 
-            // Menu.
-            let menu_weight = weights[&AnimationName::Menu];
-            let mut target_menu_weight = *targets.get(&AnimationName::Menu).unwrap_or(&0.0);
-
-            apply_weight(
-                AnimationName::Menu,
-                &mut target_menu_weight,
-                alpha,
-                &mut player,
-                nodes,
-                &mut weights,
-            );
-
             // Idle.
             let mut idle_weight = 1.0;
             idle_weight -= l_walk_weight;
             idle_weight -= r_walk_weight;
             idle_weight -= walk_weight;
-            idle_weight -= menu_weight;
+            idle_weight -= overlay_weight;
 
             apply_weight(
                 AnimationName::Idle,
@@ -1,15 +1,16 @@
-use animation::AvatarAnimationClips;
-use bevy::prelude::*;
+use animation::{AvatarAnimationClips, AvatarAnimationState};
+use bevy::{animation::animate_targets, prelude::*, transform::TransformSystem};
 use bevy_vrm::VrmPlugins;
 
 pub mod animation;
 mod defaults;
 mod fallback;
+pub mod pose;
 mod velocity;
 
 pub use defaults::*;
 pub use fallback::FallbackAvatar;
-pub use velocity::AverageVelocity;
+pub use velocity::{AverageVelocity, ExternalVelocity};
 
 pub struct AvatarPlugin;
 
@@ -26,8 +27,15 @@ impl Plugin for AvatarPlugin {
                     fallback::despawn_fallback_children,
                     fallback::remove_fallback_avatar,
                     fallback::spawn_fallback_children,
+                    pose::find_avatar_bones,
                     velocity::calc_average_velocity,
                 ),
+            )
+            .add_systems(
+                PostUpdate,
+                pose::apply_avatar_poses
+                    .after(animate_targets)
+                    .before(TransformSystem::TransformPropagate),
             );
     }
 }
@@ -36,6 +44,7 @@ impl Plugin for AvatarPlugin {
 pub struct AvatarBundle {
     pub animations: AvatarAnimationClips,
     pub fallback: FallbackAvatar,
+    pub state: AvatarAnimationState,
     pub velocity: AverageVelocity,
 }
 
@@ -44,6 +53,7 @@ impl AvatarBundle {
         Self {
             animations,
             fallback: FallbackAvatar,
+            state: AvatarAnimationState::default(),
             velocity: AverageVelocity::default(),
         }
     }
@@ -0,0 +1,204 @@
+//! Tracked poses, applied to an avatar's skeleton on top of its animations.
+
+use std::sync::LazyLock;
+
+use bevy::{
+    animation::{AnimationTarget, AnimationTargetId},
+    prelude::*,
+    utils::HashMap,
+};
+use bevy_vrm::{animations::vrm::VRM_ANIMATION_TARGETS, BoneName};
+
+/// Humanoid bones that can be posed, in a stable order for serialization.
+pub const POSE_BONES: [BoneName; 52] = [
+    BoneName::Hips,
+    BoneName::Spine,
+    BoneName::Chest,
+    BoneName::UpperChest,
+    BoneName::Neck,
+    BoneName::Head,
+    BoneName::LeftUpperLeg,
+    BoneName::LeftLowerLeg,
+    BoneName::LeftFoot,
+    BoneName::LeftToes,
+    BoneName::RightUpperLeg,
+    BoneName::RightLowerLeg,
+    BoneName::RightFoot,
+    BoneName::RightToes,
+    BoneName::LeftShoulder,
+    BoneName::LeftUpperArm,
+    BoneName::LeftLowerArm,
+    BoneName::LeftHand,
+    BoneName::LeftThumbProximal,
+    BoneName::LeftThumbIntermediate,
+    BoneName::LeftThumbDistal,
+    BoneName::LeftIndexProximal,
+    BoneName::LeftIndexIntermediate,
+    BoneName::LeftIndexDistal,
+    BoneName::LeftMiddleProximal,
+    BoneName::LeftMiddleIntermediate,
+    BoneName::LeftMiddleDistal,
+    BoneName::LeftRingProximal,
+    BoneName::LeftRingIntermediate,
+    BoneName::LeftRingDistal,
+    BoneName::LeftLittleProximal,
+    BoneName::LeftLittleIntermediate,
+    BoneName::LeftLittleDistal,
+    BoneName::RightShoulder,
+    BoneName::RightUpperArm,
+    BoneName::RightLowerArm,
+    BoneName::RightHand,
+    BoneName::RightThumbProximal,
+    BoneName::RightThumbIntermediate,
+    BoneName::RightThumbDistal,
+    BoneName::RightIndexProximal,
+    BoneName::RightIndexIntermediate,
+    BoneName::RightIndexDistal,
+    BoneName::RightMiddleProximal,
+    BoneName::RightMiddleIntermediate,
+    BoneName::RightMiddleDistal,
+    BoneName::RightRingProximal,
+    BoneName::RightRingIntermediate,
+    BoneName::RightRingDistal,
+    BoneName::RightLittleProximal,
+    BoneName::RightLittleIntermediate,
+    BoneName::RightLittleDistal,
+];
+
+/// Index of a bone in [POSE_BONES].
+pub fn pose_bone_index(bone: BoneName) -> Option<u8> {
+    POSE_BONES.iter().position(|b| *b == bone).map(|i| i as u8)
+}
+
+static TARGET_BONES: LazyLock<HashMap<AnimationTargetId, BoneName>> = LazyLock::new(|| {
+    VRM_ANIMATION_TARGETS
+        .iter()
+        .map(|(bone, target)| (*target, *bone))
+        .collect()
+});
+
+/// Pose of an avatar, overriding its animations.
+/// Anything left unset is animated as usual.
+///
+/// Head and hand rotations are relative to the avatar's root,
+/// and are applied on top of the animated pose.
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct AvatarPose {
+    /// Relative to the avatar's root.
+    pub head: Option<Transform>,
+    /// Relative to the avatar's root.
+    /// Only the rotation is applied, as positioning hands requires IK.
+    pub left_hand: Option<Transform>,
+    pub right_hand: Option<Transform>,
+    /// Local rotations of humanoid bones.
+    pub bones: HashMap<BoneName, Quat>,
+}
+
+/// Maps bones -> entities within an avatar's skeleton.
+#[derive(Component, Default, Deref, DerefMut)]
+pub(crate) struct AvatarBones(HashMap<BoneName, Entity>);
+
+pub(crate) fn find_avatar_bones(
+    mut commands: Commands,
+    avatars: Query<Entity, (With<AvatarPose>, Without<AvatarBones>)>,
+    parents: Query<&Parent>,
+    targets: Query<(Entity, &AnimationTarget)>,
+) {
+    if avatars.is_empty() {
+        return;
+    }
+
+    let mut found = HashMap::<Entity, AvatarBones>::default();
+
+    for (entity, target) in targets.iter() {
+        let Some(bone) = TARGET_BONES.get(&target.id) else {
+            continue;
+        };
+
+        // Animation players are spawned as children of the avatar.
+        let Ok(avatar) = parents.get(target.player).map(|p| p.get()) else {
+            continue;
+        };
+
+        if avatars.contains(avatar) {
+            found.entry(avatar).or_default().insert(*bone, entity);
+        }
+    }
+
+    for (avatar, bones) in found {
+        commands.entity(avatar).insert(bones);
+    }
+}
+
+/// Applies poses after animations, so they take precedence.
+pub(crate) fn apply_avatar_poses(
+    mut commands: Commands,
+    avatars: Query<(Entity, &AvatarPose, &AvatarBones, &GlobalTransform)>,
+    globals: Query<&GlobalTransform>,
+    parents: Query<&Parent>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (entity, pose, bones, avatar_global) in avatars.iter() {
+        // Rebuild the map if the skeleton changed, such as when a new avatar loads.
+        if bones.values().any(|bone| !transforms.contains(*bone)) {
+            commands.entity(entity).remove::<AvatarBones>();
+            continue;
+        }
+
+        for (name, rotation) in pose.bones.iter() {
+            if let Some(mut transform) = bones.get(name).and_then(|b| transforms.get_mut(*b).ok()) {
+                transform.rotation = *rotation;
+            }
+        }
+
+        let avatar_rotation = avatar_global.to_scale_rotation_translation().1;
+
+        let targets = [
+            (BoneName::Head, pose.head),
+            (BoneName::LeftHand, pose.left_hand),
+            (BoneName::RightHand, pose.right_hand),
+        ];
+
+        for (name, target) in targets {
+            let Some(target) = target else {
+                continue;
+            };
+
+            let Some(bone) = bones.get(&name) else {
+                continue;
+            };
+
+            // Rotate the bone in world space, so the result is independent of its rest pose.
+            let parent_rotation = parents
+                .get(*bone)
+                .and_then(|p| globals.get(p.get()))
+                .map(|g| g.to_scale_rotation_translation().1)
+                .unwrap_or_default();
+
+            let world_delta = avatar_rotation * target.rotation * avatar_rotation.inverse();
+            let local_delta = parent_rotation.inverse() * world_delta * parent_rotation;
+
+            if let Ok(mut transform) = transforms.get_mut(*bone) {
+                transform.rotation = (local_delta * transform.rotation).normalize();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_pose_bones() {
+        let unique = POSE_BONES.iter().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), POSE_BONES.len());
+
+        for (i, bone) in POSE_BONES.iter().enumerate() {
+            assert_eq!(pose_bone_index(*bone), Some(i as u8));
+            assert!(VRM_ANIMATION_TARGETS.contains_key(bone));
+        }
+    }
+}
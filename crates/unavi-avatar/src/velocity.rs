@@ -25,14 +25,26 @@ impl Default for AverageVelocity {
     }
 }
 
+/// Velocity reported from elsewhere, such as over the network.
+/// If present, [AverageVelocity] averages it instead of changes in [Transform].
+#[derive(Component, Clone, Copy, Debug, Default, Deref, DerefMut)]
+pub struct ExternalVelocity(pub Vec3);
+
 pub fn calc_average_velocity(
-    mut velocities: Query<(Entity, &mut AverageVelocity)>,
+    mut velocities: Query<(Entity, &mut AverageVelocity, Option<&ExternalVelocity>)>,
     time: Res<Time>,
     transforms: Query<&Transform>,
 ) {
     let delta_t = time.delta_seconds();
 
-    for (entity, mut avg) in velocities.iter_mut() {
+    for (entity, mut avg, external) in velocities.iter_mut() {
+        if let Some(velocity) = external {
+            avg.velocity = avg.alpha * velocity.0 + (1.0 - avg.alpha) * avg.velocity;
+            // Re-initialize from the transform if the external velocity is removed.
+            avg.initialized = false;
+            continue;
+        }
+
         let target = avg.target.unwrap_or(entity);
 
         let Ok(transform) = transforms.get(target) else {
@@ -0,0 +1,111 @@
+//! Animation states of remote players, applied to their avatars.
+//!
+//! Players that do not publish animation states are animated from their transform alone.
+
+use bevy::prelude::*;
+use capnp::message::HeapAllocator;
+use unavi_avatar::{animation::AvatarAnimationState, ExternalVelocity};
+use wired_world::{
+    datagram_capnp::{animation_state, client_datagram},
+    quantize::{dequantize_velocity, quantize_velocity},
+};
+
+/// Time without receiving an animation state before a remote avatar returns to being
+/// animated from its transform alone, in seconds.
+const ANIMATION_TIMEOUT: f32 = 1.0;
+
+/// Time a remote avatar's animation state was last received, in seconds.
+#[derive(Component)]
+pub(crate) struct AnimationReceived(pub f32);
+
+pub(crate) fn expire_remote_animations(
+    mut avatars: Query<(Entity, &AnimationReceived, &mut AvatarAnimationState)>,
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed_seconds();
+
+    for (entity, received, mut state) in avatars.iter_mut() {
+        if now - received.0 > ANIMATION_TIMEOUT {
+            state.set_if_neq(AvatarAnimationState::default());
+            commands
+                .entity(entity)
+                .remove::<(AnimationReceived, ExternalVelocity)>();
+        }
+    }
+}
+
+pub(crate) fn write_animation_datagram(
+    state: &AvatarAnimationState,
+    velocity: Vec3,
+) -> capnp::message::Builder<HeapAllocator> {
+    let mut msg = capnp::message::Builder::new_default();
+    let mut root = msg
+        .init_root::<client_datagram::Builder>()
+        .init_publish_animation();
+
+    root.set_menu_open(state.menu_open);
+    root.set_falling(state.falling);
+    root.set_emote(state.emote);
+
+    let [x, y, z] = quantize_velocity(velocity.to_array());
+    root.set_velocity_x(x);
+    root.set_velocity_y(y);
+    root.set_velocity_z(z);
+
+    msg
+}
+
+pub(crate) fn read_animation_state(
+    reader: animation_state::Reader,
+) -> (AvatarAnimationState, Vec3) {
+    let state = AvatarAnimationState {
+        menu_open: reader.get_menu_open(),
+        falling: reader.get_falling(),
+        emote: reader.get_emote(),
+    };
+
+    let velocity = Vec3::from_array(dequantize_velocity([
+        reader.get_velocity_x(),
+        reader.get_velocity_y(),
+        reader.get_velocity_z(),
+    ]));
+
+    (state, velocity)
+}
+
+#[cfg(test)]
+mod tests {
+    use capnp::message::ReaderOptions;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let state = AvatarAnimationState {
+            menu_open: false,
+            falling: true,
+            emote: 3,
+        };
+        let velocity = Vec3::new(1.5, -9.0, 0.25);
+
+        let mut bytes = Vec::new();
+        capnp::serialize_packed::write_message(
+            &mut bytes,
+            &write_animation_datagram(&state, velocity),
+        )
+        .unwrap();
+
+        let msg = capnp::serialize_packed::read_message(bytes.as_slice(), ReaderOptions::default())
+            .unwrap();
+        let root = msg.get_root::<client_datagram::Reader>().unwrap();
+
+        let Ok(client_datagram::PublishAnimation(reader)) = root.which() else {
+            panic!("Not an animation state");
+        };
+
+        let (received, received_velocity) = read_animation_state(reader.unwrap());
+        assert_eq!(received, state);
+        assert!(received_velocity.abs_diff_eq(velocity, 1e-2));
+    }
+}
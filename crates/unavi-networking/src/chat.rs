@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+
+use crate::{thread::SessionRequest, Session};
+
+/// Sends a chat message to the players in an instance.
+#[derive(Event, Clone, Debug)]
+pub struct SendChatMessage {
+    /// Instance entity to send to.
+    pub instance: Entity,
+    pub text: String,
+}
+
+/// A chat message in an instance, for the app to display.
+/// Our own messages are included once the server accepts them.
+#[derive(Event, Clone, Debug)]
+pub struct ChatMessage {
+    pub instance: Entity,
+    /// The [RemotePlayer](crate::RemotePlayer) that sent the message, or `None` if we sent it.
+    pub player: Option<Entity>,
+    /// Verified DID of the sender.
+    pub did: Option<String>,
+    pub text: String,
+}
+
+/// A chat message we sent was rejected, such as for being too long or sent too quickly.
+#[derive(Event, Clone, Debug)]
+pub struct ChatMessageFailed {
+    pub instance: Entity,
+    pub text: String,
+    pub reason: String,
+}
+
+pub(crate) fn send_chat_messages(
+    mut events: EventReader<SendChatMessage>,
+    mut failed: EventWriter<ChatMessageFailed>,
+    sessions: Query<&Session>,
+) {
+    for event in events.read() {
+        let sent = sessions.get(event.instance).is_ok_and(|session| {
+            session
+                .sender
+                .send(SessionRequest::SendMessage(event.text.clone()))
+                .is_ok()
+        });
+
+        if !sent {
+            failed.send(ChatMessageFailed {
+                instance: event.instance,
+                text: event.text.clone(),
+                reason: "Not connected".to_string(),
+            });
+        }
+    }
+}
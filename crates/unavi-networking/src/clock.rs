@@ -0,0 +1,228 @@
+//! NTP-style estimation of each server's clock, from `WorldServer.time` requests.
+//!
+//! Local times are in seconds since startup, as in [Time<Real>].
+//! Server times are in seconds since the server started.
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{thread::SessionRequest, ConnectionState, Session};
+
+/// Number of recent samples to estimate the offset from.
+const MAX_SAMPLES: usize = 8;
+/// Samples to take in quick succession after connecting, for a fast initial estimate.
+const INITIAL_SAMPLES: usize = 4;
+const INITIAL_SYNC_INTERVAL: f64 = 0.25;
+/// Time between clock samples once synced, in seconds.
+const SYNC_INTERVAL: f64 = 2.0;
+/// Weight of each new sample in the smoothed round trip time.
+const RTT_ALPHA: f64 = 0.125;
+
+/// A single request for the server's time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockSample {
+    /// Local time the request was sent.
+    pub sent: f64,
+    /// Local time the response was received.
+    pub received: f64,
+    /// Server time the request was handled.
+    pub server: f64,
+}
+
+impl ClockSample {
+    pub fn rtt(&self) -> f64 {
+        (self.received - self.sent).max(0.0)
+    }
+
+    /// Server time minus local time, assuming the request and response took equally long.
+    pub fn offset(&self) -> f64 {
+        self.server + self.rtt() / 2.0 - self.received
+    }
+}
+
+/// Estimated clock of a server.
+#[derive(Clone, Debug, Default)]
+pub struct ServerClock {
+    /// Local time of the last request.
+    last_request: Option<f64>,
+    offset: f64,
+    rtt: f64,
+    samples: VecDeque<ClockSample>,
+}
+
+impl ServerClock {
+    pub fn add_sample(&mut self, sample: ClockSample) {
+        if self.samples.is_empty() {
+            self.rtt = sample.rtt();
+        } else {
+            self.rtt += (sample.rtt() - self.rtt) * RTT_ALPHA;
+        }
+
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+
+        // The fastest round trip was the least delayed by queuing, so is the most accurate.
+        if let Some(best) = self
+            .samples
+            .iter()
+            .min_by(|a, b| a.rtt().total_cmp(&b.rtt()))
+        {
+            self.offset = best.offset();
+        }
+    }
+
+    /// Whether any samples have been received.
+    pub fn is_synced(&self) -> bool {
+        !self.samples.is_empty()
+    }
+
+    /// Server time minus local time, in seconds.
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// Smoothed round trip time, in seconds.
+    pub fn rtt(&self) -> f64 {
+        self.rtt
+    }
+
+    /// Converts a local time to server time.
+    pub fn server_time(&self, local: f64) -> f64 {
+        local + self.offset
+    }
+
+    /// Converts a server time to local time.
+    pub fn local_time(&self, server: f64) -> f64 {
+        server - self.offset
+    }
+
+    /// Estimated server time now.
+    pub fn now(&self, time: &Time<Real>) -> f64 {
+        self.server_time(time.elapsed_seconds_f64())
+    }
+
+    /// Local time that a datagram sent at a server time is expected to arrive.
+    pub fn arrival_time(&self, server: f64) -> f64 {
+        self.local_time(server) + self.rtt / 2.0
+    }
+
+    fn sync_interval(&self) -> f64 {
+        if self.samples.len() < INITIAL_SAMPLES {
+            INITIAL_SYNC_INTERVAL
+        } else {
+            SYNC_INTERVAL
+        }
+    }
+}
+
+/// Estimated server clocks, keyed by session entity.
+#[derive(Resource, Default)]
+pub struct ServerTime {
+    clocks: HashMap<Entity, ServerClock>,
+}
+
+impl ServerTime {
+    /// Clock of a session's server, once it has been sampled.
+    pub fn get(&self, session: Entity) -> Option<&ServerClock> {
+        self.clocks.get(&session).filter(|clock| clock.is_synced())
+    }
+
+    /// Synced clocks of all sessions.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &ServerClock)> {
+        self.clocks
+            .iter()
+            .filter(|(_, clock)| clock.is_synced())
+            .map(|(entity, clock)| (*entity, clock))
+    }
+
+    pub(crate) fn add_sample(&mut self, session: Entity, sample: ClockSample) {
+        self.clocks.entry(session).or_default().add_sample(sample);
+    }
+}
+
+pub(crate) fn sync_server_clocks(
+    mut server_time: ResMut<ServerTime>,
+    sessions: Query<(Entity, &Session, &ConnectionState)>,
+    time: Res<Time<Real>>,
+) {
+    // Sessions are recreated when reconnecting, possibly to a restarted server.
+    server_time
+        .clocks
+        .retain(|entity, _| sessions.contains(*entity));
+
+    let now = time.elapsed_seconds_f64();
+
+    for (entity, session, state) in sessions.iter() {
+        if *state != ConnectionState::Connected {
+            continue;
+        }
+
+        let clock = server_time.clocks.entry(entity).or_default();
+
+        if let Some(last) = clock.last_request {
+            if now - last < clock.sync_interval() {
+                continue;
+            }
+        }
+
+        clock.last_request = Some(now);
+
+        if let Err(e) = session.sender.send(SessionRequest::SyncClock) {
+            error!("Failed to send: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(sent: f64, up: f64, down: f64, offset: f64) -> ClockSample {
+        ClockSample {
+            sent,
+            received: sent + up + down,
+            server: sent + up + offset,
+        }
+    }
+
+    #[test]
+    fn test_symmetric() {
+        let mut clock = ServerClock::default();
+        assert!(!clock.is_synced());
+
+        clock.add_sample(sample(10.0, 0.05, 0.05, 100.0));
+
+        assert!(clock.is_synced());
+        assert!((clock.offset() - 100.0).abs() < 1e-9);
+        assert!((clock.rtt() - 0.1).abs() < 1e-9);
+        assert!((clock.server_time(12.0) - 112.0).abs() < 1e-9);
+        assert!((clock.local_time(112.0) - 12.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fastest_sample() {
+        let mut clock = ServerClock::default();
+
+        // Delayed by queuing on the way back, which skews the offset.
+        clock.add_sample(sample(0.0, 0.05, 0.45, 100.0));
+        assert!((clock.offset() - 99.8).abs() < 1e-9);
+
+        clock.add_sample(sample(1.0, 0.05, 0.05, 100.0));
+        assert!((clock.offset() - 100.0).abs() < 1e-9);
+
+        // Smoothed, rather than jumping to the latest round trip.
+        assert!(clock.rtt() > 0.1 && clock.rtt() < 0.5);
+    }
+
+    #[test]
+    fn test_arrival_time() {
+        let mut clock = ServerClock::default();
+        clock.add_sample(sample(0.0, 0.1, 0.1, 50.0));
+
+        // Sent at local time 5, arriving one way trip later.
+        assert!((clock.arrival_time(55.0) - 5.1).abs() < 1e-9);
+    }
+}
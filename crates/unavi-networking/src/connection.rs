@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use xwt_netsim::Conditions;
+
+use crate::Session;
+
+/// Reconnect attempts before giving up on an instance.
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Connection status of an instance.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// Waiting to reconnect after the connection was lost.
+    /// Players are kept until we reconnect, as the server keeps our session for a grace period.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// The connection was closed, and will not be retried.
+    Disconnected,
+}
+
+/// Network conditions to simulate on new sessions, for testing under bad networks.
+#[derive(Resource, Clone, Debug, Default, Deref, DerefMut)]
+pub struct SimulatedConditions(pub Conditions);
+
+/// Token for resuming the session, received when joining.
+#[derive(Component, Deref)]
+pub(crate) struct ResumeToken(pub Vec<u8>);
+
+#[derive(Component)]
+pub(crate) struct ReconnectTimer {
+    pub attempt: u32,
+    timer: Timer,
+}
+
+impl ReconnectTimer {
+    pub fn new(attempt: u32) -> Self {
+        Self {
+            attempt,
+            timer: Timer::new(backoff(attempt), TimerMode::Once),
+        }
+    }
+}
+
+/// Delay before a reconnect attempt, doubling with each attempt.
+fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF)
+}
+
+/// Removes finished timers, so the connection is opened again.
+pub(crate) fn tick_reconnect_timers(
+    mut commands: Commands,
+    mut timers: Query<(Entity, &mut ReconnectTimer), Without<Session>>,
+    time: Res<Time>,
+) {
+    for (entity, mut timer) in timers.iter_mut() {
+        if timer.timer.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<ReconnectTimer>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), INITIAL_BACKOFF);
+        assert_eq!(backoff(1), INITIAL_BACKOFF * 2);
+        assert_eq!(backoff(3), INITIAL_BACKOFF * 8);
+        assert_eq!(backoff(MAX_RECONNECT_ATTEMPTS), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+}
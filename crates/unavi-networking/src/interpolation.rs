@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+#[derive(Resource, Clone, Debug)]
+pub struct InterpolationSettings {
+    /// How far in the past remote players are rendered, in seconds.
+    /// Larger values hide more jitter and packet loss, at the cost of latency.
+    pub delay: f32,
+    /// Maximum time, in seconds, to extrapolate past the latest snapshot.
+    pub max_extrapolation: f32,
+    /// Maximum number of snapshots to buffer per player.
+    pub max_snapshots: usize,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        Self {
+            // Two server ticks.
+            delay: 0.1,
+            max_extrapolation: 0.25,
+            max_snapshots: 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Time the snapshot was received, in seconds.
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+/// Jitter buffer of received transforms for a remote player.
+#[derive(Component, Clone, Debug, Default)]
+pub struct SnapshotBuffer {
+    /// Snapshots, sorted by time.
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Adds a snapshot to the buffer, keeping it sorted by time.
+    /// Drops the oldest snapshots once `max_snapshots` is exceeded.
+    pub fn push(&mut self, snapshot: Snapshot, max_snapshots: usize) {
+        let idx = self
+            .snapshots
+            .iter()
+            .rposition(|s| s.time <= snapshot.time)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+
+        self.snapshots.insert(idx, snapshot);
+
+        while self.snapshots.len() > max_snapshots.max(2) {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Removes snapshots that are no longer needed to sample at `render_time`.
+    /// Keeps the latest snapshot before `render_time`, to interpolate from.
+    pub fn prune(&mut self, render_time: f32) {
+        while self.snapshots.len() > 2 && self.snapshots[1].time <= render_time {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Samples the buffer at `render_time`.
+    ///
+    /// Interpolates between the surrounding snapshots, or extrapolates from the
+    /// latest two snapshots (up to `max_extrapolation` seconds) if none are newer.
+    pub fn sample(&self, render_time: f32, max_extrapolation: f32) -> Option<(Vec3, Quat)> {
+        let first = self.snapshots.front()?;
+
+        if render_time <= first.time {
+            return Some((first.translation, first.rotation));
+        }
+
+        let next_idx = self.snapshots.iter().position(|s| s.time > render_time);
+
+        match next_idx {
+            Some(idx) => {
+                let a = &self.snapshots[idx - 1];
+                let b = &self.snapshots[idx];
+                let t = (render_time - a.time) / (b.time - a.time);
+
+                Some((
+                    a.translation.lerp(b.translation, t),
+                    a.rotation.slerp(b.rotation, t),
+                ))
+            }
+            None => {
+                let b = self.snapshots.back()?;
+
+                if self.snapshots.len() < 2 {
+                    return Some((b.translation, b.rotation));
+                }
+
+                let a = &self.snapshots[self.snapshots.len() - 2];
+                let dt = b.time - a.time;
+
+                if dt <= f32::EPSILON {
+                    return Some((b.translation, b.rotation));
+                }
+
+                let extra = (render_time - b.time).min(max_extrapolation);
+                let t = extra / dt;
+
+                let velocity = b.translation - a.translation;
+                let translation = b.translation + velocity * t;
+
+                let mut delta = b.rotation * a.rotation.inverse();
+                if delta.w < 0.0 {
+                    delta = -delta;
+                }
+                let (axis, angle) = delta.to_axis_angle();
+                let rotation = (Quat::from_axis_angle(axis, angle * t) * b.rotation).normalize();
+
+                Some((translation, rotation))
+            }
+        }
+    }
+}
+
+pub(crate) fn interpolate_remote_players(
+    mut players: Query<(&mut SnapshotBuffer, &mut Transform, &mut Visibility)>,
+    settings: Res<InterpolationSettings>,
+    time: Res<Time<Real>>,
+) {
+    let render_time = time.elapsed_seconds() - settings.delay;
+
+    for (mut buffer, mut transform, mut visibility) in players.iter_mut() {
+        buffer.prune(render_time);
+
+        if let Some((translation, rotation)) =
+            buffer.sample(render_time, settings.max_extrapolation)
+        {
+            transform.translation = translation;
+            transform.rotation = rotation;
+            visibility.set_if_neq(Visibility::Inherited);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    const TICK: f32 = 0.05;
+
+    fn snapshot(time: f32, x: f32, yaw: f32) -> Snapshot {
+        Snapshot {
+            time,
+            translation: Vec3::new(x, 0.0, 0.0),
+            rotation: Quat::from_rotation_y(yaw),
+        }
+    }
+
+    fn stream(count: usize) -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::default();
+        for i in 0..count {
+            let t = i as f32 * TICK;
+            buffer.push(snapshot(t, i as f32, 0.0), 32);
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let buffer = stream(4);
+
+        let (translation, _) = buffer.sample(TICK * 1.5, 0.0).unwrap();
+        assert!((translation.x - 1.5).abs() < 1e-4);
+
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(snapshot(0.0, 0.0, 0.0), 32);
+        buffer.push(snapshot(TICK, 0.0, FRAC_PI_2), 32);
+
+        let (_, rotation) = buffer.sample(TICK / 2.0, 0.0).unwrap();
+        assert!(rotation.angle_between(Quat::from_rotation_y(FRAC_PI_2 / 2.0)) < 1e-4);
+    }
+
+    #[test]
+    fn test_before_first() {
+        let buffer = stream(3);
+        let (translation, _) = buffer.sample(-1.0, 0.0).unwrap();
+        assert_eq!(translation.x, 0.0);
+    }
+
+    #[test]
+    fn test_extrapolate() {
+        let buffer = stream(3);
+
+        // One tick past the latest snapshot.
+        let (translation, _) = buffer.sample(TICK * 3.0, 1.0).unwrap();
+        assert!((translation.x - 3.0).abs() < 1e-4);
+
+        // Extrapolation is capped.
+        let (translation, _) = buffer.sample(10.0, TICK * 2.0).unwrap();
+        assert!((translation.x - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(snapshot(0.0, 0.0, 0.0), 32);
+        buffer.push(snapshot(TICK * 2.0, 2.0, 0.0), 32);
+        buffer.push(snapshot(TICK, 1.0, 0.0), 32);
+
+        let (translation, _) = buffer.sample(TICK * 0.5, 0.0).unwrap();
+        assert!((translation.x - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_packet_loss() {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(snapshot(0.0, 0.0, 0.0), 32);
+        // Lost snapshots at TICK, TICK * 2.
+        buffer.push(snapshot(TICK * 3.0, 3.0, 0.0), 32);
+
+        let (translation, _) = buffer.sample(TICK * 2.0, 0.0).unwrap();
+        assert!((translation.x - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut buffer = stream(10);
+        buffer.prune(TICK * 5.5);
+        assert_eq!(buffer.len(), 5);
+
+        let (translation, _) = buffer.sample(TICK * 5.5, 0.0).unwrap();
+        assert!((translation.x - 5.5).abs() < 1e-4);
+
+        let mut buffer = stream(10);
+        buffer.push(snapshot(1.0, 0.0, 0.0), 4);
+        assert_eq!(buffer.len(), 4);
+    }
+}
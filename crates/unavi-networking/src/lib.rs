@@ -1,24 +1,103 @@
-use bevy::prelude::*;
+use animation::AnimationReceived;
+use avian3d::prelude::LinearVelocity;
+use bevy::{prelude::*, utils::Instant};
+use chat::{ChatMessage, ChatMessageFailed, SendChatMessage};
+use clock::{ClockSample, ServerTime};
+use connection::{ReconnectTimer, ResumeToken, MAX_RECONNECT_ATTEMPTS};
+use interpolation::{InterpolationSettings, Snapshot, SnapshotBuffer};
+use objects::{
+    DespawnNetworkedObject, NetworkedObjectFailed, NetworkedObjectSpawned, NetworkedObjects,
+    ObjectResponseReceived, RequestObjectOwnership, SpawnNetworkedObject,
+};
+use players::{RemoteAvatar, RemotePlayers};
+use pose::PoseReceived;
+use stats::{NetworkStats, NetworkStatsSettings, SessionTraffic};
 use thread::{NetworkingThread, NewSession, SessionRequest, SessionResponse};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use unavi_player::Player;
-use unavi_world::{InstanceRecord, InstanceServer};
+use unavi_avatar::{animation::AvatarAnimationState, pose::AvatarPose, ExternalVelocity};
+use unavi_dwn::UserActor;
+use unavi_player::{Player, PlayerCamera};
+use unavi_world::{
+    InstanceRecord, InstanceServer, InstanceServerCertificates, RefreshCertificates,
+};
+use voice::VoiceFrameReceived;
 use wired_world::datagram_capnp;
 
+mod animation;
+pub mod chat;
+pub mod clock;
+mod connection;
+pub mod interpolation;
+pub mod objects;
+mod players;
+mod pose;
+pub mod stats;
 mod thread;
+pub mod voice;
+
+pub use connection::{ConnectionState, SimulatedConditions};
+pub use players::{PlayerInfo, RemotePlayer};
 
 pub struct NetworkingPlugin;
 
 impl Plugin for NetworkingPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<thread::NetworkingThread>().add_systems(
-            FixedUpdate,
-            (
-                connect_to_instances,
-                handle_session_response,
-                publish_transform,
-            ),
-        );
+        stats::register_diagnostics(app);
+
+        app.init_resource::<InterpolationSettings>()
+            .init_resource::<NetworkStats>()
+            .init_resource::<NetworkStatsSettings>()
+            .init_resource::<ServerTime>()
+            .init_resource::<SimulatedConditions>()
+            .init_resource::<thread::NetworkingThread>()
+            .add_event::<ChatMessage>()
+            .add_event::<ChatMessageFailed>()
+            .add_event::<DespawnNetworkedObject>()
+            .add_event::<NetworkedObjectFailed>()
+            .add_event::<NetworkedObjectSpawned>()
+            .add_event::<ObjectResponseReceived>()
+            .add_event::<RequestObjectOwnership>()
+            .add_event::<SendChatMessage>()
+            .add_event::<SpawnNetworkedObject>()
+            .add_event::<VoiceFrameReceived>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    animation::expire_remote_animations,
+                    clock::sync_server_clocks.after(handle_session_response),
+                    connection::tick_reconnect_timers.before(connect_to_instances),
+                    connect_to_instances,
+                    handle_session_response,
+                    players::despawn_remote_players.after(handle_session_response),
+                    (
+                        objects::handle_object_responses,
+                        objects::remove_stale_objects,
+                        objects::sync_object_ownership,
+                    )
+                        .chain()
+                        .after(handle_session_response),
+                    objects::publish_object_state,
+                    pose::expire_remote_poses,
+                    publish_transform,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    chat::send_chat_messages,
+                    interpolation::interpolate_remote_players,
+                    objects::despawn_networked_objects,
+                    objects::request_object_ownership,
+                    objects::spawn_networked_objects,
+                    (
+                        stats::update_network_stats,
+                        stats::log_network_stats,
+                        stats::overlay::toggle_stats_overlay,
+                        stats::overlay::update_stats_overlay,
+                    )
+                        .chain(),
+                ),
+            );
     }
 }
 
@@ -29,22 +108,58 @@ struct Session {
 }
 
 fn connect_to_instances(
+    actor: Res<UserActor>,
     mut commands: Commands,
     runtime: Res<NetworkingThread>,
-    to_open: Query<(Entity, &InstanceServer, &InstanceRecord), Without<Session>>,
+    simulated: Res<SimulatedConditions>,
+    to_open: Query<
+        (
+            Entity,
+            &InstanceServer,
+            &InstanceRecord,
+            Option<&InstanceServerCertificates>,
+            Option<&ResumeToken>,
+            Option<&ConnectionState>,
+            Option<&SessionTraffic>,
+        ),
+        (
+            Without<Session>,
+            Without<ReconnectTimer>,
+            Without<RefreshCertificates>,
+        ),
+    >,
 ) {
-    for (entity, server, record) in to_open.iter() {
+    for (entity, server, record, certificates, resume_token, state, traffic) in to_open.iter() {
+        let state = match state {
+            Some(ConnectionState::Disconnected) => continue,
+            Some(state @ ConnectionState::Reconnecting { .. }) => *state,
+            _ => ConnectionState::Connecting,
+        };
+
         let address = server.0.clone();
         let record_id = record.0.record_id.clone();
 
+        let certificate_hashes = certificates
+            .map(|c| c.0.iter().filter_map(|hash| hash.sha256_digest()).collect())
+            .unwrap_or_default();
+
+        let traffic = traffic.cloned().unwrap_or_default();
+
         let (send_req, recv_req) = tokio::sync::mpsc::unbounded_channel::<SessionRequest>();
         let (send_res, recv_res) = tokio::sync::mpsc::unbounded_channel::<SessionResponse>();
 
         if let Err(e) = runtime.sender.send(NewSession {
             address,
+            certificate_hashes,
+            conditions: simulated.0.clone(),
+            counters: traffic.0.clone(),
+            did: actor.0.did.clone(),
+            key: actor.0.authorization.jwk.clone(),
             receiver: recv_req,
             record_id,
+            resume_token: resume_token.map(|token| token.0.clone()),
             sender: send_res,
+            world_host: record.0.did.clone(),
         }) {
             error!("{}", e);
             continue;
@@ -56,7 +171,16 @@ fn connect_to_instances(
                 sender: send_req,
             },
             LastTransformPublish(0.0),
+            state,
+            traffic,
         ));
+
+        // Players are kept while reconnecting, in case the session is resumed.
+        if state == ConnectionState::Connecting {
+            commands
+                .entity(entity)
+                .insert((NetworkedObjects::default(), RemotePlayers::default()));
+        }
     }
 }
 
@@ -65,19 +189,251 @@ fn connect_to_instances(
 #[derive(Component, Deref, DerefMut)]
 struct Tickrate(f32);
 
-fn handle_session_response(mut commands: Commands, mut sessions: Query<(Entity, &mut Session)>) {
-    for (entity, mut session) in sessions.iter_mut() {
-        if let Ok(res) = session.receiver.try_recv() {
+fn handle_session_response(
+    asset_server: Res<AssetServer>,
+    mut chat: EventWriter<ChatMessage>,
+    mut chat_failed: EventWriter<ChatMessageFailed>,
+    mut commands: Commands,
+    mut players: Query<(&mut SnapshotBuffer, &mut PlayerInfo, &RemoteAvatar), With<RemotePlayer>>,
+    mut object_responses: EventWriter<ObjectResponseReceived>,
+    mut sessions: Query<(
+        Entity,
+        &mut Session,
+        &mut RemotePlayers,
+        &mut NetworkedObjects,
+        &mut ConnectionState,
+        Option<&Tickrate>,
+    )>,
+    real_time: Res<Time<Real>>,
+    mut server_time: ResMut<ServerTime>,
+    settings: Res<InterpolationSettings>,
+    mut voice_frames: EventWriter<VoiceFrameReceived>,
+) {
+    let local_time = |instant: Instant| {
+        instant
+            .saturating_duration_since(real_time.startup())
+            .as_secs_f64()
+    };
+
+    for (entity, mut session, mut remote_players, mut objects, mut state, tickrate) in
+        sessions.iter_mut()
+    {
+        while let Ok(res) = session.receiver.try_recv() {
             match res {
+                SessionResponse::Connected { resume_token } => {
+                    *state = ConnectionState::Connected;
+                    commands.entity(entity).insert(ResumeToken(resume_token));
+                }
+                SessionResponse::SessionExpired => {
+                    info!("Session expired, rejoining.");
+
+                    for (_, player_ent) in remote_players.drain() {
+                        commands.entity(player_ent).despawn_recursive();
+                    }
+
+                    // Existing objects are sent again when we join.
+                    objects.clear();
+                }
+                SessionResponse::Closed { retry } => {
+                    commands.entity(entity).remove::<(Session, Tickrate)>();
+
+                    let attempt = match *state {
+                        ConnectionState::Reconnecting { attempt } => attempt + 1,
+                        _ => 0,
+                    };
+
+                    if retry && attempt < MAX_RECONNECT_ATTEMPTS {
+                        info!("Connection lost, reconnecting (attempt {}).", attempt + 1);
+                        *state = ConnectionState::Reconnecting { attempt };
+                        commands
+                            .entity(entity)
+                            .insert((ReconnectTimer::new(attempt), RefreshCertificates));
+                    } else {
+                        warn!("Disconnected from instance.");
+                        *state = ConnectionState::Disconnected;
+
+                        for (_, player_ent) in remote_players.drain() {
+                            commands.entity(player_ent).despawn_recursive();
+                        }
+
+                        objects.clear();
+                    }
+
+                    break;
+                }
+                SessionResponse::ChatMessage { player, did, text } => {
+                    let player = match player {
+                        Some(player) => match remote_players.get(&player) {
+                            Some(player_ent) => Some(*player_ent),
+                            None => {
+                                warn!("Chat message from unknown player {}.", player);
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+
+                    chat.send(ChatMessage {
+                        instance: entity,
+                        player,
+                        did,
+                        text,
+                    });
+                }
+                SessionResponse::ChatMessageFailed { text, reason } => {
+                    warn!("Failed to send chat message: {}", reason);
+
+                    chat_failed.send(ChatMessageFailed {
+                        instance: entity,
+                        text,
+                        reason,
+                    });
+                }
                 SessionResponse::Tickrate(tickrate) => {
                     commands.entity(entity).insert(Tickrate(tickrate));
                 }
+                SessionResponse::ClockSample {
+                    sent,
+                    received,
+                    server,
+                } => {
+                    server_time.add_sample(
+                        entity,
+                        ClockSample {
+                            sent: local_time(sent),
+                            received: local_time(received),
+                            server: server.as_secs_f64(),
+                        },
+                    );
+                }
+                SessionResponse::Kicked { reason } => {
+                    warn!("Kicked from instance: {}", reason);
+
+                    // The session is kept, so we do not reconnect.
+                    for (_, player_ent) in remote_players.drain() {
+                        commands.entity(player_ent).despawn_recursive();
+                    }
+
+                    objects.clear();
+                }
+                SessionResponse::PlayerJoined { player, info } => {
+                    if remote_players.contains_key(&player) {
+                        warn!("Player {} already joined.", player);
+                        continue;
+                    }
+
+                    info!("Player {} joined.", player);
+
+                    let player_ent = players::spawn_remote_player(
+                        &asset_server,
+                        &mut commands,
+                        entity,
+                        player,
+                        info,
+                        SnapshotBuffer::default(),
+                    );
+                    remote_players.insert(player, player_ent);
+                }
+                SessionResponse::PlayerLeft { player } => {
+                    info!("Player {} left.", player);
+
+                    if let Some(player_ent) = remote_players.remove(&player) {
+                        commands.entity(player_ent).despawn_recursive();
+                    }
+                }
+                SessionResponse::PlayerUpdated { player, info } => {
+                    if let Some((_, mut current, _)) = remote_players
+                        .get(&player)
+                        .and_then(|ent| players.get_mut(*ent).ok())
+                    {
+                        current.set_if_neq(info);
+                    }
+                }
+                SessionResponse::PlayerAnimation {
+                    player,
+                    state,
+                    velocity,
+                } => {
+                    let Some((_, _, avatar)) = remote_players
+                        .get(&player)
+                        .and_then(|ent| players.get(*ent).ok())
+                    else {
+                        continue;
+                    };
+
+                    commands.entity(**avatar).insert((
+                        state,
+                        ExternalVelocity(velocity),
+                        AnimationReceived(real_time.elapsed_seconds()),
+                    ));
+                }
+                SessionResponse::PlayerPose { player, pose } => {
+                    // Like transforms, poses may arrive outside of join and leave events.
+                    let Some((_, _, avatar)) = remote_players
+                        .get(&player)
+                        .and_then(|ent| players.get(*ent).ok())
+                    else {
+                        continue;
+                    };
+
+                    commands
+                        .entity(**avatar)
+                        .insert((pose, PoseReceived(real_time.elapsed_seconds())));
+                }
                 SessionResponse::PlayerTransform {
                     player,
+                    received,
+                    tick,
                     rotation,
                     translation,
                 } => {
-                    info!("Player: {}, translation: {:?}", player, translation);
+                    // Transforms may arrive before the join event, or after the leave
+                    // event, as datagrams are unordered. These are ignored.
+                    let Some((mut buffer, _, _)) = remote_players
+                        .get(&player)
+                        .and_then(|ent| players.get_mut(*ent).ok())
+                    else {
+                        continue;
+                    };
+
+                    // Once the clock is synced, snapshots are timed by their tick,
+                    // so their spacing is not affected by network jitter.
+                    let time = match (server_time.get(entity), tickrate) {
+                        (Some(clock), Some(tickrate)) => {
+                            clock.arrival_time(tick as f64 * tickrate.0 as f64)
+                        }
+                        _ => local_time(received),
+                    };
+
+                    buffer.push(
+                        Snapshot {
+                            time: time as f32,
+                            translation: Vec3::from_array(translation),
+                            rotation: Quat::from_array(rotation).normalize(),
+                        },
+                        settings.max_snapshots,
+                    );
+                }
+                SessionResponse::VoiceFrame {
+                    player,
+                    sequence,
+                    data,
+                } => {
+                    let Some(player_ent) = remote_players.get(&player) else {
+                        continue;
+                    };
+
+                    voice_frames.send(VoiceFrameReceived {
+                        player: *player_ent,
+                        sequence,
+                        data,
+                    });
+                }
+                SessionResponse::Object(response) => {
+                    object_responses.send(ObjectResponseReceived {
+                        session: entity,
+                        response,
+                    });
                 }
             };
         }
@@ -88,22 +444,49 @@ fn handle_session_response(mut commands: Commands, mut sessions: Query<(Entity,
 struct LastTransformPublish(f32);
 
 fn publish_transform(
+    animation_states: Query<&AvatarAnimationState>,
+    avatars: Query<&AvatarPose>,
+    cameras: Query<&GlobalTransform, With<PlayerCamera>>,
     mut sessions: Query<(&Session, &Tickrate, &mut LastTransformPublish)>,
-    players: Query<&Transform, With<Player>>,
+    players: Query<(&Transform, &Children, Option<&LinearVelocity>), With<Player>>,
     time: Res<Time>,
 ) {
     let elapsed = time.elapsed_seconds();
 
-    if let Some(transform) = players.iter().next() {
+    if let Some((transform, children, velocity)) = players.iter().next() {
+        // Head relative to the player, from where the camera is looking.
+        let head = cameras.iter().next().map(|camera| {
+            let inverse = transform.rotation.inverse();
+            Transform {
+                translation: inverse * (camera.translation() - transform.translation),
+                rotation: inverse * camera.to_scale_rotation_translation().1,
+                ..default()
+            }
+        });
+
+        // Tracked poses, such as from XR, are set on the avatar.
+        let pose = children.iter().find_map(|child| avatars.get(*child).ok());
+
+        let animation_state = children
+            .iter()
+            .find_map(|child| animation_states.get(*child).ok())
+            .copied()
+            .unwrap_or_default();
+        let velocity = velocity.map(|v| v.0).unwrap_or_default();
+
         for (session, interval, mut last) in sessions.iter_mut() {
             let delta = elapsed - last.0;
 
-            if delta > interval.0 {
-                **last = elapsed;
+            if delta < interval.0 {
+                continue;
             }
 
+            **last = elapsed;
+
             let mut msg = capnp::message::Builder::new_default();
-            let mut root = msg.init_root::<datagram_capnp::publish_transform::Builder>();
+            let mut root = msg
+                .init_root::<datagram_capnp::client_datagram::Builder>()
+                .init_publish_transform();
 
             let mut translation = root.reborrow().init_translation();
             translation.set_x(transform.translation.x);
@@ -119,6 +502,18 @@ fn publish_transform(
             if let Err(e) = session.sender.send(SessionRequest::SendDatagram(msg)) {
                 error!("Failed to send: {}", e);
             }
+
+            let msg = pose::write_pose_datagram(head, pose);
+
+            if let Err(e) = session.sender.send(SessionRequest::SendDatagram(msg)) {
+                error!("Failed to send: {}", e);
+            }
+
+            let msg = animation::write_animation_datagram(&animation_state, velocity);
+
+            if let Err(e) = session.sender.send(SessionRequest::SendDatagram(msg)) {
+                error!("Failed to send: {}", e);
+            }
         }
     }
 }
@@ -0,0 +1,552 @@
+//! Networked objects, shared between the players of an instance.
+//!
+//! Each object has at most one owner, which sends its state to the server at the tickrate.
+//! Objects owned by others are interpolated like remote players, and their physics bodies
+//! are made kinematic until we take ownership, such as when grabbing them.
+
+use avian3d::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use wired_world::datagram_capnp::{client_datagram, object_state};
+
+use crate::{
+    interpolation::{InterpolationSettings, Snapshot, SnapshotBuffer},
+    players::RemotePlayers,
+    thread::{ObjectOwner, ObjectResponse, SessionRequest},
+    Session, Tickrate,
+};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectState {
+    pub transform: Transform,
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+}
+
+/// An object that has been spawned on the server.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct NetworkedObject {
+    pub id: u32,
+    /// The [Session](crate::Session) entity this object belongs to.
+    pub session: Entity,
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectOwnership {
+    /// Owned by us, so we send its state.
+    Local,
+    /// Owned by a [RemotePlayer](crate::RemotePlayer).
+    Remote(Entity),
+    /// Not owned by anyone, such as after its owner left.
+    None,
+}
+
+/// Maps object ids -> [NetworkedObject] entities, for each session.
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct NetworkedObjects(pub HashMap<u32, Entity>);
+
+/// Latest velocities received for an object, applied when we take ownership.
+#[derive(Component, Default)]
+struct ReceivedVelocity {
+    linear: Vec3,
+    angular: Vec3,
+}
+
+/// Marks objects spawned by another player, which are despawned with their session.
+#[derive(Component)]
+struct SpawnedRemotely;
+
+/// Spawns an entity as a networked object, owned by us.
+/// Its [Transform], and velocities if it is a physics body, are sent to other players.
+#[derive(Event, Clone, Debug)]
+pub struct SpawnNetworkedObject {
+    /// Instance entity to spawn in.
+    pub instance: Entity,
+    pub entity: Entity,
+    /// Application defined, such as what to spawn for other players.
+    pub data: Vec<u8>,
+    /// Whether other players may take ownership while we own it.
+    pub transferable: bool,
+}
+
+/// Removes a networked object from the server.
+/// The entity is kept, for the app to despawn.
+#[derive(Event, Clone, Debug)]
+pub struct DespawnNetworkedObject {
+    pub entity: Entity,
+}
+
+/// Requests ownership of a networked object, such as when grabbing it.
+#[derive(Event, Clone, Debug)]
+pub struct RequestObjectOwnership {
+    pub entity: Entity,
+}
+
+/// A networked object was spawned by another player, for the app to attach
+/// visuals and physics to.
+#[derive(Event, Clone, Debug)]
+pub struct NetworkedObjectSpawned {
+    pub instance: Entity,
+    pub entity: Entity,
+    pub data: Vec<u8>,
+}
+
+/// Spawning or taking ownership of an object was rejected by the server.
+#[derive(Event, Clone, Debug)]
+pub struct NetworkedObjectFailed {
+    pub entity: Entity,
+    pub reason: String,
+}
+
+/// An object response from a session, for [handle_object_responses].
+#[derive(Event)]
+pub(crate) struct ObjectResponseReceived {
+    pub session: Entity,
+    pub response: ObjectResponse,
+}
+
+pub(crate) fn spawn_networked_objects(
+    mut events: EventReader<SpawnNetworkedObject>,
+    mut failed: EventWriter<NetworkedObjectFailed>,
+    objects: Query<(
+        &Transform,
+        Option<&LinearVelocity>,
+        Option<&AngularVelocity>,
+    )>,
+    sessions: Query<&Session>,
+) {
+    for event in events.read() {
+        let Ok((transform, linear, angular)) = objects.get(event.entity) else {
+            continue;
+        };
+
+        let state = ObjectState {
+            transform: *transform,
+            linear_velocity: linear.map(|v| v.0).unwrap_or_default(),
+            angular_velocity: angular.map(|v| v.0).unwrap_or_default(),
+        };
+
+        let sent = sessions.get(event.instance).is_ok_and(|session| {
+            session
+                .sender
+                .send(SessionRequest::SpawnObject {
+                    entity: event.entity,
+                    data: event.data.clone(),
+                    transferable: event.transferable,
+                    state,
+                })
+                .is_ok()
+        });
+
+        if !sent {
+            failed.send(NetworkedObjectFailed {
+                entity: event.entity,
+                reason: "Not connected".to_string(),
+            });
+        }
+    }
+}
+
+pub(crate) fn despawn_networked_objects(
+    mut commands: Commands,
+    mut events: EventReader<DespawnNetworkedObject>,
+    objects: Query<&NetworkedObject>,
+    mut sessions: Query<(&Session, &mut NetworkedObjects)>,
+) {
+    for event in events.read() {
+        let Ok(object) = objects.get(event.entity) else {
+            continue;
+        };
+
+        if let Ok((session, mut ids)) = sessions.get_mut(object.session) {
+            ids.remove(&object.id);
+
+            if let Err(e) = session
+                .sender
+                .send(SessionRequest::DespawnObject(object.id))
+            {
+                error!("Failed to send: {}", e);
+            }
+        }
+
+        commands
+            .entity(event.entity)
+            .remove::<(NetworkedObject, ObjectOwnership, ReceivedVelocity)>();
+    }
+}
+
+pub(crate) fn request_object_ownership(
+    mut events: EventReader<RequestObjectOwnership>,
+    objects: Query<(&NetworkedObject, &ObjectOwnership)>,
+    sessions: Query<&Session>,
+) {
+    for event in events.read() {
+        let Ok((object, ownership)) = objects.get(event.entity) else {
+            continue;
+        };
+
+        if *ownership == ObjectOwnership::Local {
+            continue;
+        }
+
+        if let Ok(session) = sessions.get(object.session) {
+            if let Err(e) = session
+                .sender
+                .send(SessionRequest::RequestOwnership(object.id))
+            {
+                error!("Failed to send: {}", e);
+            }
+        }
+    }
+}
+
+pub(crate) fn handle_object_responses(
+    mut commands: Commands,
+    mut events: EventReader<ObjectResponseReceived>,
+    mut failed: EventWriter<NetworkedObjectFailed>,
+    mut objects: Query<(&mut SnapshotBuffer, &mut ReceivedVelocity)>,
+    real_time: Res<Time<Real>>,
+    mut sessions: Query<(&mut NetworkedObjects, &RemotePlayers)>,
+    settings: Res<InterpolationSettings>,
+    mut spawned: EventWriter<NetworkedObjectSpawned>,
+) {
+    for event in events.read() {
+        let Ok((mut ids, players)) = sessions.get_mut(event.session) else {
+            continue;
+        };
+
+        let ownership = |owner: ObjectOwner| match owner {
+            ObjectOwner::Local => ObjectOwnership::Local,
+            ObjectOwner::Player(id) => match players.get(&id) {
+                Some(player) => ObjectOwnership::Remote(*player),
+                None => {
+                    warn!("Object owned by unknown player {}.", id);
+                    ObjectOwnership::None
+                }
+            },
+            ObjectOwner::None => ObjectOwnership::None,
+        };
+
+        match &event.response {
+            ObjectResponse::Created { entity, id } => {
+                let Some(mut entity_commands) = commands.get_entity(*entity) else {
+                    continue;
+                };
+
+                entity_commands.insert((
+                    NetworkedObject {
+                        id: *id,
+                        session: event.session,
+                    },
+                    ObjectOwnership::Local,
+                    ReceivedVelocity::default(),
+                ));
+                ids.insert(*id, *entity);
+            }
+            ObjectResponse::SpawnFailed { entity, reason } => {
+                warn!("Failed to spawn object: {}", reason);
+
+                failed.send(NetworkedObjectFailed {
+                    entity: *entity,
+                    reason: reason.clone(),
+                });
+            }
+            ObjectResponse::Spawned {
+                id,
+                owner,
+                data,
+                state,
+                ..
+            } => {
+                if ids.contains_key(id) {
+                    continue;
+                }
+
+                let entity = commands
+                    .spawn((
+                        NetworkedObject {
+                            id: *id,
+                            session: event.session,
+                        },
+                        ownership(*owner),
+                        ReceivedVelocity {
+                            linear: state.linear_velocity,
+                            angular: state.angular_velocity,
+                        },
+                        SpawnedRemotely,
+                        SpatialBundle::from_transform(state.transform),
+                    ))
+                    .id();
+                ids.insert(*id, entity);
+
+                spawned.send(NetworkedObjectSpawned {
+                    instance: event.session,
+                    entity,
+                    data: data.clone(),
+                });
+            }
+            ObjectResponse::Despawned { id } => {
+                if let Some(entity) = ids.remove(id) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            ObjectResponse::OwnerChanged { id, owner } => {
+                if let Some(entity) = ids.get(id) {
+                    commands.entity(*entity).insert(ownership(*owner));
+                }
+            }
+            ObjectResponse::OwnershipDenied { id, reason } => {
+                debug!("Ownership of object {} denied: {}", id, reason);
+
+                if let Some(entity) = ids.get(id) {
+                    failed.send(NetworkedObjectFailed {
+                        entity: *entity,
+                        reason: reason.clone(),
+                    });
+                }
+            }
+            ObjectResponse::State {
+                id,
+                received,
+                state,
+            } => {
+                // Only objects owned by others are buffered.
+                let Some((mut buffer, mut velocity)) =
+                    ids.get(id).and_then(|entity| objects.get_mut(*entity).ok())
+                else {
+                    continue;
+                };
+
+                buffer.push(
+                    Snapshot {
+                        time: received
+                            .saturating_duration_since(real_time.startup())
+                            .as_secs_f32(),
+                        translation: state.transform.translation,
+                        rotation: state.transform.rotation.normalize(),
+                    },
+                    settings.max_snapshots,
+                );
+
+                velocity.linear = state.linear_velocity;
+                velocity.angular = state.angular_velocity;
+            }
+        }
+    }
+}
+
+/// Interpolates objects owned by others, and simulates objects we own.
+pub(crate) fn sync_object_ownership(
+    mut commands: Commands,
+    mut objects: Query<
+        (
+            Entity,
+            &ObjectOwnership,
+            &ReceivedVelocity,
+            Option<&mut RigidBody>,
+        ),
+        Or<(Changed<ObjectOwnership>, Added<RigidBody>)>,
+    >,
+) {
+    for (entity, ownership, velocity, body) in objects.iter_mut() {
+        let local = *ownership == ObjectOwnership::Local;
+
+        if local {
+            commands.entity(entity).remove::<SnapshotBuffer>();
+        } else {
+            commands.entity(entity).insert(SnapshotBuffer::default());
+        }
+
+        let Some(mut body) = body else {
+            continue;
+        };
+
+        if body.is_static() {
+            continue;
+        }
+
+        if local {
+            // Continue the motion from where the previous owner left it.
+            *body = RigidBody::Dynamic;
+            commands.entity(entity).insert((
+                LinearVelocity(velocity.linear),
+                AngularVelocity(velocity.angular),
+            ));
+        } else {
+            *body = RigidBody::Kinematic;
+        }
+    }
+}
+
+/// Sends the state of objects we own, at the server tickrate.
+pub(crate) fn publish_object_state(
+    mut last_publish: Local<HashMap<Entity, f32>>,
+    objects: Query<(
+        &NetworkedObject,
+        &ObjectOwnership,
+        &Transform,
+        Option<&LinearVelocity>,
+        Option<&AngularVelocity>,
+    )>,
+    sessions: Query<(Entity, &Session, &Tickrate)>,
+    time: Res<Time>,
+) {
+    let elapsed = time.elapsed_seconds();
+
+    for (entity, session, tickrate) in sessions.iter() {
+        let last = last_publish.entry(entity).or_default();
+
+        if elapsed - *last < tickrate.0 {
+            continue;
+        }
+
+        *last = elapsed;
+
+        for (object, ownership, transform, linear, angular) in objects.iter() {
+            if object.session != entity || *ownership != ObjectOwnership::Local {
+                continue;
+            }
+
+            let state = ObjectState {
+                transform: *transform,
+                linear_velocity: linear.map(|v| v.0).unwrap_or_default(),
+                angular_velocity: angular.map(|v| v.0).unwrap_or_default(),
+            };
+
+            let mut msg = capnp::message::Builder::new_default();
+            let root = msg.init_root::<client_datagram::Builder>();
+            write_object_state(root.init_object_state(), object.id, &state);
+
+            if let Err(e) = session.sender.send(SessionRequest::SendDatagram(msg)) {
+                error!("Failed to send: {}", e);
+            }
+        }
+    }
+
+    last_publish.retain(|entity, _| sessions.contains(*entity));
+}
+
+/// Removes objects whose session has closed, or that are no longer known to their session.
+/// Objects we spawned are kept, as the app owns their entity.
+pub(crate) fn remove_stale_objects(
+    mut commands: Commands,
+    objects: Query<(Entity, &NetworkedObject, Has<SpawnedRemotely>)>,
+    sessions: Query<&NetworkedObjects>,
+) {
+    for (entity, object, remote) in objects.iter() {
+        let known = sessions
+            .get(object.session)
+            .is_ok_and(|ids| ids.get(&object.id) == Some(&entity));
+
+        if known {
+            continue;
+        }
+
+        debug!("Removing stale object {}.", object.id);
+
+        if remote {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            commands.entity(entity).remove::<(
+                NetworkedObject,
+                ObjectOwnership,
+                ReceivedVelocity,
+                SnapshotBuffer,
+            )>();
+        }
+    }
+}
+
+pub(crate) fn read_object_state(reader: object_state::Reader) -> capnp::Result<(u32, ObjectState)> {
+    let translation = reader.get_translation()?;
+    let rotation = reader.get_rotation()?;
+    let linear = reader.get_linear_velocity()?;
+    let angular = reader.get_angular_velocity()?;
+
+    let state = ObjectState {
+        transform: Transform {
+            translation: Vec3::new(
+                translation.get_x(),
+                translation.get_y(),
+                translation.get_z(),
+            ),
+            rotation: Quat::from_xyzw(
+                rotation.get_x(),
+                rotation.get_y(),
+                rotation.get_z(),
+                rotation.get_w(),
+            ),
+            ..default()
+        },
+        linear_velocity: Vec3::new(linear.get_x(), linear.get_y(), linear.get_z()),
+        angular_velocity: Vec3::new(angular.get_x(), angular.get_y(), angular.get_z()),
+    };
+
+    Ok((reader.get_id(), state))
+}
+
+pub(crate) fn write_object_state(mut builder: object_state::Builder, id: u32, state: &ObjectState) {
+    builder.set_id(id);
+
+    let mut translation = builder.reborrow().init_translation();
+    translation.set_x(state.transform.translation.x);
+    translation.set_y(state.transform.translation.y);
+    translation.set_z(state.transform.translation.z);
+
+    let mut rotation = builder.reborrow().init_rotation();
+    rotation.set_x(state.transform.rotation.x);
+    rotation.set_y(state.transform.rotation.y);
+    rotation.set_z(state.transform.rotation.z);
+    rotation.set_w(state.transform.rotation.w);
+
+    let mut linear = builder.reborrow().init_linear_velocity();
+    linear.set_x(state.linear_velocity.x);
+    linear.set_y(state.linear_velocity.y);
+    linear.set_z(state.linear_velocity.z);
+
+    let mut angular = builder.init_angular_velocity();
+    angular.set_x(state.angular_velocity.x);
+    angular.set_y(state.angular_velocity.y);
+    angular.set_z(state.angular_velocity.z);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_stale_objects() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_systems(Update, remove_stale_objects);
+
+        let session = app.world_mut().spawn(NetworkedObjects::default()).id();
+
+        let remote = app
+            .world_mut()
+            .spawn((NetworkedObject { id: 0, session }, SpawnedRemotely))
+            .id();
+        let local = app
+            .world_mut()
+            .spawn((NetworkedObject { id: 1, session }, ObjectOwnership::Local))
+            .id();
+
+        {
+            let mut ids = app
+                .world_mut()
+                .get_mut::<NetworkedObjects>(session)
+                .unwrap();
+            ids.insert(0, remote);
+            ids.insert(1, local);
+        }
+
+        app.update();
+        assert!(app.world().get_entity(remote).is_some());
+        assert!(app.world().get::<NetworkedObject>(local).is_some());
+
+        app.world_mut().despawn(session);
+        app.update();
+        assert!(app.world().get_entity(remote).is_none());
+        assert!(app.world().get::<NetworkedObject>(local).is_none());
+        assert!(app.world().get_entity(local).is_some());
+    }
+}
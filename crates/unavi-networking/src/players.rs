@@ -0,0 +1,133 @@
+use avian3d::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_vrm::VrmBundle;
+use unavi_avatar::{
+    default_character_animations, default_vrm, AvatarBundle, AverageVelocity, FallbackAvatar,
+};
+use unavi_constants::layers::OTHER_PLAYER_LAYER;
+use unavi_player::{PLAYER_HEIGHT, PLAYER_WIDTH};
+
+use crate::interpolation::SnapshotBuffer;
+
+/// A player connected to the same instance as us.
+#[derive(Component)]
+pub struct RemotePlayer {
+    /// Local id of the player, assigned by the server for this session.
+    pub id: u16,
+    /// The [Session](crate::Session) entity this player belongs to.
+    pub session: Entity,
+}
+
+/// The avatar entity of a [RemotePlayer].
+#[derive(Component, Clone, Copy, Deref)]
+pub struct RemoteAvatar(pub Entity);
+
+/// Player metadata, as reported by the server.
+#[derive(Component, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlayerInfo {
+    pub avatar: Option<String>,
+    pub did: Option<String>,
+    pub name: Option<String>,
+}
+
+/// Maps local player ids -> [RemotePlayer] entities, for each session.
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct RemotePlayers(pub HashMap<u16, Entity>);
+
+pub(crate) fn spawn_remote_player(
+    asset_server: &AssetServer,
+    commands: &mut Commands,
+    session: Entity,
+    id: u16,
+    info: PlayerInfo,
+    buffer: SnapshotBuffer,
+) -> Entity {
+    let animations = default_character_animations(asset_server);
+
+    let body = commands
+        .spawn((
+            Collider::capsule(PLAYER_WIDTH / 2.0, PLAYER_HEIGHT - PLAYER_WIDTH),
+            CollisionLayers {
+                memberships: OTHER_PLAYER_LAYER,
+                ..default()
+            },
+            RemotePlayer { id, session },
+            RigidBody::Kinematic,
+            buffer,
+            info,
+            // Hidden until the first transform is received.
+            SpatialBundle::HIDDEN_IDENTITY,
+        ))
+        .id();
+
+    let avatar = commands
+        .spawn((
+            AvatarBundle {
+                animations,
+                fallback: FallbackAvatar,
+                state: default(),
+                velocity: AverageVelocity {
+                    target: Some(body),
+                    ..default()
+                },
+            },
+            VrmBundle {
+                scene_bundle: SceneBundle {
+                    transform: Transform::from_xyz(0.0, -PLAYER_HEIGHT / 2.0, 0.0),
+                    ..default()
+                },
+                vrm: default_vrm(asset_server),
+                ..default()
+            },
+        ))
+        .id();
+
+    commands
+        .entity(body)
+        .insert(RemoteAvatar(avatar))
+        .push_children(&[avatar]);
+
+    body
+}
+
+/// Despawns remote players whose session has closed.
+pub(crate) fn despawn_remote_players(
+    mut commands: Commands,
+    players: Query<(Entity, &RemotePlayer)>,
+    sessions: Query<(), With<RemotePlayers>>,
+) {
+    for (entity, player) in players.iter() {
+        if sessions.contains(player.session) {
+            continue;
+        }
+
+        debug!("Session closed, removing player {}.", player.id);
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_despawn_closed_session() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_systems(Update, despawn_remote_players);
+
+        let session = app.world_mut().spawn(RemotePlayers::default()).id();
+        let player = app.world_mut().spawn(RemotePlayer { id: 0, session }).id();
+        app.world_mut()
+            .get_mut::<RemotePlayers>(session)
+            .unwrap()
+            .insert(0, player);
+
+        app.update();
+        assert!(app.world().get_entity(player).is_some());
+
+        app.world_mut().despawn(session);
+        app.update();
+        assert!(app.world().get_entity(player).is_none());
+    }
+}
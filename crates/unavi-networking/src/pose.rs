@@ -0,0 +1,175 @@
+//! Extended poses of remote players, applied to their avatars.
+//!
+//! Players that do not publish poses, or stop publishing them, are animated from
+//! their transform alone.
+
+use bevy::prelude::*;
+use capnp::message::HeapAllocator;
+use unavi_avatar::pose::{pose_bone_index, AvatarPose, POSE_BONES};
+use wired_world::{
+    datagram_capnp::{client_datagram, pose, pose_transform},
+    quantize::{dequantize_offset, pack_rotation, quantize_offset, unpack_rotation},
+};
+
+/// Time without receiving a pose before a remote avatar returns to being animated
+/// from its transform alone, in seconds.
+const POSE_TIMEOUT: f32 = 1.0;
+
+/// Time a remote avatar's pose was last received, in seconds.
+#[derive(Component)]
+pub(crate) struct PoseReceived(pub f32);
+
+pub(crate) fn expire_remote_poses(
+    mut commands: Commands,
+    avatars: Query<(Entity, &PoseReceived)>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed_seconds();
+
+    for (entity, received) in avatars.iter() {
+        if now - received.0 > POSE_TIMEOUT {
+            commands
+                .entity(entity)
+                .remove::<(AvatarPose, PoseReceived)>();
+        }
+    }
+}
+
+pub(crate) fn write_pose_datagram(
+    head: Option<Transform>,
+    pose: Option<&AvatarPose>,
+) -> capnp::message::Builder<HeapAllocator> {
+    let mut msg = capnp::message::Builder::new_default();
+    let mut root = msg
+        .init_root::<client_datagram::Builder>()
+        .init_publish_pose();
+
+    let write_transform = |mut builder: pose_transform::Builder, transform: &Transform| {
+        let [x, y, z] = quantize_offset(transform.translation.to_array());
+        builder.set_x(x);
+        builder.set_y(y);
+        builder.set_z(z);
+        builder.set_rotation(pack_rotation(transform.rotation.to_array()));
+    };
+
+    if let Some(head) = pose.and_then(|p| p.head).or(head) {
+        write_transform(root.reborrow().init_head(), &head);
+    }
+
+    let Some(pose) = pose else {
+        return msg;
+    };
+
+    if let Some(hand) = &pose.left_hand {
+        write_transform(root.reborrow().init_left_hand(), hand);
+    }
+    if let Some(hand) = &pose.right_hand {
+        write_transform(root.reborrow().init_right_hand(), hand);
+    }
+
+    let bones = pose
+        .bones
+        .iter()
+        .filter_map(|(bone, rotation)| Some((pose_bone_index(*bone)?, rotation)))
+        .collect::<Vec<_>>();
+
+    let mut list = root.init_bones(bones.len() as u32);
+    for (i, (bone, rotation)) in bones.into_iter().enumerate() {
+        let mut builder = list.reborrow().get(i as u32);
+        builder.set_bone(bone);
+        builder.set_rotation(pack_rotation(rotation.to_array()));
+    }
+
+    msg
+}
+
+pub(crate) fn read_pose(reader: pose::Reader) -> capnp::Result<AvatarPose> {
+    let read_transform = |reader: pose_transform::Reader| Transform {
+        translation: Vec3::from_array(dequantize_offset([
+            reader.get_x(),
+            reader.get_y(),
+            reader.get_z(),
+        ])),
+        rotation: Quat::from_array(unpack_rotation(reader.get_rotation())),
+        ..default()
+    };
+
+    let mut pose = AvatarPose::default();
+
+    if reader.has_head() {
+        pose.head = Some(read_transform(reader.get_head()?));
+    }
+    if reader.has_left_hand() {
+        pose.left_hand = Some(read_transform(reader.get_left_hand()?));
+    }
+    if reader.has_right_hand() {
+        pose.right_hand = Some(read_transform(reader.get_right_hand()?));
+    }
+
+    for bone in reader.get_bones()?.iter() {
+        // Unknown bones are skipped, in case the list grows.
+        if let Some(name) = POSE_BONES.get(bone.get_bone() as usize) {
+            let rotation = Quat::from_array(unpack_rotation(bone.get_rotation()));
+            pose.bones.insert(*name, rotation);
+        }
+    }
+
+    Ok(pose)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_vrm::BoneName;
+    use capnp::message::ReaderOptions;
+
+    use super::*;
+
+    fn round_trip(head: Option<Transform>, pose: Option<&AvatarPose>) -> AvatarPose {
+        let mut bytes = Vec::new();
+        capnp::serialize_packed::write_message(&mut bytes, &write_pose_datagram(head, pose))
+            .unwrap();
+
+        let msg = capnp::serialize_packed::read_message(bytes.as_slice(), ReaderOptions::default())
+            .unwrap();
+        let root = msg.get_root::<client_datagram::Reader>().unwrap();
+
+        let Ok(client_datagram::PublishPose(pose)) = root.which() else {
+            panic!("Not a pose");
+        };
+
+        read_pose(pose.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_head_only() {
+        let head = Transform::from_xyz(0.0, 0.75, 0.0).with_rotation(Quat::from_rotation_y(0.5));
+        let pose = round_trip(Some(head), None);
+
+        let received = pose.head.unwrap();
+        assert!(received.translation.abs_diff_eq(head.translation, 1e-3));
+        assert!(received.rotation.angle_between(head.rotation) < 1e-2);
+        assert!(pose.left_hand.is_none());
+        assert!(pose.bones.is_empty());
+    }
+
+    #[test]
+    fn test_bones() {
+        let mut pose = AvatarPose {
+            left_hand: Some(Transform::from_xyz(-0.3, 0.2, -0.4)),
+            ..default()
+        };
+        pose.bones
+            .insert(BoneName::LeftUpperArm, Quat::from_rotation_z(1.0));
+        pose.bones.insert(BoneName::Spine, Quat::IDENTITY);
+
+        let received = round_trip(None, Some(&pose));
+
+        assert!(received.head.is_none());
+        assert!(received.left_hand.is_some());
+        assert_eq!(received.bones.len(), 2);
+        assert!(
+            received.bones[&BoneName::LeftUpperArm].angle_between(Quat::from_rotation_z(1.0))
+                < 1e-2
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! Per-session network statistics, for diagnosing multiplayer issues.
+//!
+//! Traffic is counted on the session thread, and turned into rates every [STATS_INTERVAL].
+//! Stats are exposed through the [NetworkStats] resource, as [bevy::diagnostic] entries,
+//! and optionally logged or shown with the [overlay].
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    utils::{HashMap, Instant},
+};
+use unavi_world::InstanceServer;
+
+use crate::{clock::ServerTime, ConnectionState};
+
+pub mod overlay;
+
+/// Time between stat updates, in seconds.
+pub const STATS_INTERVAL: f64 = 1.0;
+
+/// Worst round trip time of any session, in milliseconds.
+pub const RTT: DiagnosticPath = DiagnosticPath::const_new("network/rtt");
+/// Worst snapshot jitter of any session, in milliseconds.
+pub const JITTER: DiagnosticPath = DiagnosticPath::const_new("network/jitter");
+/// Worst snapshot loss of any session, as a percentage.
+pub const LOSS: DiagnosticPath = DiagnosticPath::const_new("network/loss");
+pub const DATAGRAMS_RECEIVED: DiagnosticPath =
+    DiagnosticPath::const_new("network/datagrams_received");
+pub const DATAGRAMS_SENT: DiagnosticPath = DiagnosticPath::const_new("network/datagrams_sent");
+pub const BYTES_RECEIVED: DiagnosticPath = DiagnosticPath::const_new("network/bytes_received");
+pub const BYTES_SENT: DiagnosticPath = DiagnosticPath::const_new("network/bytes_sent");
+
+pub(crate) fn register_diagnostics(app: &mut App) {
+    app.register_diagnostic(Diagnostic::new(RTT).with_suffix("ms"))
+        .register_diagnostic(Diagnostic::new(JITTER).with_suffix("ms"))
+        .register_diagnostic(Diagnostic::new(LOSS).with_suffix("%"))
+        .register_diagnostic(Diagnostic::new(DATAGRAMS_RECEIVED).with_suffix("/s"))
+        .register_diagnostic(Diagnostic::new(DATAGRAMS_SENT).with_suffix("/s"))
+        .register_diagnostic(Diagnostic::new(BYTES_RECEIVED).with_suffix("B/s"))
+        .register_diagnostic(Diagnostic::new(BYTES_SENT).with_suffix("B/s"));
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct NetworkStatsSettings {
+    /// Logs the stats of each session at this interval, in seconds.
+    /// Useful when running headless, such as in CI.
+    pub log_interval: Option<f32>,
+    /// Key to toggle the on-screen [overlay] with.
+    pub overlay_key: Option<KeyCode>,
+}
+
+impl Default for NetworkStatsSettings {
+    fn default() -> Self {
+        Self {
+            log_interval: None,
+            overlay_key: Some(KeyCode::F3),
+        }
+    }
+}
+
+/// Traffic in one direction, per second.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TrafficRate {
+    pub datagrams: f32,
+    pub bytes: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionStats {
+    /// Address of the instance server.
+    pub server: String,
+    pub state: ConnectionState,
+    /// Smoothed round trip time, in seconds.
+    /// `None` until the server's clock has been sampled.
+    pub rtt: Option<f32>,
+    /// Variation in the arrival time of snapshots, in seconds.
+    pub jitter: f32,
+    /// Fraction of snapshots lost over the last interval, from 0 to 1.
+    pub loss: f32,
+    pub received: TrafficRate,
+    pub sent: TrafficRate,
+}
+
+impl std::fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self.state {
+            ConnectionState::Connecting => "connecting".to_string(),
+            ConnectionState::Connected => "connected".to_string(),
+            ConnectionState::Reconnecting { attempt } => format!("reconnecting ({})", attempt + 1),
+            ConnectionState::Disconnected => "disconnected".to_string(),
+        };
+
+        let rtt = match self.rtt {
+            Some(rtt) => format!("{:.0} ms", rtt * 1000.0),
+            None => "-".to_string(),
+        };
+
+        write!(
+            f,
+            "{} [{}] rtt {}, jitter {:.1} ms, loss {:.1}%, in {:.0}/s {:.1} KB/s, out {:.0}/s {:.1} KB/s",
+            self.server,
+            state,
+            rtt,
+            self.jitter * 1000.0,
+            self.loss * 100.0,
+            self.received.datagrams,
+            self.received.bytes / 1000.0,
+            self.sent.datagrams,
+            self.sent.bytes / 1000.0,
+        )
+    }
+}
+
+/// Network stats of each session, keyed by session entity.
+#[derive(Resource, Default)]
+pub struct NetworkStats {
+    /// Counter totals as of the last update, to calculate rates from.
+    previous: HashMap<Entity, CounterTotals>,
+    sessions: HashMap<Entity, SessionStats>,
+}
+
+impl NetworkStats {
+    pub fn get(&self, session: Entity) -> Option<&SessionStats> {
+        self.sessions.get(&session)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &SessionStats)> {
+        self.sessions.iter().map(|(entity, stats)| (*entity, stats))
+    }
+}
+
+/// Traffic counters of a session, shared with its thread.
+/// Kept across reconnects, so rates are continuous.
+#[derive(Component, Clone, Default, Deref)]
+pub(crate) struct SessionTraffic(pub Arc<SessionCounters>);
+
+#[derive(Debug, Default)]
+pub(crate) struct SessionCounters {
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    datagrams_sent: AtomicU64,
+    /// Interarrival jitter of snapshots, in microseconds.
+    jitter_micros: AtomicU64,
+    snapshots_expected: AtomicU64,
+    snapshots_received: AtomicU64,
+}
+
+impl SessionCounters {
+    pub fn datagram_received(&self, len: usize) {
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn datagram_sent(&self, len: usize) {
+        self.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn totals(&self) -> CounterTotals {
+        CounterTotals {
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            datagrams_received: self.datagrams_received.load(Ordering::Relaxed),
+            datagrams_sent: self.datagrams_sent.load(Ordering::Relaxed),
+            snapshots_expected: self.snapshots_expected.load(Ordering::Relaxed),
+            snapshots_received: self.snapshots_received.load(Ordering::Relaxed),
+        }
+    }
+
+    fn jitter(&self) -> f32 {
+        self.jitter_micros.load(Ordering::Relaxed) as f32 / 1_000_000.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct CounterTotals {
+    bytes_received: u64,
+    bytes_sent: u64,
+    datagrams_received: u64,
+    datagrams_sent: u64,
+    snapshots_expected: u64,
+    snapshots_received: u64,
+}
+
+impl CounterTotals {
+    /// Traffic received and sent per second, and the fraction of snapshots lost,
+    /// since `previous`.
+    fn rates(&self, previous: &Self, elapsed: f32) -> (TrafficRate, TrafficRate, f32) {
+        let per_second = |now: u64, before: u64| now.saturating_sub(before) as f32 / elapsed;
+
+        let received = TrafficRate {
+            datagrams: per_second(self.datagrams_received, previous.datagrams_received),
+            bytes: per_second(self.bytes_received, previous.bytes_received),
+        };
+        let sent = TrafficRate {
+            datagrams: per_second(self.datagrams_sent, previous.datagrams_sent),
+            bytes: per_second(self.bytes_sent, previous.bytes_sent),
+        };
+
+        let expected = self
+            .snapshots_expected
+            .saturating_sub(previous.snapshots_expected);
+        let snapshots = self
+            .snapshots_received
+            .saturating_sub(previous.snapshots_received);
+
+        let loss = if expected == 0 {
+            0.0
+        } else {
+            // Reordered snapshots can arrive after being counted as lost.
+            (1.0 - snapshots as f32 / expected as f32).clamp(0.0, 1.0)
+        };
+
+        (received, sent, loss)
+    }
+}
+
+/// Tracks the loss and jitter of snapshots, on the session thread.
+/// Snapshots are sent every tick with increasing sequence numbers,
+/// so gaps are lost datagrams, and their ticks give the time they were sent.
+pub(crate) struct SnapshotTracker {
+    jitter: f64,
+    /// Sequence, tick, and arrival time of the newest snapshot.
+    last: Option<(u32, u32, Instant)>,
+    /// Seconds per tick.
+    tickrate: f64,
+}
+
+impl SnapshotTracker {
+    pub fn new(tickrate: f32) -> Self {
+        Self {
+            jitter: 0.0,
+            last: None,
+            tickrate: tickrate as f64,
+        }
+    }
+
+    /// Records the first chunk of a snapshot.
+    pub fn record(
+        &mut self,
+        counters: &SessionCounters,
+        sequence: u32,
+        tick: u32,
+        received: Instant,
+    ) {
+        counters.snapshots_received.fetch_add(1, Ordering::Relaxed);
+
+        let Some((last_sequence, last_tick, last_received)) = self.last else {
+            counters.snapshots_expected.fetch_add(1, Ordering::Relaxed);
+            self.last = Some((sequence, tick, received));
+            return;
+        };
+
+        let gap = sequence.wrapping_sub(last_sequence);
+
+        // Duplicated or reordered.
+        if gap == 0 || gap > u32::MAX / 2 {
+            return;
+        }
+
+        counters
+            .snapshots_expected
+            .fetch_add(gap as u64, Ordering::Relaxed);
+
+        // RFC 3550 interarrival jitter.
+        let arrival = received
+            .saturating_duration_since(last_received)
+            .as_secs_f64();
+        let sent = tick.wrapping_sub(last_tick) as f64 * self.tickrate;
+        self.jitter += ((arrival - sent).abs() - self.jitter) / 16.0;
+
+        counters
+            .jitter_micros
+            .store((self.jitter * 1_000_000.0) as u64, Ordering::Relaxed);
+
+        self.last = Some((sequence, tick, received));
+    }
+}
+
+pub(crate) fn update_network_stats(
+    mut diagnostics: Diagnostics,
+    mut last_update: Local<f64>,
+    server_time: Res<ServerTime>,
+    sessions: Query<(
+        Entity,
+        &InstanceServer,
+        &ConnectionState,
+        Option<&SessionTraffic>,
+    )>,
+    mut stats: ResMut<NetworkStats>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let elapsed = now - *last_update;
+
+    if elapsed < STATS_INTERVAL {
+        return;
+    }
+
+    *last_update = now;
+
+    let NetworkStats {
+        previous,
+        sessions: all,
+    } = &mut *stats;
+    previous.retain(|entity, _| sessions.contains(*entity));
+    all.retain(|entity, _| sessions.contains(*entity));
+
+    for (entity, server, state, traffic) in sessions.iter() {
+        let totals = traffic.map(|t| t.totals()).unwrap_or_default();
+        let last = previous.insert(entity, totals).unwrap_or_default();
+
+        let (received, sent, loss) = totals.rates(&last, elapsed as f32);
+
+        all.insert(
+            entity,
+            SessionStats {
+                server: server.0.clone(),
+                state: *state,
+                rtt: server_time.get(entity).map(|clock| clock.rtt() as f32),
+                jitter: traffic.map(|t| t.jitter()).unwrap_or_default(),
+                loss,
+                received,
+                sent,
+            },
+        );
+    }
+
+    let max = |value: fn(&SessionStats) -> f32| {
+        all.values().map(value).fold(0.0, |a: f32, b: f32| a.max(b)) as f64
+    };
+    let sum = |value: fn(&SessionStats) -> f32| all.values().map(value).sum::<f32>() as f64;
+
+    diagnostics.add_measurement(&RTT, || max(|s| s.rtt.unwrap_or_default()) * 1000.0);
+    diagnostics.add_measurement(&JITTER, || max(|s| s.jitter) * 1000.0);
+    diagnostics.add_measurement(&LOSS, || max(|s| s.loss) * 100.0);
+    diagnostics.add_measurement(&DATAGRAMS_RECEIVED, || sum(|s| s.received.datagrams));
+    diagnostics.add_measurement(&DATAGRAMS_SENT, || sum(|s| s.sent.datagrams));
+    diagnostics.add_measurement(&BYTES_RECEIVED, || sum(|s| s.received.bytes));
+    diagnostics.add_measurement(&BYTES_SENT, || sum(|s| s.sent.bytes));
+}
+
+pub(crate) fn log_network_stats(
+    mut last_log: Local<f64>,
+    settings: Res<NetworkStatsSettings>,
+    stats: Res<NetworkStats>,
+    time: Res<Time<Real>>,
+) {
+    let Some(interval) = settings.log_interval else {
+        return;
+    };
+
+    let now = time.elapsed_seconds_f64();
+
+    if now - *last_log < interval as f64 {
+        return;
+    }
+
+    *last_log = now;
+
+    for (_, session) in stats.iter() {
+        info!("{}", session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_loss() {
+        let counters = SessionCounters::default();
+        let mut tracker = SnapshotTracker::new(0.05);
+        let start = Instant::now();
+
+        for sequence in [1, 2, 4, 5, 3] {
+            tracker.record(&counters, sequence, sequence, start);
+        }
+
+        let totals = counters.totals();
+        assert_eq!(totals.snapshots_expected, 5);
+        assert_eq!(totals.snapshots_received, 5);
+
+        tracker.record(&counters, 10, 10, start);
+
+        let (_, _, loss) = counters.totals().rates(&totals, 1.0);
+        // 6 to 9 were lost.
+        assert!((loss - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let counters = SessionCounters::default();
+        let mut tracker = SnapshotTracker::new(0.05);
+        let start = Instant::now();
+
+        // Arriving exactly one tick apart.
+        for i in 0..10 {
+            tracker.record(
+                &counters,
+                i,
+                i,
+                start + Duration::from_millis(50 * i as u64),
+            );
+        }
+        assert!(counters.jitter() < 1e-4);
+
+        // Arriving alternately early and late.
+        for i in 10..100 {
+            let offset = if i % 2 == 0 { 0 } else { 20 };
+            let received = start + Duration::from_millis(50 * i as u64 + offset);
+            tracker.record(&counters, i, i, received);
+        }
+        assert!((counters.jitter() - 0.02).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_rates() {
+        let counters = SessionCounters::default();
+        let previous = counters.totals();
+
+        for _ in 0..10 {
+            counters.datagram_received(100);
+        }
+        counters.datagram_sent(50);
+
+        let (received, sent, loss) = counters.totals().rates(&previous, 2.0);
+        assert_eq!(received.datagrams, 5.0);
+        assert_eq!(received.bytes, 500.0);
+        assert_eq!(sent.datagrams, 0.5);
+        assert_eq!(sent.bytes, 25.0);
+        assert_eq!(loss, 0.0);
+    }
+}
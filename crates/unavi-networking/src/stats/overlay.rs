@@ -0,0 +1,82 @@
+//! On-screen overlay of [NetworkStats], toggled with [NetworkStatsSettings::overlay_key].
+
+use bevy::prelude::*;
+
+use super::{NetworkStats, NetworkStatsSettings};
+
+const FONT_SIZE: f32 = 14.0;
+
+#[derive(Component)]
+pub struct StatsOverlay;
+
+pub(crate) fn toggle_stats_overlay(
+    mut commands: Commands,
+    keys: Option<Res<ButtonInput<KeyCode>>>,
+    overlays: Query<Entity, With<StatsOverlay>>,
+    settings: Res<NetworkStatsSettings>,
+) {
+    // No input when running headless.
+    let (Some(keys), Some(key)) = (keys, settings.overlay_key) else {
+        return;
+    };
+
+    if !keys.just_pressed(key) {
+        return;
+    }
+
+    if overlays.is_empty() {
+        commands.spawn((
+            StatsOverlay,
+            TextBundle::from_section(
+                String::new(),
+                TextStyle {
+                    font_size: FONT_SIZE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.0),
+                left: Val::Px(8.0),
+                ..default()
+            })
+            .with_background_color(Color::BLACK.with_alpha(0.5)),
+        ));
+    } else {
+        for entity in overlays.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub(crate) fn update_stats_overlay(
+    added: Query<(), Added<StatsOverlay>>,
+    mut overlays: Query<&mut Text, With<StatsOverlay>>,
+    stats: Res<NetworkStats>,
+) {
+    if !stats.is_changed() && added.is_empty() {
+        return;
+    }
+
+    let value = format_stats(&stats);
+
+    for mut text in overlays.iter_mut() {
+        if let Some(section) = text.sections.first_mut() {
+            section.value.clone_from(&value);
+        }
+    }
+}
+
+fn format_stats(stats: &NetworkStats) -> String {
+    let mut lines = stats
+        .iter()
+        .map(|(_, session)| session.to_string())
+        .collect::<Vec<_>>();
+
+    if lines.is_empty() {
+        return "No sessions".to_string();
+    }
+
+    lines.sort();
+    lines.join("\n")
+}
@@ -1,14 +1,18 @@
 use anyhow::Result;
 
-use wtransport::ClientConfig;
+use wtransport::{tls::Sha256Digest, ClientConfig};
 use xwt_core::endpoint::connect::{Connect, Connecting};
 use xwt_wtransport::Connection;
 
-pub async fn connect(addr: &str) -> Result<Connection> {
+pub async fn connect(addr: &str, certificate_hashes: &[[u8; 32]]) -> Result<Connection> {
     let config = ClientConfig::builder().with_bind_default();
 
     let config = if addr.starts_with("https://127.0.0.1:") {
         config.with_no_cert_validation()
+    } else if !certificate_hashes.is_empty() {
+        config.with_server_certificate_hashes(
+            certificate_hashes.iter().copied().map(Sha256Digest::new),
+        )
     } else {
         config.with_native_certs()
     };
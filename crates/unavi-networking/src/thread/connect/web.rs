@@ -1,9 +1,20 @@
 use anyhow::{anyhow, Result};
 use xwt_core::endpoint::connect::{Connect, Connecting};
-use xwt_web_sys::Session;
+use xwt_web_sys::{CertificateHash, HashAlgorithm, Session, WebTransportOptions};
 
-pub async fn connect(addr: &str) -> Result<Session> {
-    let endpoint = xwt_web_sys::Endpoint::default();
+pub async fn connect(addr: &str, certificate_hashes: &[[u8; 32]]) -> Result<Session> {
+    let endpoint = xwt_web_sys::Endpoint {
+        options: WebTransportOptions {
+            server_certificate_hashes: certificate_hashes
+                .iter()
+                .map(|hash| CertificateHash {
+                    algorithm: HashAlgorithm::Sha256,
+                    value: hash.to_vec(),
+                })
+                .collect(),
+            ..Default::default()
+        },
+    };
 
     let connecting = endpoint.connect(addr).await.map_err(|e| anyhow!("{}", e))?;
 
@@ -0,0 +1,156 @@
+use capnp::capability::Promise;
+use capnp_rpc::pry;
+use tokio::sync::mpsc::UnboundedSender;
+use wired_world::world_server_capnp::{
+    object_owner,
+    player_events::{
+        ChatMessageParams, ChatMessageResults, KickedParams, KickedResults, ObjectDespawnedParams,
+        ObjectDespawnedResults, ObjectOwnerChangedParams, ObjectOwnerChangedResults,
+        ObjectSpawnedParams, ObjectSpawnedResults, PlayerJoinedParams, PlayerJoinedResults,
+        PlayerLeftParams, PlayerLeftResults, PlayerUpdatedParams, PlayerUpdatedResults, Server,
+    },
+    player_info,
+};
+
+use crate::{objects::read_object_state, players::PlayerInfo};
+
+use super::{ObjectOwner, ObjectResponse, SessionResponse};
+
+/// Receives player events pushed by the world server.
+pub struct PlayerEvents {
+    pub sender: UnboundedSender<SessionResponse>,
+}
+
+impl PlayerEvents {
+    fn send(&self, response: SessionResponse) -> Result<(), capnp::Error> {
+        self.sender
+            .send(response)
+            .map_err(|_| capnp::Error::disconnected("Session closed".to_string()))
+    }
+}
+
+impl Server for PlayerEvents {
+    fn chat_message(
+        &mut self,
+        params: ChatMessageParams,
+        _: ChatMessageResults,
+    ) -> Promise<(), capnp::Error> {
+        let message = pry!(pry!(params.get()).get_message());
+        let did = pry!(pry!(message.get_did()).to_string());
+        let text = pry!(pry!(message.get_text()).to_string());
+
+        pry!(self.send(SessionResponse::ChatMessage {
+            player: Some(message.get_player()),
+            did: Some(did).filter(|s| !s.is_empty()),
+            text,
+        }));
+        Promise::ok(())
+    }
+
+    fn kicked(&mut self, params: KickedParams, _: KickedResults) -> Promise<(), capnp::Error> {
+        let reason = pry!(pry!(pry!(params.get()).get_reason()).to_string());
+        pry!(self.send(SessionResponse::Kicked { reason }));
+        Promise::ok(())
+    }
+
+    fn object_spawned(
+        &mut self,
+        params: ObjectSpawnedParams,
+        _: ObjectSpawnedResults,
+    ) -> Promise<(), capnp::Error> {
+        let object = pry!(pry!(params.get()).get_object());
+        let owner = pry!(read_object_owner(pry!(object.get_owner())));
+        let (_, state) = pry!(read_object_state(pry!(object.get_state())));
+
+        pry!(self.send(SessionResponse::Object(ObjectResponse::Spawned {
+            id: object.get_id(),
+            owner,
+            data: pry!(object.get_data()).to_vec(),
+            transferable: object.get_transferable(),
+            state,
+        })));
+        Promise::ok(())
+    }
+
+    fn object_despawned(
+        &mut self,
+        params: ObjectDespawnedParams,
+        _: ObjectDespawnedResults,
+    ) -> Promise<(), capnp::Error> {
+        let id = pry!(params.get()).get_id();
+        pry!(self.send(SessionResponse::Object(ObjectResponse::Despawned { id })));
+        Promise::ok(())
+    }
+
+    fn object_owner_changed(
+        &mut self,
+        params: ObjectOwnerChangedParams,
+        _: ObjectOwnerChangedResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let owner = pry!(read_object_owner(pry!(params.get_owner())));
+
+        pry!(
+            self.send(SessionResponse::Object(ObjectResponse::OwnerChanged {
+                id: params.get_id(),
+                owner,
+            }))
+        );
+        Promise::ok(())
+    }
+
+    fn player_joined(
+        &mut self,
+        params: PlayerJoinedParams,
+        _: PlayerJoinedResults,
+    ) -> Promise<(), capnp::Error> {
+        let (player, info) = pry!(read_player_info(pry!(pry!(params.get()).get_player())));
+        pry!(self.send(SessionResponse::PlayerJoined { player, info }));
+        Promise::ok(())
+    }
+
+    fn player_left(
+        &mut self,
+        params: PlayerLeftParams,
+        _: PlayerLeftResults,
+    ) -> Promise<(), capnp::Error> {
+        let player = pry!(params.get()).get_id();
+        pry!(self.send(SessionResponse::PlayerLeft { player }));
+        Promise::ok(())
+    }
+
+    fn player_updated(
+        &mut self,
+        params: PlayerUpdatedParams,
+        _: PlayerUpdatedResults,
+    ) -> Promise<(), capnp::Error> {
+        let (player, info) = pry!(read_player_info(pry!(pry!(params.get()).get_player())));
+        pry!(self.send(SessionResponse::PlayerUpdated { player, info }));
+        Promise::ok(())
+    }
+}
+
+fn read_object_owner(reader: object_owner::Reader) -> Result<ObjectOwner, capnp::Error> {
+    Ok(match reader.which()? {
+        object_owner::None(_) => ObjectOwner::None,
+        object_owner::You(_) => ObjectOwner::Local,
+        object_owner::Player(id) => ObjectOwner::Player(id),
+    })
+}
+
+fn read_player_info(reader: player_info::Reader) -> Result<(u16, PlayerInfo), capnp::Error> {
+    let read_text =
+        |text: capnp::Result<capnp::text::Reader>| -> Result<Option<String>, capnp::Error> {
+            let text = text?.to_string()?;
+            Ok(Some(text).filter(|s| !s.is_empty()))
+        };
+
+    Ok((
+        reader.get_id(),
+        PlayerInfo {
+            avatar: read_text(reader.get_avatar())?,
+            did: read_text(reader.get_did())?,
+            name: read_text(reader.get_name())?,
+        },
+    ))
+}
@@ -1,22 +1,43 @@
 use anyhow::anyhow;
 use bevy::{
     log::{debug, error, info, info_span},
-    utils::tracing::Instrument,
+    utils::{tracing::Instrument, Instant},
 };
 use capnp::message::ReaderOptions;
 use capnp_rpc::{rpc_twoparty_capnp::Side, twoparty::VatNetwork, RpcSystem};
 use thiserror::Error;
 use tokio::sync::mpsc::{error::SendError, UnboundedSender};
-use wired_world::{datagram_capnp, world_server_capnp::world_server::Client};
+use wired_world::{
+    datagram_capnp::{client_datagram, server_datagram},
+    world_server_capnp::world_server::Client,
+};
 use xwt_core::{
     base::Session,
     session::{datagram::Receive, stream::OpeningBi},
 };
 use xwt_futures_io::{read::ReadCompat, write::WriteCompat};
+use xwt_netsim::SimulatedSession;
 
-use crate::thread::SessionResponse;
+use crate::{
+    animation::read_animation_state,
+    objects::read_object_state,
+    pose::read_pose,
+    stats::{SessionCounters, SnapshotTracker},
+    thread::SessionResponse,
+};
 
-use super::{rpc::join::JoinError, NewSession, SessionRequest};
+use super::{
+    events::PlayerEvents,
+    rpc::{
+        authenticate::AuthenticateError,
+        join::JoinError,
+        objects::{despawn_object, request_ownership, spawn_object},
+        send_message::send_message,
+        time::time,
+    },
+    snapshot::SnapshotDecoder,
+    NewSession, ObjectOwner, ObjectResponse, SessionRequest,
+};
 
 #[derive(Error, Debug)]
 pub enum SessionError {
@@ -26,29 +47,63 @@ pub enum SessionError {
     Connect(anyhow::Error),
     #[error("Connection failed: {0}")]
     Connection(anyhow::Error),
+    #[error(transparent)]
+    Authenticate(#[from] AuthenticateError),
     #[error("Event channel closed")]
     EventChannelClosed,
     #[error(transparent)]
     Join(#[from] JoinError),
+    #[error(transparent)]
+    NotInSchema(#[from] capnp::NotInSchema),
     #[error("Failed to open stream: {0}")]
     OpenStream(anyhow::Error),
     #[error(transparent)]
     Send(#[from] SendError<SessionResponse>),
 }
 
+impl SessionError {
+    /// Whether reconnecting could succeed.
+    /// Failures caused by the server rejecting us, or the app closing the session, are not retried.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Authenticate(
+                AuthenticateError::Denied(_)
+                    | AuthenticateError::Sign(_)
+                    | AuthenticateError::UnsupportedKey
+            ) | Self::EventChannelClosed
+                | Self::Join(JoinError::JoinDenied(_))
+                | Self::Send(_)
+        )
+    }
+}
+
 pub async fn handle_session(
     NewSession {
         address,
+        certificate_hashes,
+        conditions,
+        counters,
+        did,
+        key,
         mut receiver,
         record_id,
+        resume_token,
         sender,
+        world_host,
     }: NewSession,
 ) -> Result<(), SessionError> {
-    let session = super::connect::connect(&address)
+    let session = super::connect::connect(&address, &certificate_hashes)
         .await
         .map_err(SessionError::Connect)?;
     info!("Started session.");
 
+    if !conditions.is_ideal() {
+        info!("Simulating network conditions: {:?}", conditions);
+    }
+
+    let session = SimulatedSession::new(session, conditions);
+
     let (writer, reader) = open_stream(&session)
         .await
         .map_err(SessionError::OpenStream)?;
@@ -74,20 +129,52 @@ pub async fn handle_session(
     );
     info!("Created world server RPC.");
 
-    super::rpc::join::join(&world_server, record_id.clone()).await?;
+    // Subscribe first, to receive events buffered while we were disconnected.
+    let events = capnp_rpc::new_client(PlayerEvents {
+        sender: sender.clone(),
+    });
+    super::rpc::subscribe::subscribe(&world_server, events).await?;
+
+    let resumed = match &resume_token {
+        Some(token) => super::rpc::resume::resume(&world_server, token).await?,
+        None => false,
+    };
+
+    let resume_token = match resume_token {
+        Some(token) if resumed => token,
+        previous => {
+            if previous.is_some() {
+                // Sent before joining, so it arrives before the new join events.
+                sender.send(SessionResponse::SessionExpired)?;
+            }
+
+            super::rpc::authenticate::authenticate(&world_server, did.clone(), &key, &world_host)
+                .await?;
+            super::rpc::join::join(&world_server, record_id.clone()).await?
+        }
+    };
+
+    sender.send(SessionResponse::Connected { resume_token })?;
 
     let tickrate = super::rpc::tickrate::tickrate(&world_server).await?;
     sender.send(SessionResponse::Tickrate(tickrate))?;
 
+    let mut decoder = SnapshotDecoder::default();
+    let mut tracker = SnapshotTracker::new(tickrate);
+
     loop {
         tokio::select! {
             datagram = session.receive_datagram() => {
                 let datagram = datagram.map_err(|e| SessionError::Connection(anyhow!("{}", e)))?;
-                handle_datagram(datagram,  &sender).await?;
+                counters.datagram_received(datagram.as_ref().len());
+                handle_datagram(datagram, &mut decoder, &mut tracker, &counters, &sender, &session)
+                    .await?;
             }
             event = receiver.recv() => {
                 let event = event.ok_or(SessionError::EventChannelClosed)?;
-                let closed = handle_event(event, &session).await?;
+                let closed =
+                    handle_event(event, &session, &counters, &world_server, &record_id, &did, &sender)
+                        .await?;
                 if closed {
                     break;
                 }
@@ -100,47 +187,232 @@ pub async fn handle_session(
 
 async fn handle_datagram(
     dgram: impl AsRef<[u8]>,
+    decoder: &mut SnapshotDecoder,
+    tracker: &mut SnapshotTracker,
+    counters: &SessionCounters,
     sender: &UnboundedSender<SessionResponse>,
+    session: &impl Session,
 ) -> Result<(), SessionError> {
+    let received = Instant::now();
+
     let msg = capnp::serialize_packed::read_message(dgram.as_ref(), ReaderOptions::default())?;
-    let transform = msg.get_root::<datagram_capnp::receive_transform::Reader>()?;
-
-    let player = transform.get_player_id();
-
-    let rotation = transform.get_rotation()?;
-    let rotation = [
-        rotation.get_x(),
-        rotation.get_y(),
-        rotation.get_z(),
-        rotation.get_w(),
-    ];
-
-    let translation = transform.get_translation()?;
-    let translation = [
-        translation.get_x(),
-        translation.get_y(),
-        translation.get_z(),
-    ];
-
-    sender.send(SessionResponse::PlayerTransform {
-        player,
-        rotation,
-        translation,
-    })?;
+    let root = msg.get_root::<server_datagram::Reader>()?;
+
+    let snapshot = match root.which()? {
+        server_datagram::Snapshot(snapshot) => snapshot?,
+        server_datagram::Voice(voice) => {
+            let voice = voice?;
+            let frame = voice.get_frame()?;
+
+            sender.send(SessionResponse::VoiceFrame {
+                player: voice.get_player_id(),
+                sequence: frame.get_sequence(),
+                data: frame.get_data()?.to_vec(),
+            })?;
+
+            return Ok(());
+        }
+        server_datagram::Pose(pose) => {
+            let pose = pose?;
+
+            sender.send(SessionResponse::PlayerPose {
+                player: pose.get_player_id(),
+                pose: read_pose(pose.get_pose()?)?,
+            })?;
+
+            return Ok(());
+        }
+        server_datagram::Animation(animation) => {
+            let animation = animation?;
+            let (state, velocity) = read_animation_state(animation.get_state()?);
+
+            sender.send(SessionResponse::PlayerAnimation {
+                player: animation.get_player_id(),
+                state,
+                velocity,
+            })?;
+
+            return Ok(());
+        }
+        server_datagram::ObjectState(state) => {
+            let (id, state) = read_object_state(state?)?;
+
+            sender.send(SessionResponse::Object(ObjectResponse::State {
+                id,
+                received,
+                state,
+            }))?;
+
+            return Ok(());
+        }
+    };
+
+    // Every snapshot has a first chunk, so only it is tracked.
+    if snapshot.get_chunk() == 0 {
+        tracker.record(
+            counters,
+            snapshot.get_sequence(),
+            snapshot.get_tick(),
+            received,
+        );
+    }
+
+    let (transforms, ack) = decoder.decode(snapshot)?;
+    let tick = snapshot.get_tick();
+
+    for transform in transforms {
+        sender.send(SessionResponse::PlayerTransform {
+            player: transform.player,
+            received,
+            tick,
+            rotation: transform.rotation,
+            translation: transform.translation,
+        })?;
+    }
+
+    if ack {
+        let mut msg = capnp::message::Builder::new_default();
+        let mut root = msg
+            .init_root::<client_datagram::Builder>()
+            .init_snapshot_ack();
+        root.set_sequence(snapshot.get_sequence());
+        root.set_chunk(snapshot.get_chunk());
+
+        let mut data = Vec::new();
+        capnp::serialize_packed::write_message(&mut data, &msg)?;
+        let len = data.len();
+        match session.send_datagram(data).await {
+            Ok(()) => counters.datagram_sent(len),
+            Err(e) => error!("Failed to send snapshot ack: {}", e),
+        };
+    }
 
     Ok(())
 }
 
-async fn handle_event(event: SessionRequest, session: &impl Session) -> Result<bool, SessionError> {
+async fn handle_event(
+    event: SessionRequest,
+    session: &impl Session,
+    counters: &SessionCounters,
+    world_server: &Client,
+    record_id: &str,
+    did: &str,
+    sender: &UnboundedSender<SessionResponse>,
+) -> Result<bool, SessionError> {
     match event {
         SessionRequest::Close => return Ok(true),
         SessionRequest::SendDatagram(builder) => {
             let mut data = Vec::new();
             capnp::serialize_packed::write_message(&mut data, &builder)?;
-            if let Err(e) = session.send_datagram(data).await {
-                error!("Failed to send datagram: {}", e);
+            let len = data.len();
+            match session.send_datagram(data).await {
+                Ok(()) => counters.datagram_sent(len),
+                Err(e) => error!("Failed to send datagram: {}", e),
             };
         }
+        SessionRequest::SendMessage(text) => {
+            let world_server = world_server.clone();
+            let record_id = record_id.to_string();
+            let did = did.to_string();
+            let sender = sender.clone();
+
+            // Sent in the background, so datagrams are not delayed.
+            tokio::task::spawn_local(async move {
+                let response = match send_message(&world_server, &record_id, &text).await {
+                    Ok(()) => SessionResponse::ChatMessage {
+                        player: None,
+                        did: Some(did),
+                        text,
+                    },
+                    Err(e) => SessionResponse::ChatMessageFailed {
+                        text,
+                        reason: e.to_string(),
+                    },
+                };
+
+                let _ = sender.send(response);
+            });
+        }
+        SessionRequest::SpawnObject {
+            entity,
+            data,
+            transferable,
+            state,
+        } => {
+            let world_server = world_server.clone();
+            let record_id = record_id.to_string();
+            let sender = sender.clone();
+
+            tokio::task::spawn_local(async move {
+                let response = match spawn_object(
+                    &world_server,
+                    &record_id,
+                    &data,
+                    transferable,
+                    &state,
+                )
+                .await
+                {
+                    Ok(id) => ObjectResponse::Created { entity, id },
+                    Err(e) => ObjectResponse::SpawnFailed {
+                        entity,
+                        reason: e.to_string(),
+                    },
+                };
+
+                let _ = sender.send(SessionResponse::Object(response));
+            });
+        }
+        SessionRequest::DespawnObject(id) => {
+            let world_server = world_server.clone();
+            let record_id = record_id.to_string();
+
+            tokio::task::spawn_local(async move {
+                if let Err(e) = despawn_object(&world_server, &record_id, id).await {
+                    error!("Failed to despawn object {}: {}", id, e);
+                }
+            });
+        }
+        SessionRequest::RequestOwnership(id) => {
+            let world_server = world_server.clone();
+            let record_id = record_id.to_string();
+            let sender = sender.clone();
+
+            tokio::task::spawn_local(async move {
+                let response = match request_ownership(&world_server, &record_id, id).await {
+                    Ok(()) => ObjectResponse::OwnerChanged {
+                        id,
+                        owner: ObjectOwner::Local,
+                    },
+                    Err(e) => ObjectResponse::OwnershipDenied {
+                        id,
+                        reason: e.to_string(),
+                    },
+                };
+
+                let _ = sender.send(SessionResponse::Object(response));
+            });
+        }
+        SessionRequest::SyncClock => {
+            let world_server = world_server.clone();
+            let sender = sender.clone();
+
+            // Sent in the background, so the round trip is not delayed by other events.
+            tokio::task::spawn_local(async move {
+                let sent = Instant::now();
+
+                match time(&world_server).await {
+                    Ok(server) => {
+                        let _ = sender.send(SessionResponse::ClockSample {
+                            sent,
+                            received: Instant::now(),
+                            server,
+                        });
+                    }
+                    Err(e) => error!("Failed to sync clock: {}", e),
+                }
+            });
+        }
     };
 
     Ok(false)
@@ -1,15 +1,27 @@
-use bevy::{prelude::*, utils::tracing::Instrument};
+use std::{sync::Arc, time::Duration};
+
+use bevy::{
+    prelude::*,
+    utils::{tracing::Instrument, Instant},
+};
 use capnp::message::HeapAllocator;
+use didkit::JWK;
 use tokio::{
     sync::mpsc::{UnboundedReceiver, UnboundedSender},
     task::LocalSet,
 };
+use unavi_avatar::{animation::AvatarAnimationState, pose::AvatarPose};
+use xwt_netsim::Conditions;
+
+use crate::{objects::ObjectState, players::PlayerInfo, stats::SessionCounters};
 
 use self::handler::handle_session;
 
 mod connect;
+mod events;
 mod handler;
 mod rpc;
+mod snapshot;
 
 #[derive(Resource)]
 pub struct NetworkingThread {
@@ -18,23 +30,159 @@ pub struct NetworkingThread {
 
 pub struct NewSession {
     pub address: String,
+    /// SHA-256 hashes of self-signed certificates to accept from the server.
+    pub certificate_hashes: Vec<[u8; 32]>,
+    /// Network conditions to simulate.
+    pub conditions: Conditions,
+    /// Traffic counters of the session, kept across reconnects.
+    pub counters: Arc<SessionCounters>,
+    /// DID to authenticate as.
+    pub did: String,
+    /// Authentication key for `did`.
+    pub key: JWK,
     pub receiver: UnboundedReceiver<SessionRequest>,
     pub record_id: String,
+    /// Token from a previous session, to resume it instead of joining again.
+    pub resume_token: Option<Vec<u8>>,
     pub sender: UnboundedSender<SessionResponse>,
+    /// DID of the world host the instance belongs to, which the server authenticates for.
+    pub world_host: String,
 }
 
 pub enum SessionRequest {
     Close,
     SendDatagram(capnp::message::Builder<HeapAllocator>),
+    SendMessage(String),
+    SpawnObject {
+        /// Local entity of the object, to assign the id to.
+        entity: Entity,
+        data: Vec<u8>,
+        transferable: bool,
+        state: ObjectState,
+    },
+    DespawnObject(u32),
+    RequestOwnership(u32),
+    /// Samples the server's clock.
+    SyncClock,
 }
 
 pub enum SessionResponse {
+    /// Joined the instance.
+    Connected {
+        resume_token: Vec<u8>,
+    },
+    /// The previous session could not be resumed, so its players are gone.
+    SessionExpired,
+    /// The session ended.
+    Closed {
+        /// Whether to try reconnecting.
+        retry: bool,
+    },
     Tickrate(f32),
+    /// Response to [SessionRequest::SyncClock].
+    ClockSample {
+        /// Time the request was sent.
+        sent: Instant,
+        /// Time the response was received.
+        received: Instant,
+        /// Time since the server started, when it handled the request.
+        server: Duration,
+    },
+    ChatMessage {
+        /// `None` for our own messages, once accepted by the server.
+        player: Option<u16>,
+        did: Option<String>,
+        text: String,
+    },
+    /// Our chat message was rejected by the server.
+    ChatMessageFailed {
+        text: String,
+        reason: String,
+    },
+    /// Removed from the instance by the server.
+    Kicked {
+        reason: String,
+    },
+    PlayerJoined {
+        player: u16,
+        info: PlayerInfo,
+    },
+    PlayerLeft {
+        player: u16,
+    },
+    PlayerUpdated {
+        player: u16,
+        info: PlayerInfo,
+    },
+    PlayerAnimation {
+        player: u16,
+        state: AvatarAnimationState,
+        velocity: Vec3,
+    },
+    PlayerPose {
+        player: u16,
+        pose: AvatarPose,
+    },
     PlayerTransform {
         player: u16,
+        /// Time the datagram was received.
+        received: Instant,
+        /// Server tick the snapshot was sent on.
+        tick: u32,
         rotation: [f32; 4],
         translation: [f32; 3],
     },
+    VoiceFrame {
+        player: u16,
+        sequence: u16,
+        data: Vec<u8>,
+    },
+    Object(ObjectResponse),
+}
+
+pub enum ObjectResponse {
+    /// An object we spawned was accepted by the server.
+    Created {
+        entity: Entity,
+        id: u32,
+    },
+    SpawnFailed {
+        entity: Entity,
+        reason: String,
+    },
+    /// An object already in the instance, or spawned by another player.
+    Spawned {
+        id: u32,
+        owner: ObjectOwner,
+        data: Vec<u8>,
+        transferable: bool,
+        state: ObjectState,
+    },
+    Despawned {
+        id: u32,
+    },
+    OwnerChanged {
+        id: u32,
+        owner: ObjectOwner,
+    },
+    OwnershipDenied {
+        id: u32,
+        reason: String,
+    },
+    State {
+        id: u32,
+        /// Time the datagram was received.
+        received: Instant,
+        state: ObjectState,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectOwner {
+    None,
+    /// Owned by us.
+    Local,
+    Player(u16),
 }
 
 impl Default for NetworkingThread {
@@ -47,10 +195,20 @@ impl Default for NetworkingThread {
 
                 tokio::task::spawn_local(
                     async move {
-                        match handle_session(new_session).await {
-                            Ok(_) => info!("Graceful exit."),
-                            Err(e) => error!("{}", e),
+                        let sender = new_session.sender.clone();
+
+                        let retry = match handle_session(new_session).await {
+                            Ok(_) => {
+                                info!("Graceful exit.");
+                                false
+                            }
+                            Err(e) => {
+                                error!("{}", e);
+                                e.is_retryable()
+                            }
                         };
+
+                        let _ = sender.send(SessionResponse::Closed { retry });
                     }
                     .instrument(span),
                 );
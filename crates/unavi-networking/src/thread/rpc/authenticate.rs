@@ -0,0 +1,60 @@
+use std::str::Utf8Error;
+
+use bevy::log::info;
+use didkit::{ssi::jws::sign_bytes, JWK};
+use thiserror::Error;
+use wired_world::{
+    auth::challenge_payload,
+    world_server_capnp::{success::Which, world_server::Client},
+};
+
+#[derive(Error, Debug)]
+pub enum AuthenticateError {
+    #[error(transparent)]
+    Capnp(#[from] capnp::Error),
+    #[error("Authentication denied: {0}")]
+    Denied(String),
+    #[error(transparent)]
+    NotInSchema(#[from] capnp::NotInSchema),
+    #[error("Failed to sign challenge: {0}")]
+    Sign(#[from] didkit::ssi::jws::Error),
+    #[error("Key has no signing algorithm")]
+    UnsupportedKey,
+    #[error(transparent)]
+    Utf8(#[from] Utf8Error),
+}
+
+/// Proves control of `did` to the server of `world_host`, by signing its challenge with `key`.
+pub async fn authenticate(
+    rpc: &Client,
+    did: String,
+    key: &JWK,
+    world_host: &str,
+) -> Result<(), AuthenticateError> {
+    let reply = rpc.challenge_request().send().promise.await?;
+    let nonce = reply.get()?.get_nonce()?;
+
+    let algorithm = key
+        .get_algorithm()
+        .ok_or(AuthenticateError::UnsupportedKey)?;
+    let signature = sign_bytes(algorithm, &challenge_payload(world_host, nonce), key)?;
+
+    let mut request = rpc.authenticate_request();
+    request.get().set_did(did);
+    request.get().set_signature(&signature);
+
+    let reply = request.send().promise.await?;
+    let success = reply.get()?.get_success()?;
+
+    match success.which()? {
+        Which::Success(_) => {
+            info!("Authenticated.");
+        }
+        Which::Error(e) => {
+            let e = e?.to_str()?;
+            return Err(AuthenticateError::Denied(e.to_string()));
+        }
+    };
+
+    Ok(())
+}
@@ -16,12 +16,14 @@ pub enum JoinError {
     Utf8(#[from] Utf8Error),
 }
 
-pub async fn join(rpc: &Client, record_id: String) -> Result<(), JoinError> {
+/// Joins an instance, returning a token to resume the session with.
+pub async fn join(rpc: &Client, record_id: String) -> Result<Vec<u8>, JoinError> {
     let mut request = rpc.join_request();
     request.get().set_record_id(record_id);
 
     let reply = request.send().promise.await?;
-    let success = reply.get()?.get_success()?;
+    let reply = reply.get()?;
+    let success = reply.get_success()?;
 
     match success.which()? {
         Which::Success(_) => {
@@ -33,5 +35,5 @@ pub async fn join(rpc: &Client, record_id: String) -> Result<(), JoinError> {
         }
     };
 
-    Ok(())
+    Ok(reply.get_resume_token()?.to_vec())
 }
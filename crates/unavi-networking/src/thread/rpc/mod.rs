@@ -1,2 +1,8 @@
+pub mod authenticate;
 pub mod join;
+pub mod objects;
+pub mod resume;
+pub mod send_message;
+pub mod subscribe;
 pub mod tickrate;
+pub mod time;
@@ -0,0 +1,68 @@
+use thiserror::Error;
+use wired_world::world_server_capnp::{success, world_server::Client};
+
+use crate::objects::{write_object_state, ObjectState};
+
+#[derive(Error, Debug)]
+pub enum ObjectRequestError {
+    #[error(transparent)]
+    Capnp(#[from] capnp::Error),
+    #[error(transparent)]
+    NotInSchema(#[from] capnp::NotInSchema),
+    #[error("Request rejected: {0}")]
+    Rejected(String),
+}
+
+/// Spawns a networked object, returning its id.
+pub async fn spawn_object(
+    rpc: &Client,
+    record_id: &str,
+    data: &[u8],
+    transferable: bool,
+    state: &ObjectState,
+) -> Result<u32, ObjectRequestError> {
+    let mut request = rpc.spawn_object_request();
+    request.get().set_record_id(record_id);
+    request.get().set_data(data);
+    request.get().set_transferable(transferable);
+    write_object_state(request.get().init_state(), 0, state);
+
+    let reply = request.send().promise.await?;
+    let reply = reply.get()?;
+    read_success(reply.get_success()?)?;
+
+    Ok(reply.get_id())
+}
+
+pub async fn despawn_object(
+    rpc: &Client,
+    record_id: &str,
+    id: u32,
+) -> Result<(), ObjectRequestError> {
+    let mut request = rpc.despawn_object_request();
+    request.get().set_record_id(record_id);
+    request.get().set_id(id);
+
+    let reply = request.send().promise.await?;
+    read_success(reply.get()?.get_success()?)
+}
+
+pub async fn request_ownership(
+    rpc: &Client,
+    record_id: &str,
+    id: u32,
+) -> Result<(), ObjectRequestError> {
+    let mut request = rpc.request_ownership_request();
+    request.get().set_record_id(record_id);
+    request.get().set_id(id);
+
+    let reply = request.send().promise.await?;
+    read_success(reply.get()?.get_success()?)
+}
+
+fn read_success(success: success::Reader) -> Result<(), ObjectRequestError> {
+    match success.which()? {
+        success::Which::Success(_) => Ok(()),
+        success::Which::Error(e) => Err(ObjectRequestError::Rejected(e?.to_string()?)),
+    }
+}
@@ -0,0 +1,26 @@
+use bevy::log::{info, warn};
+use wired_world::world_server_capnp::{success::Which, world_server::Client};
+
+/// Attempts to resume a previous session.
+/// Returns whether the session was resumed.
+pub async fn resume(rpc: &Client, token: &[u8]) -> Result<bool, capnp::Error> {
+    let mut request = rpc.resume_request();
+    request.get().set_token(token);
+
+    let reply = request.send().promise.await?;
+    let success = reply.get()?.get_success()?;
+
+    match success.which()? {
+        Which::Success(_) => {
+            info!("Resumed session.");
+            Ok(true)
+        }
+        Which::Error(e) => {
+            warn!(
+                "Failed to resume session: {}",
+                String::from_utf8_lossy(e?.as_bytes())
+            );
+            Ok(false)
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use thiserror::Error;
+use wired_world::world_server_capnp::{success::Which, world_server::Client};
+
+#[derive(Error, Debug)]
+pub enum SendMessageError {
+    #[error(transparent)]
+    Capnp(#[from] capnp::Error),
+    #[error(transparent)]
+    NotInSchema(#[from] capnp::NotInSchema),
+    #[error("Message rejected: {0}")]
+    Rejected(String),
+}
+
+/// Sends a chat message to the other players in the instance.
+pub async fn send_message(
+    rpc: &Client,
+    record_id: &str,
+    text: &str,
+) -> Result<(), SendMessageError> {
+    let mut request = rpc.send_message_request();
+    request.get().set_record_id(record_id);
+    request.get().set_text(text);
+
+    let reply = request.send().promise.await?;
+    let success = reply.get()?.get_success()?;
+
+    match success.which()? {
+        Which::Success(_) => Ok(()),
+        Which::Error(e) => Err(SendMessageError::Rejected(e?.to_string()?)),
+    }
+}
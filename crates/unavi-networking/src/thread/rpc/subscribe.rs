@@ -0,0 +1,8 @@
+use wired_world::world_server_capnp::{player_events, world_server::Client};
+
+pub async fn subscribe(rpc: &Client, events: player_events::Client) -> Result<(), capnp::Error> {
+    let mut request = rpc.subscribe_request();
+    request.get().set_events(events);
+    request.send().promise.await?;
+    Ok(())
+}
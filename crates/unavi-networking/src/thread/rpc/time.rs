@@ -0,0 +1,13 @@
+use std::time::Duration;
+
+use wired_world::world_server_capnp::world_server::Client;
+
+/// Time since the server started.
+pub async fn time(rpc: &Client) -> Result<Duration, capnp::Error> {
+    let request = rpc.time_request();
+
+    let reply = request.send().promise.await?;
+    let time = reply.get()?.get_time();
+
+    Ok(Duration::from_micros(time))
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, VecDeque};
+
+use wired_world::{datagram_capnp::transform_snapshot, quantize::QuantizedTransform};
+
+/// Number of decoded transforms to keep per player, to resolve delta baselines.
+/// Must cover the server's maximum baseline offset.
+const HISTORY_LEN: usize = 64;
+
+pub struct DecodedTransform {
+    pub player: u16,
+    pub rotation: [f32; 4],
+    pub translation: [f32; 3],
+}
+
+/// Decodes transform snapshots, tracking the baselines needed for delta decoding.
+#[derive(Default)]
+pub struct SnapshotDecoder {
+    history: HashMap<u16, VecDeque<(u32, QuantizedTransform)>>,
+}
+
+impl SnapshotDecoder {
+    /// Decodes a snapshot chunk.
+    /// Returns the decoded transforms, and whether the chunk should be acknowledged.
+    /// Chunks are only acknowledged if every transform could be decoded, so the server
+    /// never delta encodes against a baseline we do not have.
+    pub fn decode(
+        &mut self,
+        snapshot: transform_snapshot::Reader,
+    ) -> capnp::Result<(Vec<DecodedTransform>, bool)> {
+        let sequence = snapshot.get_sequence();
+
+        let origin = snapshot.get_origin()?;
+        let origin = [origin.get_x(), origin.get_y(), origin.get_z()];
+
+        let players = snapshot.get_players()?;
+
+        let mut decoded = Vec::with_capacity(players.len() as usize);
+        let mut complete = true;
+
+        for player in players.iter() {
+            let id = player.get_player_id();
+
+            let value = QuantizedTransform {
+                translation: [player.get_x(), player.get_y(), player.get_z()],
+                rotation: player.get_rotation(),
+            };
+
+            let quantized = match player.get_baseline() {
+                0 => value,
+                offset => {
+                    let baseline_sequence = sequence.wrapping_sub(offset as u32);
+
+                    let baseline = self.history.get(&id).and_then(|history| {
+                        history
+                            .iter()
+                            .find(|(s, _)| *s == baseline_sequence)
+                            .map(|(_, q)| *q)
+                    });
+
+                    match baseline {
+                        Some(baseline) => value.apply_delta(&baseline),
+                        None => {
+                            complete = false;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let history = self.history.entry(id).or_default();
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back((sequence, quantized));
+
+            decoded.push(DecodedTransform {
+                player: id,
+                rotation: quantized.rotation(),
+                translation: quantized.translation(origin),
+            });
+        }
+
+        Ok((decoded, complete))
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use bevy::log::error;
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    SampleFormat, Stream,
+};
+
+use super::codec::SAMPLE_RATE;
+
+/// Microphone samples, downmixed to mono and resampled to [SAMPLE_RATE].
+pub type CapturedSamples = Arc<Mutex<Vec<f32>>>;
+
+/// Starts capturing from the default input device.
+/// Capture stops when the returned stream is dropped.
+pub fn start_capture(samples: CapturedSamples) -> Result<Stream> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or_else(|| anyhow!("No input device"))?;
+    let config = device.default_input_config()?;
+
+    let channels = config.channels() as usize;
+    let mut resampler = Resampler::new(config.sample_rate().0, SAMPLE_RATE);
+
+    let mut push = move |mono: &mut dyn Iterator<Item = f32>| {
+        let mut samples = samples.lock().unwrap();
+        resampler.process(mono, &mut samples);
+    };
+
+    let on_error = |e: cpal::StreamError| error!("Microphone error: {}", e);
+
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => device.build_input_stream(
+            &config.config(),
+            move |data: &[f32], _| push(&mut downmix(data, channels, |s| s)),
+            on_error,
+            None,
+        )?,
+        SampleFormat::I16 => device.build_input_stream(
+            &config.config(),
+            move |data: &[i16], _| {
+                push(&mut downmix(data, channels, |s| s as f32 / i16::MAX as f32))
+            },
+            on_error,
+            None,
+        )?,
+        format => return Err(anyhow!("Unsupported sample format: {:?}", format)),
+    };
+
+    stream.play()?;
+
+    Ok(stream)
+}
+
+/// Averages interleaved channels into mono samples.
+fn downmix<'a, T: Copy>(
+    data: &'a [T],
+    channels: usize,
+    convert: impl Fn(T) -> f32 + 'a,
+) -> impl Iterator<Item = f32> + 'a {
+    data.chunks(channels.max(1))
+        .map(move |frame| frame.iter().map(|s| convert(*s)).sum::<f32>() / frame.len() as f32)
+}
+
+/// Streaming linear resampler.
+struct Resampler {
+    /// Input samples per output sample.
+    step: f64,
+    /// Position of the next output sample, relative to `last`.
+    position: f64,
+    last: f32,
+}
+
+impl Resampler {
+    fn new(from: u32, to: u32) -> Self {
+        Self {
+            step: from as f64 / to as f64,
+            position: 0.0,
+            last: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: &mut dyn Iterator<Item = f32>, output: &mut Vec<f32>) {
+        for sample in input {
+            while self.position < 1.0 {
+                let t = self.position as f32;
+                output.push(self.last + (sample - self.last) * t);
+                self.position += self.step;
+            }
+
+            self.position -= 1.0;
+            self.last = sample;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(from: u32, to: u32, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::new();
+        Resampler::new(from, to).process(&mut input.iter().copied(), &mut output);
+        output
+    }
+
+    #[test]
+    fn test_downmix() {
+        let mono = downmix(&[1.0, 0.0, 0.5, 0.5], 2, |s| s).collect::<Vec<_>>();
+        assert_eq!(mono, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_resample() {
+        let input = vec![1.0; 441];
+        assert_eq!(resample(SAMPLE_RATE, SAMPLE_RATE, &input).len(), 441);
+
+        let output = resample(44_100, SAMPLE_RATE, &input);
+        assert!((479..=481).contains(&output.len()), "{}", output.len());
+
+        let output = resample(96_000, SAMPLE_RATE, &[0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(output.len(), 2);
+    }
+}
@@ -0,0 +1,122 @@
+use opus::{Application, Bitrate, Channels, Decoder, Encoder};
+
+pub const SAMPLE_RATE: u32 = 48_000;
+/// Samples per 20ms frame.
+pub const FRAME_SAMPLES: usize = SAMPLE_RATE as usize / 50;
+
+const BITRATE: i32 = 32_000;
+/// Frames quieter than this RMS are not sent.
+const SILENCE_THRESHOLD: f32 = 0.01;
+
+/// Encodes mono 48kHz audio into Opus frames.
+pub struct VoiceEncoder {
+    encoder: Encoder,
+    pending: Vec<f32>,
+}
+
+impl VoiceEncoder {
+    pub fn new() -> Result<Self, opus::Error> {
+        let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Voip)?;
+        encoder.set_bitrate(Bitrate::Bits(BITRATE))?;
+
+        Ok(Self {
+            encoder,
+            pending: Vec::with_capacity(FRAME_SAMPLES),
+        })
+    }
+
+    /// Buffers samples, returning any complete frames that are not silent.
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>, opus::Error> {
+        let mut frames = Vec::new();
+
+        for sample in samples {
+            self.pending.push(*sample);
+
+            if self.pending.len() < FRAME_SAMPLES {
+                continue;
+            }
+
+            if rms(&self.pending) >= SILENCE_THRESHOLD {
+                frames.push(self.encoder.encode_vec_float(&self.pending, 1275)?);
+            }
+
+            self.pending.clear();
+        }
+
+        Ok(frames)
+    }
+}
+
+/// Decodes Opus frames from a single player.
+pub struct VoiceDecoder {
+    decoder: Decoder,
+    output: Vec<f32>,
+}
+
+impl VoiceDecoder {
+    pub fn new() -> Result<Self, opus::Error> {
+        Ok(Self {
+            decoder: Decoder::new(SAMPLE_RATE, Channels::Mono)?,
+            output: vec![0.0; FRAME_SAMPLES],
+        })
+    }
+
+    /// Decodes a frame, or conceals a lost frame if `data` is `None`.
+    pub fn decode(&mut self, data: Option<&[u8]>) -> Result<&[f32], opus::Error> {
+        let len = self
+            .decoder
+            .decode_float(data.unwrap_or_default(), &mut self.output, false)?;
+
+        Ok(&self.output[..len])
+    }
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    fn sine(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (i as f32 * 440.0 * TAU / SAMPLE_RATE as f32).sin() * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut encoder = VoiceEncoder::new().unwrap();
+        let mut decoder = VoiceDecoder::new().unwrap();
+
+        // Partial frames are buffered.
+        assert!(encoder.push(&sine(FRAME_SAMPLES / 2)).unwrap().is_empty());
+
+        let frames = encoder.push(&sine(FRAME_SAMPLES * 10)).unwrap();
+        assert_eq!(frames.len(), 10);
+
+        let mut decoded = Vec::new();
+        for frame in frames.iter() {
+            decoded.extend_from_slice(decoder.decode(Some(frame)).unwrap());
+        }
+        assert_eq!(decoded.len(), FRAME_SAMPLES * 10);
+
+        // Skip the first frames, while the codec warms up.
+        let level = rms(&decoded[FRAME_SAMPLES * 2..]);
+        let expected = rms(&sine(FRAME_SAMPLES));
+        assert!((level - expected).abs() < 0.1, "{} vs {}", level, expected);
+
+        let concealed = decoder.decode(None).unwrap();
+        assert_eq!(concealed.len(), FRAME_SAMPLES);
+    }
+
+    #[test]
+    fn test_silence() {
+        let mut encoder = VoiceEncoder::new().unwrap();
+        let frames = encoder.push(&vec![0.0; FRAME_SAMPLES * 5]).unwrap();
+        assert!(frames.is_empty());
+    }
+}
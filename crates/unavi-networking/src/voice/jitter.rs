@@ -0,0 +1,158 @@
+use bevy::utils::HashMap;
+
+/// Frames to buffer before starting playback, to absorb network jitter.
+const PLAYBACK_DELAY: usize = 3;
+/// Missing frames to conceal before skipping ahead, such as after a pause in speech.
+const MAX_CONCEALED: u16 = 5;
+/// How far behind playback a frame may arrive before the sender is assumed to have restarted
+/// its sequence, rather than the frame being late.
+const MAX_LATE: u16 = 50;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JitterFrame {
+    Audio(Vec<u8>),
+    /// The next frame was lost, and should be concealed.
+    Lost,
+}
+
+/// Reorders received voice frames by sequence number.
+#[derive(Default)]
+pub struct JitterBuffer {
+    frames: HashMap<u16, Vec<u8>>,
+    /// Sequence of the next frame to play.
+    next: Option<u16>,
+    playing: bool,
+}
+
+impl JitterBuffer {
+    pub fn push(&mut self, sequence: u16, data: Vec<u8>) {
+        if let Some(next) = self.next {
+            if is_before(sequence, next) {
+                if next.wrapping_sub(sequence) <= MAX_LATE {
+                    // Arrived after it should have been played.
+                    return;
+                }
+
+                // Start again from the new sequence.
+                *self = Self::default();
+            }
+        }
+
+        self.frames.insert(sequence, data);
+    }
+
+    /// Returns the next frame to play, or `None` if we are waiting for more frames.
+    pub fn pop(&mut self) -> Option<JitterFrame> {
+        if !self.playing {
+            if self.frames.len() < PLAYBACK_DELAY {
+                return None;
+            }
+
+            self.playing = true;
+            self.next = self.earliest();
+        }
+
+        let Some(earliest) = self.earliest() else {
+            // Ran out of frames, so buffer again before resuming.
+            self.playing = false;
+            return None;
+        };
+
+        let next = match self.next {
+            Some(next) if earliest.wrapping_sub(next) <= MAX_CONCEALED => next,
+            _ => earliest,
+        };
+
+        self.next = Some(next.wrapping_add(1));
+
+        match self.frames.remove(&next) {
+            Some(data) => Some(JitterFrame::Audio(data)),
+            None => Some(JitterFrame::Lost),
+        }
+    }
+
+    fn earliest(&self) -> Option<u16> {
+        self.frames
+            .keys()
+            .copied()
+            .reduce(|a, b| if is_before(b, a) { b } else { a })
+    }
+}
+
+/// Whether sequence `a` comes before `b`, accounting for wrapping.
+fn is_before(a: u16, b: u16) -> bool {
+    a != b && b.wrapping_sub(a) < u16::MAX / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop_all(buffer: &mut JitterBuffer) -> Vec<JitterFrame> {
+        std::iter::from_fn(|| buffer.pop()).collect()
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut buffer = JitterBuffer::default();
+
+        buffer.push(1, vec![1]);
+        assert_eq!(buffer.pop(), None);
+
+        buffer.push(0, vec![0]);
+        buffer.push(3, vec![3]);
+        buffer.push(u16::MAX, vec![255]);
+
+        assert_eq!(
+            pop_all(&mut buffer),
+            vec![
+                JitterFrame::Audio(vec![255]),
+                JitterFrame::Audio(vec![0]),
+                JitterFrame::Audio(vec![1]),
+                JitterFrame::Lost,
+                JitterFrame::Audio(vec![3]),
+            ]
+        );
+
+        // Too late to be played.
+        buffer.push(2, vec![2]);
+        assert!(buffer.frames.is_empty());
+    }
+
+    #[test]
+    fn test_skip_gap() {
+        let mut buffer = JitterBuffer::default();
+
+        for sequence in [0, 1, 2, 100, 101, 102] {
+            buffer.push(sequence, vec![sequence as u8]);
+        }
+
+        let frames = pop_all(&mut buffer);
+        assert_eq!(frames.len(), 6);
+        assert!(frames.iter().all(|f| matches!(f, JitterFrame::Audio(_))));
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut buffer = JitterBuffer::default();
+
+        for sequence in 1000..1010 {
+            buffer.push(sequence, vec![1]);
+        }
+        assert_eq!(pop_all(&mut buffer).len(), 10);
+
+        // The sender restarted from 0.
+        for sequence in 0..3 {
+            buffer.push(sequence, vec![sequence as u8]);
+        }
+
+        assert_eq!(
+            pop_all(&mut buffer),
+            vec![
+                JitterFrame::Audio(vec![0]),
+                JitterFrame::Audio(vec![1]),
+                JitterFrame::Audio(vec![2]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Spatial voice chat.
+//!
+//! Microphone audio is encoded with Opus and sent to the world server as datagrams,
+//! which relays it to nearby players.
+//! The microphone is only opened once enabled with [VoiceSettings::microphone],
+//! and by default only sends while the push-to-talk key is held.
+//! Each [RemotePlayer](crate::RemotePlayer) plays their voice from a spatial audio source.
+//!
+//! Voice is not yet supported on the web.
+
+use bevy::prelude::*;
+
+#[cfg(not(target_family = "wasm"))]
+mod capture;
+#[cfg(not(target_family = "wasm"))]
+mod codec;
+#[cfg(not(target_family = "wasm"))]
+mod jitter;
+#[cfg(not(target_family = "wasm"))]
+mod playback;
+
+pub struct VoicePlugin;
+
+impl Plugin for VoicePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoiceSettings>();
+
+        #[cfg(not(target_family = "wasm"))]
+        {
+            use bevy::audio::AddAudioSource;
+
+            app.add_audio_source::<playback::VoiceStream>()
+                .init_resource::<native::VoiceSequence>()
+                .add_systems(
+                    Update,
+                    (
+                        (
+                            native::update_microphone,
+                            native::send_voice.run_if(resource_exists::<native::VoiceInput>),
+                        )
+                            .chain(),
+                        (
+                            native::setup_voice_output,
+                            native::receive_voice,
+                            native::play_voice,
+                        )
+                            .chain(),
+                    ),
+                );
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct VoiceSettings {
+    /// Whether to open the microphone and send its audio.
+    /// Off until the user opts in.
+    pub microphone: bool,
+    /// Only sends audio while this key is held.
+    /// If `None`, audio is sent whenever the microphone is enabled.
+    pub push_to_talk: Option<KeyCode>,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            microphone: false,
+            push_to_talk: Some(KeyCode::KeyV),
+        }
+    }
+}
+
+/// A voice frame received from a remote player.
+#[derive(Event)]
+#[cfg_attr(target_family = "wasm", allow(dead_code))]
+pub(crate) struct VoiceFrameReceived {
+    pub player: Entity,
+    pub sequence: u16,
+    pub data: Vec<u8>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+mod native {
+    use bevy::utils::synccell::SyncCell;
+    use capnp::message::HeapAllocator;
+    use unavi_player::PLAYER_HEIGHT;
+    use wired_world::datagram_capnp::client_datagram;
+
+    use crate::{thread::SessionRequest, ConnectionState, RemotePlayer, Session};
+
+    use super::{
+        capture::{start_capture, CapturedSamples},
+        codec::{VoiceDecoder, VoiceEncoder, FRAME_SAMPLES},
+        jitter::{JitterBuffer, JitterFrame},
+        playback::{PlaybackSamples, VoiceStream},
+        *,
+    };
+
+    /// Decoded frames to keep queued for playback.
+    const QUEUED_FRAMES: usize = 3;
+
+    /// Keeps the microphone stream open.
+    struct Microphone(#[allow(dead_code)] cpal::Stream);
+
+    #[derive(Resource)]
+    pub struct VoiceInput {
+        encoder: SyncCell<VoiceEncoder>,
+        samples: CapturedSamples,
+    }
+
+    /// Sequence of the next voice frame to send.
+    /// Kept when the microphone is reopened, as listeners drop frames older than they have played.
+    #[derive(Resource, Default)]
+    pub struct VoiceSequence(u16);
+
+    #[derive(Component)]
+    pub struct VoiceOutput {
+        decoder: SyncCell<VoiceDecoder>,
+        jitter: JitterBuffer,
+        samples: PlaybackSamples,
+    }
+
+    /// Opens the microphone when enabled, and closes it when disabled.
+    pub fn update_microphone(world: &mut World, mut failed: Local<bool>) {
+        let enabled = world.resource::<VoiceSettings>().microphone;
+        let open = world.contains_resource::<VoiceInput>();
+
+        if !enabled {
+            *failed = false;
+
+            if open {
+                world.remove_resource::<VoiceInput>();
+                world.remove_non_send_resource::<Microphone>();
+            }
+
+            return;
+        }
+
+        // Not retried until the microphone is toggled, to avoid spamming errors.
+        if open || *failed {
+            return;
+        }
+
+        *failed = !start_microphone(world);
+    }
+
+    fn start_microphone(world: &mut World) -> bool {
+        let encoder = match VoiceEncoder::new() {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                error!("Failed to create voice encoder: {}", e);
+                return false;
+            }
+        };
+
+        let samples = CapturedSamples::default();
+
+        let stream = match start_capture(samples.clone()) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Microphone unavailable: {}", e);
+                return false;
+            }
+        };
+
+        world.insert_non_send_resource(Microphone(stream));
+        world.insert_resource(VoiceInput {
+            encoder: SyncCell::new(encoder),
+            samples,
+        });
+
+        true
+    }
+
+    pub fn send_voice(
+        mut input: ResMut<VoiceInput>,
+        mut sequence: ResMut<VoiceSequence>,
+        keys: Option<Res<ButtonInput<KeyCode>>>,
+        sessions: Query<(&Session, &ConnectionState)>,
+        settings: Res<VoiceSettings>,
+    ) {
+        let samples = std::mem::take(&mut *input.samples.lock().unwrap());
+
+        if let Some(key) = settings.push_to_talk {
+            if !keys.is_some_and(|keys| keys.pressed(key)) {
+                return;
+            }
+        }
+
+        let frames = match input.encoder.get().push(&samples) {
+            Ok(frames) => frames,
+            Err(e) => {
+                error!("Failed to encode voice: {}", e);
+                return;
+            }
+        };
+
+        for data in frames {
+            let current = sequence.0;
+            sequence.0 = current.wrapping_add(1);
+
+            for (session, state) in sessions.iter() {
+                if *state != ConnectionState::Connected {
+                    continue;
+                }
+
+                let msg = write_voice_datagram(current, &data);
+
+                if let Err(e) = session.sender.send(SessionRequest::SendDatagram(msg)) {
+                    error!("Failed to send: {}", e);
+                }
+            }
+        }
+    }
+
+    pub fn setup_voice_output(
+        mut commands: Commands,
+        mut streams: ResMut<Assets<VoiceStream>>,
+        players: Query<Entity, Added<RemotePlayer>>,
+    ) {
+        for entity in players.iter() {
+            let decoder = match VoiceDecoder::new() {
+                Ok(decoder) => decoder,
+                Err(e) => {
+                    error!("Failed to create voice decoder: {}", e);
+                    continue;
+                }
+            };
+
+            let samples = PlaybackSamples::default();
+
+            // Near the head, relative to the player's center.
+            let mouth = Transform::from_xyz(0.0, (PLAYER_HEIGHT / 2.0) * 0.85, 0.0);
+
+            let source = commands
+                .spawn((
+                    AudioSourceBundle {
+                        source: streams.add(VoiceStream {
+                            samples: samples.clone(),
+                        }),
+                        settings: PlaybackSettings::LOOP.with_spatial(true),
+                    },
+                    TransformBundle::from_transform(mouth),
+                ))
+                .id();
+
+            commands
+                .entity(entity)
+                .add_child(source)
+                .insert(VoiceOutput {
+                    decoder: SyncCell::new(decoder),
+                    jitter: JitterBuffer::default(),
+                    samples,
+                });
+        }
+    }
+
+    pub fn receive_voice(
+        mut events: EventReader<VoiceFrameReceived>,
+        mut outputs: Query<&mut VoiceOutput>,
+    ) {
+        for event in events.read() {
+            if let Ok(mut output) = outputs.get_mut(event.player) {
+                output.jitter.push(event.sequence, event.data.clone());
+            }
+        }
+    }
+
+    pub fn play_voice(mut outputs: Query<&mut VoiceOutput>) {
+        for mut output in outputs.iter_mut() {
+            let output = &mut *output;
+            let mut samples = output.samples.lock().unwrap();
+
+            while samples.len() < FRAME_SAMPLES * QUEUED_FRAMES {
+                let Some(frame) = output.jitter.pop() else {
+                    break;
+                };
+
+                let data = match &frame {
+                    JitterFrame::Audio(data) => Some(data.as_slice()),
+                    JitterFrame::Lost => None,
+                };
+
+                match output.decoder.get().decode(data) {
+                    Ok(decoded) => samples.extend(decoded),
+                    Err(e) => warn!("Failed to decode voice: {}", e),
+                }
+            }
+        }
+    }
+
+    pub fn write_voice_datagram(
+        sequence: u16,
+        data: &[u8],
+    ) -> capnp::message::Builder<HeapAllocator> {
+        let mut msg = capnp::message::Builder::new_default();
+        let mut root = msg.init_root::<client_datagram::Builder>().init_voice();
+        root.set_sequence(sequence);
+        root.set_data(data);
+        msg
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use capnp::message::ReaderOptions;
+    use wired_world::datagram_capnp::client_datagram;
+
+    use super::{
+        codec::{VoiceDecoder, VoiceEncoder, FRAME_SAMPLES, SAMPLE_RATE},
+        jitter::{JitterBuffer, JitterFrame},
+        native::write_voice_datagram,
+    };
+
+    /// Sends a datagram through a loopback transport, reading it as the server would.
+    fn loopback(sequence: u16, data: &[u8]) -> (u16, Vec<u8>) {
+        let mut bytes = Vec::new();
+        capnp::serialize_packed::write_message(&mut bytes, &write_voice_datagram(sequence, data))
+            .unwrap();
+
+        let msg = capnp::serialize_packed::read_message(bytes.as_slice(), ReaderOptions::default())
+            .unwrap();
+        let root = msg.get_root::<client_datagram::Reader>().unwrap();
+
+        let Ok(client_datagram::Voice(frame)) = root.which() else {
+            panic!("Not a voice frame");
+        };
+        let frame = frame.unwrap();
+
+        (frame.get_sequence(), frame.get_data().unwrap().to_vec())
+    }
+
+    #[test]
+    fn test_loopback() {
+        const FRAMES: usize = 10;
+        const LOST: u16 = 4;
+
+        let audio = (0..FRAME_SAMPLES * FRAMES)
+            .map(|i| (i as f32 * 220.0 * TAU / SAMPLE_RATE as f32).sin() * 0.5)
+            .collect::<Vec<_>>();
+
+        let mut encoder = VoiceEncoder::new().unwrap();
+        let frames = encoder.push(&audio).unwrap();
+        assert_eq!(frames.len(), FRAMES);
+
+        // Delivered in reverse, with one frame lost.
+        let mut jitter = JitterBuffer::default();
+        for (sequence, data) in frames.iter().enumerate().rev() {
+            let sequence = sequence as u16;
+            if sequence != LOST {
+                let (sequence, data) = loopback(sequence, data);
+                jitter.push(sequence, data);
+            }
+        }
+
+        let mut decoder = VoiceDecoder::new().unwrap();
+        let mut decoded = Vec::new();
+        let mut lost = 0;
+
+        while let Some(frame) = jitter.pop() {
+            let data = match &frame {
+                JitterFrame::Audio(data) => Some(data.as_slice()),
+                JitterFrame::Lost => {
+                    lost += 1;
+                    None
+                }
+            };
+
+            decoded.extend_from_slice(decoder.decode(data).unwrap());
+        }
+
+        assert_eq!(lost, 1);
+        assert_eq!(decoded.len(), audio.len());
+        assert!(decoded.iter().any(|s| s.abs() > 0.1));
+    }
+}
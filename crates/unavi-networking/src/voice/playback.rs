@@ -0,0 +1,93 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{
+    audio::{Decodable, Source},
+    prelude::*,
+};
+
+use super::codec::{FRAME_SAMPLES, SAMPLE_RATE};
+
+/// Decoded samples waiting to be played.
+pub type PlaybackSamples = Arc<Mutex<VecDeque<f32>>>;
+
+/// Endless audio source, playing samples as they are decoded.
+#[derive(Asset, TypePath)]
+pub struct VoiceStream {
+    pub samples: PlaybackSamples,
+}
+
+impl Decodable for VoiceStream {
+    type DecoderItem = f32;
+    type Decoder = VoiceSource;
+
+    fn decoder(&self) -> Self::Decoder {
+        VoiceSource {
+            buffer: VecDeque::with_capacity(FRAME_SAMPLES),
+            samples: self.samples.clone(),
+        }
+    }
+}
+
+pub struct VoiceSource {
+    /// Samples taken from the shared queue, to avoid locking for every sample.
+    buffer: VecDeque<f32>,
+    samples: PlaybackSamples,
+}
+
+impl Iterator for VoiceSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            let mut samples = self.samples.lock().unwrap();
+            let len = samples.len().min(FRAME_SAMPLES / 4);
+            self.buffer.extend(samples.drain(..len));
+        }
+
+        // Silence while waiting for more audio.
+        Some(self.buffer.pop_front().unwrap_or_default())
+    }
+}
+
+impl Source for VoiceSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_source() {
+        let samples = PlaybackSamples::default();
+        let stream = VoiceStream {
+            samples: samples.clone(),
+        };
+        let mut source = stream.decoder();
+
+        assert_eq!(source.next(), Some(0.0));
+
+        samples.lock().unwrap().extend([0.5, 0.25]);
+        assert_eq!(source.next(), Some(0.5));
+        assert_eq!(source.next(), Some(0.25));
+        assert_eq!(source.next(), Some(0.0));
+    }
+}
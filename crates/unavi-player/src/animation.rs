@@ -0,0 +1,29 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use unavi_avatar::animation::AvatarAnimationState;
+
+use crate::{menu::PlayerMenuOpen, Player};
+
+/// Downward speed above which the player is falling, in meters per second.
+/// Jumps stay below this, so they do not play the falling animation.
+const FALLING_SPEED: f32 = 5.0;
+
+/// Sets the animation state of the player's avatar, which is also published to other players.
+pub(crate) fn update_animation_state(
+    mut avatars: Query<&mut AvatarAnimationState>,
+    players: Query<(&PlayerMenuOpen, &LinearVelocity, &Children), With<Player>>,
+) {
+    for (open, velocity, children) in players.iter() {
+        for child in children.iter() {
+            let Ok(mut state) = avatars.get_mut(*child) else {
+                continue;
+            };
+
+            state.set_if_neq(AvatarAnimationState {
+                menu_open: **open,
+                falling: velocity.y < -FALLING_SPEED,
+                ..*state
+            });
+        }
+    }
+}
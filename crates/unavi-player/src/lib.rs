@@ -15,6 +15,7 @@ use unavi_avatar::{
 };
 use unavi_constants::layers::LOCAL_PLAYER_LAYER;
 
+mod animation;
 mod controls;
 mod input;
 mod look;
@@ -37,9 +38,9 @@ impl Plugin for PlayerPlugin {
         .add_systems(
             Update,
             (
+                animation::update_animation_state,
                 input::handle_raycast_input,
                 look::grab_mouse,
-                menu::play_menu_animation,
                 setup_first_person,
                 (controls::void_teleport, input::read_keyboard_input).before(controls::move_player),
                 (
@@ -75,8 +76,8 @@ impl Default for Player {
 #[derive(Component)]
 pub struct PlayerCamera;
 
-const PLAYER_HEIGHT: f32 = 1.8;
-const PLAYER_WIDTH: f32 = 0.5;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const PLAYER_WIDTH: f32 = 0.5;
 const SPAWN: Vec3 = Vec3::new(0.0, PLAYER_HEIGHT * 2.0, 0.0);
 
 fn spawn_player(asset_server: Res<AssetServer>, mut commands: Commands) {
@@ -106,6 +107,7 @@ fn spawn_player(asset_server: Res<AssetServer>, mut commands: Commands) {
             AvatarBundle {
                 animations,
                 fallback: FallbackAvatar,
+                state: default(),
                 velocity: AverageVelocity {
                     target: Some(body),
                     ..default()
@@ -138,6 +140,8 @@ fn spawn_player(asset_server: Res<AssetServer>, mut commands: Commands) {
         .spawn((
             Camera3dBundle::default(),
             PlayerCamera,
+            // Ears are roughly 20cm apart.
+            SpatialListener::new(0.2),
             RenderLayers::layer(0).union(&RENDER_LAYERS[&FirstPersonFlag::FirstPersonOnly]),
         ))
         .id();
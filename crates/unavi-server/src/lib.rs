@@ -1,6 +1,9 @@
 use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
     sync::{Arc, LazyLock},
+    time::Duration,
 };
 
 use anyhow::Result;
@@ -11,6 +14,9 @@ use dwn::{
     DWN,
 };
 use tracing::{debug, info_span, Instrument};
+use xwt_netsim::Conditions;
+
+mod metrics;
 
 pub static STORAGE_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
     let dirs =
@@ -23,6 +29,11 @@ pub static STORAGE_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
 #[derive(Parser, Debug)]
 #[command(version, about)]
 pub struct Args {
+    /// Address to listen on.
+    /// Use `0.0.0.0` or `::` to accept connections from other machines.
+    #[arg(long, default_value = "127.0.0.1")]
+    pub bind: IpAddr,
+
     /// Enables debug logging.
     #[arg(long)]
     pub debug: bool,
@@ -50,6 +61,29 @@ pub enum Command {
         #[arg(short, long, default_value = "localhost:<port>")]
         domain: String,
 
+        /// Address to serve the admin API on.
+        /// The API is plain HTTP, so only expose it through a TLS proxy.
+        #[arg(long, default_value = "127.0.0.1")]
+        admin_bind: IpAddr,
+
+        /// Port to serve the admin API on, for moderation.
+        #[arg(long, requires = "admin_token")]
+        admin_port: Option<u16>,
+
+        /// Bearer token required by the admin API.
+        #[arg(long, env = "UNAVI_ADMIN_TOKEN")]
+        admin_token: Option<String>,
+
+        /// PEM encoded certificate chain to serve.
+        /// Reloaded when the file changes.
+        /// A self-signed certificate is generated if not set.
+        #[arg(long, requires = "key")]
+        cert: Option<PathBuf>,
+
+        /// PEM encoded private key for `--cert`.
+        #[arg(long, requires = "cert")]
+        key: Option<PathBuf>,
+
         /// Remote DWN to connect to.
         #[arg(long, default_value = "http://localhost:3000")]
         remote_dwn: String,
@@ -57,10 +91,43 @@ pub enum Command {
         #[arg(short, long, default_value = "3001")]
         port: u16,
 
+        /// What to do with invalid or excessive datagrams from clients.
+        #[arg(long, default_value = "drop")]
+        invalid_datagrams: DatagramPolicy,
+
+        /// Maximum number of players per instance.
+        #[arg(long)]
+        max_players: Option<usize>,
+
+        /// Port to serve Prometheus metrics on, at `/metrics`.
+        /// Metrics are not served if not set.
+        #[arg(long)]
+        metrics_port: Option<u16>,
+
         /// Maximum number of threads to use for connection handling.
         /// Defaults to available parallelism.
         #[arg(short, long)]
         threads: Option<usize>,
+
+        /// Simulated one-way latency, in milliseconds.
+        #[arg(long, default_value_t, value_name = "MS")]
+        sim_latency: u64,
+
+        /// Simulated random variation in latency, in milliseconds.
+        #[arg(long, default_value_t, value_name = "MS")]
+        sim_jitter: u64,
+
+        /// Simulated datagram loss, as a percentage.
+        #[arg(long, default_value_t, value_name = "PERCENT")]
+        sim_loss: f32,
+
+        /// Simulated datagram reordering, as a percentage.
+        #[arg(long, default_value_t, value_name = "PERCENT")]
+        sim_reorder: f32,
+
+        /// Simulated bandwidth cap per connection, in kilobytes per second.
+        #[arg(long, value_name = "KB/S")]
+        sim_bandwidth: Option<u32>,
     },
 }
 
@@ -70,6 +137,26 @@ pub enum Storage {
     Memory,
 }
 
+#[derive(ValueEnum, Clone, Debug)]
+pub enum DatagramPolicy {
+    /// Discard the datagram.
+    Drop,
+    /// Correct the datagram where possible, discarding it otherwise.
+    Clamp,
+    /// Close the connection.
+    Disconnect,
+}
+
+impl From<DatagramPolicy> for unavi_world_server::ViolationPolicy {
+    fn from(value: DatagramPolicy) -> Self {
+        match value {
+            DatagramPolicy::Drop => Self::Drop,
+            DatagramPolicy::Clamp => Self::Clamp,
+            DatagramPolicy::Disconnect => Self::Disconnect,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct StartOptions {
     pub enable_remote_sync: bool,
@@ -96,25 +183,44 @@ pub async fn start(
             let mut opts = opts.clone();
             opts.enable_remote_sync = false;
 
+            let bind = args.bind.to_string();
+
             tokio::select! {
-                res = start(Args::parse_from(["unavi-server", "social"]), opts.clone(), dwn.clone()) => {
+                res = start(Args::parse_from(["unavi-server", "--bind", &bind, "social"]), opts.clone(), dwn.clone()) => {
                     res?;
                 }
-                res = start(Args::parse_from(["unavi-server", "world"]), opts, dwn) => {
+                res = start(Args::parse_from(["unavi-server", "--bind", &bind, "world"]), opts, dwn) => {
                     res?;
                 }
             }
         }
         Command::Social { port } => {
-            unavi_social_server::start(unavi_social_server::ServerOptions { dwn, port })
-                .instrument(info_span!("Social"))
-                .await?;
+            unavi_social_server::start(unavi_social_server::ServerOptions {
+                bind: args.bind,
+                dwn,
+                port,
+            })
+            .instrument(info_span!("Social"))
+            .await?;
         }
         Command::World {
+            admin_bind,
+            admin_port,
+            admin_token,
+            cert,
             domain,
+            invalid_datagrams,
+            key,
+            max_players,
+            metrics_port,
             port,
             remote_dwn,
             threads,
+            sim_latency,
+            sim_jitter,
+            sim_loss,
+            sim_reorder,
+            sim_bandwidth,
         } => {
             let domain = if domain == "localhost:<port>" {
                 format!("localhost:{}", port)
@@ -127,16 +233,72 @@ pub async fn start(
                 Storage::Memory => unavi_world_host::Storage::Memory,
             };
 
+            let (send_players, recv_players) = tokio::sync::watch::channel(HashMap::default());
+            let (send_hashes, recv_hashes) = tokio::sync::watch::channel(Vec::new());
+
+            let tls = match (cert, key) {
+                (Some(cert), Some(key)) => Some(unavi_world_server::TlsOptions { cert, key }),
+                _ => None,
+            };
+
+            let metrics_sources = metrics::MetricsSources {
+                connection_gauges: Arc::default(),
+                datagram_stats: Arc::default(),
+                instance_players: recv_players.clone(),
+                server: Arc::default(),
+            };
+
+            let admin = match (admin_port, admin_token) {
+                (Some(port), Some(token)) => Some(unavi_world_server::AdminOptions {
+                    bind: admin_bind,
+                    port,
+                    token,
+                }),
+                _ => None,
+            };
+
+            let bans = Arc::new(tokio::sync::watch::Sender::new(Default::default()));
+            let mutes = Arc::new(tokio::sync::watch::Sender::new(Default::default()));
+
             let server_options = unavi_world_server::ServerOptions {
+                admin,
+                bans: bans.clone(),
+                bind: args.bind,
+                certificate_hashes: Some(Arc::new(send_hashes)),
+                connection_gauges: metrics_sources.connection_gauges.clone(),
+                datagram_stats: metrics_sources.datagram_stats.clone(),
                 domain: domain.clone(),
+                delta_snapshots: true,
                 dwn: dwn.clone(),
+                instance_players: Some(Arc::new(send_players)),
+                max_players,
+                metrics: metrics_sources.server.clone(),
+                mutes: mutes.clone(),
+                network_conditions: Conditions {
+                    latency: Duration::from_millis(sim_latency),
+                    jitter: Duration::from_millis(sim_jitter),
+                    loss: sim_loss / 100.0,
+                    reorder: sim_reorder / 100.0,
+                    bandwidth: sim_bandwidth.map(|kb| kb.saturating_mul(1000)),
+                },
                 port,
                 threads,
+                tls,
+                validation: unavi_world_server::ValidationOptions {
+                    policy: invalid_datagrams.into(),
+                    ..Default::default()
+                },
             };
 
             let host_options = unavi_world_host::ServerOptions {
+                bans: Some(bans),
+                bind: args.bind,
+                certificate_hashes: Some(recv_hashes),
                 domain,
                 dwn,
+                instance_players: Some(recv_players),
+                max_players,
+                mutes: Some(mutes),
                 port,
                 remote_dwn,
                 remote_sync: opts.enable_remote_sync,
@@ -145,6 +307,17 @@ pub async fn start(
 
             let span = info_span!("World");
 
+            let serve_metrics = async {
+                match metrics_port {
+                    Some(port) => {
+                        metrics::serve(SocketAddr::new(args.bind, port), metrics_sources)
+                            .instrument(info_span!(parent: &span, "Metrics"))
+                            .await
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+
             tokio::select! {
                 res = unavi_world_server::start(server_options).instrument(info_span!(parent: &span, "Server")) => {
                     res?;
@@ -152,6 +325,9 @@ pub async fn start(
                 res = unavi_world_host::start(host_options).instrument(info_span!(parent: &span, "Host")) => {
                     res?;
                 }
+                res = serve_metrics => {
+                    res?;
+                }
             };
         }
     };
@@ -0,0 +1,266 @@
+//! Prometheus metrics endpoint for the world server.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
+
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use tokio::sync::watch;
+use tracing::info;
+use unavi_world_server::{ConnectionGauges, DatagramStats, Histogram, ServerMetrics};
+
+#[derive(Clone)]
+pub struct MetricsSources {
+    pub connection_gauges: Arc<ConnectionGauges>,
+    pub datagram_stats: Arc<DatagramStats>,
+    pub instance_players: watch::Receiver<HashMap<String, usize>>,
+    pub server: Arc<ServerMetrics>,
+}
+
+/// Serves metrics in the Prometheus text format at `/metrics`.
+pub async fn serve(addr: SocketAddr, sources: MetricsSources) -> std::io::Result<()> {
+    let router = Router::new().route(
+        "/metrics",
+        get(|| async move {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                render(&sources),
+            )
+                .into_response()
+        }),
+    );
+
+    info!("Serving metrics on {}", addr);
+    axum_server::bind(addr)
+        .serve(router.into_make_service())
+        .await
+}
+
+fn render(sources: &MetricsSources) -> String {
+    let mut out = String::new();
+
+    let threads = sources.connection_gauges.threads();
+    header(
+        &mut out,
+        "unavi_world_sessions",
+        "gauge",
+        "Connected sessions.",
+    );
+    writeln!(
+        out,
+        "unavi_world_sessions {}",
+        threads.iter().sum::<usize>()
+    )
+    .unwrap();
+
+    header(
+        &mut out,
+        "unavi_world_thread_sessions",
+        "gauge",
+        "Connected sessions on each connection thread.",
+    );
+    for (thread, count) in threads.iter().enumerate() {
+        writeln!(
+            out,
+            "unavi_world_thread_sessions{{thread=\"{}\"}} {}",
+            thread, count
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "unavi_world_instance_players",
+        "gauge",
+        "Players in each instance.",
+    );
+    let mut instances = sources
+        .instance_players
+        .borrow()
+        .iter()
+        .map(|(id, count)| (id.clone(), *count))
+        .collect::<Vec<_>>();
+    instances.sort();
+    for (id, count) in instances {
+        writeln!(
+            out,
+            "unavi_world_instance_players{{instance=\"{}\"}} {}",
+            escape(&id),
+            count
+        )
+        .unwrap();
+    }
+
+    let server = &sources.server;
+    counter(
+        &mut out,
+        "unavi_world_datagrams_received_total",
+        "Datagrams received from clients.",
+        server.datagrams_received.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "unavi_world_datagrams_sent_total",
+        "Datagrams sent to clients.",
+        server.datagrams_sent.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "unavi_world_datagram_bytes_received_total",
+        "Datagram bytes received from clients.",
+        server.bytes_received.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "unavi_world_datagram_bytes_sent_total",
+        "Datagram bytes sent to clients.",
+        server.bytes_sent.load(Ordering::Relaxed),
+    );
+
+    header(
+        &mut out,
+        "unavi_world_datagrams_validated_total",
+        "counter",
+        "Outcomes of validating received datagrams.",
+    );
+    let stats = &sources.datagram_stats;
+    for (outcome, value) in [
+        ("accepted", &stats.accepted),
+        ("clamped", &stats.clamped),
+        ("dropped", &stats.dropped),
+        ("rate_limited", &stats.rate_limited),
+        ("disconnected", &stats.disconnected),
+    ] {
+        writeln!(
+            out,
+            "unavi_world_datagrams_validated_total{{outcome=\"{}\"}} {}",
+            outcome,
+            value.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+
+    let rpc = server.rpc.counts();
+    header(
+        &mut out,
+        "unavi_world_rpc_calls_total",
+        "counter",
+        "RPC calls, by method.",
+    );
+    for (method, counts) in &rpc {
+        writeln!(
+            out,
+            "unavi_world_rpc_calls_total{{method=\"{}\"}} {}",
+            method, counts.calls
+        )
+        .unwrap();
+    }
+    header(
+        &mut out,
+        "unavi_world_rpc_errors_total",
+        "counter",
+        "Failed RPC calls, by method.",
+    );
+    for (method, counts) in &rpc {
+        writeln!(
+            out,
+            "unavi_world_rpc_errors_total{{method=\"{}\"}} {}",
+            method, counts.errors
+        )
+        .unwrap();
+    }
+
+    histogram(
+        &mut out,
+        "unavi_world_tick_duration_seconds",
+        "Time taken to simulate one instance tick.",
+        &server.tick_duration,
+    );
+    histogram(
+        &mut out,
+        "unavi_world_dwn_latency_seconds",
+        "Time taken to read records from the DWN.",
+        &server.dwn_latency,
+    );
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    header(out, name, "histogram", help);
+
+    // Read the count first, so buckets observed meanwhile never exceed it.
+    let count = histogram.count();
+
+    for (bound, value) in histogram.buckets() {
+        writeln!(
+            out,
+            "{}_bucket{{le=\"{}\"}} {}",
+            name,
+            bound,
+            value.min(count)
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+    writeln!(out, "{}_sum {}", name, histogram.sum()).unwrap();
+    writeln!(out, "{}_count {}", name, count).unwrap();
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let (_send_players, instance_players) =
+            watch::channel(HashMap::from([("abc".to_string(), 3)]));
+
+        let sources = MetricsSources {
+            connection_gauges: Arc::default(),
+            datagram_stats: Arc::default(),
+            instance_players,
+            server: Arc::default(),
+        };
+
+        sources.server.datagram_sent(100);
+        sources.server.rpc.call("join");
+        sources
+            .server
+            .tick_duration
+            .observe(Duration::from_millis(2));
+
+        let out = render(&sources);
+
+        assert!(out.contains("unavi_world_instance_players{instance=\"abc\"} 3\n"));
+        assert!(out.contains("unavi_world_datagram_bytes_sent_total 100\n"));
+        assert!(out.contains("unavi_world_rpc_calls_total{method=\"join\"} 1\n"));
+        assert!(out.contains("unavi_world_rpc_errors_total{method=\"join\"} 0\n"));
+        assert!(out.contains("unavi_world_tick_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(out.contains("unavi_world_tick_duration_seconds_bucket{le=\"0.0025\"} 1\n"));
+        assert!(out.contains("unavi_world_tick_duration_seconds_count 1\n"));
+    }
+}
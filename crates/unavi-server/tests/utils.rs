@@ -1,10 +1,16 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Result;
 use dwn::{store::SurrealStore, DWN};
 use surrealdb::{engine::local::Mem, Surreal};
 use tokio::task::JoinHandle;
-use unavi_server::{Args, Command, StartOptions, Storage};
+use unavi_server::{Args, Command, DatagramPolicy, StartOptions, Storage};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
 
 pub struct TestServer {
     pub domain_social: String,
@@ -21,19 +27,34 @@ pub async fn setup_test_server() -> TestServer {
     let domain_world = local_domain(port_world);
 
     let args_social = Args {
+        bind: LOCALHOST,
         debug: true,
         storage: Storage::Memory,
         command: Command::Social { port: port_social },
     };
 
     let args_world = Args {
+        bind: LOCALHOST,
         debug: true,
         storage: Storage::Memory,
         command: Command::World {
+            admin_bind: LOCALHOST,
+            admin_port: None,
+            admin_token: None,
+            cert: None,
             domain: domain_world.clone(),
+            invalid_datagrams: DatagramPolicy::Drop,
+            key: None,
+            max_players: None,
+            metrics_port: None,
             port: port_world,
             remote_dwn: format!("http://{}", domain_social),
             threads: Some(1),
+            sim_latency: 0,
+            sim_jitter: 0,
+            sim_loss: 0.0,
+            sim_reorder: 0.0,
+            sim_bandwidth: None,
         },
     };
 
@@ -2,7 +2,7 @@
 //! Hosts a DWN, provides login APIs, and more.
 
 use std::{
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
     sync::Arc,
 };
 
@@ -14,6 +14,8 @@ use tracing::info;
 
 #[derive(Clone)]
 pub struct ServerOptions<D: DataStore, M: MessageStore> {
+    /// Address to listen on.
+    pub bind: IpAddr,
     pub dwn: Arc<DWN<D, M>>,
     pub port: u16,
 }
@@ -23,7 +25,7 @@ pub async fn start(
 ) -> std::io::Result<()> {
     let opts = Arc::new(opts);
 
-    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), opts.port);
+    let addr = SocketAddr::new(opts.bind, opts.port);
     let router = dwn_server::router(opts.dwn.clone());
 
     info!("Listening on {}", addr);
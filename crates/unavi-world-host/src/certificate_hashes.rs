@@ -0,0 +1,156 @@
+use dwn::{
+    actor::{Actor, ProcessMessageError},
+    message::descriptor::{records::RecordsFilter, Descriptor},
+    store::{DataStore, MessageStore},
+};
+use tokio::sync::watch;
+use tracing::{error, info};
+use wired_social::protocols::world_host::{
+    world_host_protocol_url, CertificateHash, CERTIFICATE_HASHES_PATH, WORLD_HOST_PROTOCOL_VERSION,
+};
+
+/// Publishes the world server's certificate hashes, next to the connect URL.
+/// Runs until the sender is dropped.
+pub async fn publish_certificate_hashes(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    mut certificate_hashes: watch::Receiver<Vec<CertificateHash>>,
+) {
+    while certificate_hashes.changed().await.is_ok() {
+        let hashes = certificate_hashes.borrow_and_update().clone();
+
+        match write_certificate_hashes(actor, &hashes).await {
+            Ok(()) => info!("Published {} certificate hashes.", hashes.len()),
+            Err(e) => error!("Failed to publish certificate hashes: {}", e),
+        }
+    }
+}
+
+/// Creates or updates the `certificate-hashes` record.
+async fn write_certificate_hashes(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    hashes: &[CertificateHash],
+) -> Result<(), ProcessMessageError> {
+    let data = serde_json::to_vec(hashes).unwrap();
+
+    let records = actor
+        .query_records(RecordsFilter {
+            data_format: Some("application/json".to_string()),
+            protocol: Some(world_host_protocol_url()),
+            protocol_version: Some(WORLD_HOST_PROTOCOL_VERSION),
+            ..Default::default()
+        })
+        .process()
+        .await?;
+
+    let found = records.entries.iter().find(|message| {
+        if let Descriptor::RecordsWrite(descriptor) = &message.descriptor {
+            descriptor.protocol_path.as_deref() == Some(CERTIFICATE_HASHES_PATH)
+        } else {
+            false
+        }
+    });
+
+    match found {
+        Some(found) => {
+            // Updates do not inherit the protocol, so it is set again
+            // for the record to still be found by its path.
+            actor
+                .update_record(found.record_id.clone(), found.entry_id().unwrap())
+                .protocol(
+                    world_host_protocol_url(),
+                    WORLD_HOST_PROTOCOL_VERSION,
+                    CERTIFICATE_HASHES_PATH.to_string(),
+                )
+                .data(data)
+                .data_format("application/json".to_string())
+                .published(true)
+                .process()
+                .await?;
+        }
+        None => {
+            actor
+                .create_record()
+                .protocol(
+                    world_host_protocol_url(),
+                    WORLD_HOST_PROTOCOL_VERSION,
+                    CERTIFICATE_HASHES_PATH.to_string(),
+                )
+                .data(data)
+                .data_format("application/json".to_string())
+                .published(true)
+                .process()
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use dwn::message::Data;
+
+    use crate::world_host::tests::world_host_actor;
+
+    use super::*;
+
+    async fn read_certificate_hashes(
+        actor: &Actor<impl DataStore, impl MessageStore>,
+    ) -> Vec<CertificateHash> {
+        let records = actor
+            .query_records(RecordsFilter {
+                protocol: Some(world_host_protocol_url()),
+                ..Default::default()
+            })
+            .process()
+            .await
+            .unwrap();
+
+        let found = records
+            .entries
+            .iter()
+            .filter(|message| {
+                if let Descriptor::RecordsWrite(descriptor) = &message.descriptor {
+                    descriptor.protocol_path.as_deref() == Some(CERTIFICATE_HASHES_PATH)
+                } else {
+                    false
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found.len(), 1);
+
+        let read = actor
+            .read_record(found[0].record_id.clone())
+            .process()
+            .await
+            .unwrap();
+
+        let Some(Data::Base64(encoded)) = read.record.data else {
+            panic!("No data");
+        };
+
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(encoded).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_write_certificate_hashes() {
+        let actor = world_host_actor().await;
+
+        let hashes = vec![CertificateHash::sha256(&[1; 32])];
+        write_certificate_hashes(&actor, &hashes).await.unwrap();
+        assert_eq!(read_certificate_hashes(&actor).await, hashes);
+
+        // Rotated certificates update the same record.
+        let hashes = vec![
+            CertificateHash::sha256(&[1; 32]),
+            CertificateHash::sha256(&[2; 32]),
+        ];
+        write_certificate_hashes(&actor, &hashes).await.unwrap();
+        assert_eq!(read_certificate_hashes(&actor).await, hashes);
+
+        let hashes = vec![CertificateHash::sha256(&[2; 32])];
+        write_certificate_hashes(&actor, &hashes).await.unwrap();
+        assert_eq!(read_certificate_hashes(&actor).await, hashes);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, time::Duration};
+
+use dwn::{
+    actor::{Actor, ProcessMessageError},
+    message::{
+        descriptor::{records::RecordsFilter, Descriptor},
+        Message,
+    },
+    store::{DataStore, MessageStore},
+};
+use tokio::sync::watch;
+use tracing::{debug, error};
+use wired_social::{
+    protocols::world_host::{world_host_protocol_url, WORLD_HOST_PROTOCOL_VERSION},
+    schemas::instance_info::{instance_info_schema_url, InstanceInfo},
+};
+
+const INSTANCE_INFO_PATH: &str = "instance/instance-info";
+
+/// Minimum time between publishing updates, to avoid flooding the DWN as players come and go.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Publishes an `instance-info` record for each live instance, with its current player count.
+/// Runs until the sender is dropped.
+pub async fn publish_instance_info(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    connect_url: &str,
+    max_players: Option<usize>,
+    mut instance_players: watch::Receiver<HashMap<String, usize>>,
+) {
+    // Last published player count for each instance.
+    let mut published = HashMap::<String, usize>::default();
+
+    while instance_players.changed().await.is_ok() {
+        let mut current = instance_players.borrow_and_update().clone();
+
+        // Instances that closed are published as empty.
+        for id in published.keys() {
+            current.entry(id.clone()).or_insert(0);
+        }
+
+        for (id, num_players) in current {
+            if published.get(&id) == Some(&num_players) {
+                continue;
+            }
+
+            let info = InstanceInfo {
+                url: connect_url.to_string(),
+                num_players: Some(num_players),
+                max_players,
+            };
+
+            if let Err(e) = write_instance_info(actor, &id, &info).await {
+                error!("Failed to publish instance info for {}: {}", id, e);
+                continue;
+            }
+
+            debug!(
+                "Published instance info for {}: {} players",
+                id, num_players
+            );
+
+            if num_players == 0 {
+                published.remove(&id);
+            } else {
+                published.insert(id, num_players);
+            }
+        }
+
+        tokio::time::sleep(PUBLISH_INTERVAL).await;
+    }
+}
+
+/// Creates or updates the `instance-info` record for an instance.
+async fn write_instance_info(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    instance_id: &str,
+    info: &InstanceInfo,
+) -> Result<(), ProcessMessageError> {
+    let data = serde_json::to_vec(info).unwrap();
+
+    let records = actor
+        .query_records(RecordsFilter {
+            data_format: Some("application/json".to_string()),
+            protocol: Some(world_host_protocol_url()),
+            protocol_version: Some(WORLD_HOST_PROTOCOL_VERSION),
+            ..Default::default()
+        })
+        .process()
+        .await?;
+
+    let found = records
+        .entries
+        .iter()
+        .find(|message| is_instance_info(message, instance_id));
+
+    match found {
+        Some(found) => {
+            // Updates do not inherit the protocol, so it is set again
+            // for the record to still be found by its path.
+            actor
+                .update_record(found.record_id.clone(), found.entry_id().unwrap())
+                .protocol(
+                    world_host_protocol_url(),
+                    WORLD_HOST_PROTOCOL_VERSION,
+                    INSTANCE_INFO_PATH.to_string(),
+                )
+                .parent_context_id(instance_id.to_string())
+                .schema(instance_info_schema_url())
+                .data(data)
+                .data_format("application/json".to_string())
+                .published(true)
+                .process()
+                .await?;
+        }
+        None => {
+            actor
+                .create_record()
+                .protocol(
+                    world_host_protocol_url(),
+                    WORLD_HOST_PROTOCOL_VERSION,
+                    INSTANCE_INFO_PATH.to_string(),
+                )
+                .parent_context_id(instance_id.to_string())
+                .schema(instance_info_schema_url())
+                .data(data)
+                .data_format("application/json".to_string())
+                .published(true)
+                .process()
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the message is the `instance-info` record of an instance.
+fn is_instance_info(message: &Message, instance_id: &str) -> bool {
+    let Descriptor::RecordsWrite(descriptor) = &message.descriptor else {
+        return false;
+    };
+
+    // Nested under the instance, so its context id is `<instance_id>/<record_id>`.
+    descriptor.protocol_path.as_deref() == Some(INSTANCE_INFO_PATH)
+        && message.context_id.as_deref() == Some(&format!("{}/{}", instance_id, message.record_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use dwn::message::Data;
+
+    use crate::world_host::tests::world_host_actor;
+
+    use super::*;
+
+    async fn read_instance_info(
+        actor: &Actor<impl DataStore, impl MessageStore>,
+        instance_id: &str,
+    ) -> InstanceInfo {
+        let records = actor
+            .query_records(RecordsFilter {
+                protocol: Some(world_host_protocol_url()),
+                ..Default::default()
+            })
+            .process()
+            .await
+            .unwrap();
+
+        let found = records
+            .entries
+            .iter()
+            .filter(|message| is_instance_info(message, instance_id))
+            .collect::<Vec<_>>();
+        assert_eq!(found.len(), 1);
+
+        let read = actor
+            .read_record(found[0].record_id.clone())
+            .process()
+            .await
+            .unwrap();
+
+        let Some(Data::Base64(encoded)) = read.record.data else {
+            panic!("No data");
+        };
+
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(encoded).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "Fetches the instance-info schema over the network"]
+    async fn test_write_instance_info() {
+        let actor = world_host_actor().await;
+
+        let instance = actor
+            .create_record()
+            .protocol(
+                world_host_protocol_url(),
+                WORLD_HOST_PROTOCOL_VERSION,
+                "instance".to_string(),
+            )
+            .data(b"{}".to_vec())
+            .data_format("application/json".to_string())
+            .published(true)
+            .process()
+            .await
+            .unwrap();
+
+        let mut info = InstanceInfo {
+            url: "https://127.0.0.1:3000".to_string(),
+            num_players: Some(1),
+            max_players: Some(4),
+        };
+        write_instance_info(&actor, &instance.record_id, &info)
+            .await
+            .unwrap();
+        let read = read_instance_info(&actor, &instance.record_id).await;
+        assert_eq!(read.num_players, Some(1));
+        assert_eq!(read.max_players, Some(4));
+
+        // Updates the same record.
+        info.num_players = Some(2);
+        write_instance_info(&actor, &instance.record_id, &info)
+            .await
+            .unwrap();
+        let read = read_instance_info(&actor, &instance.record_id).await;
+        assert_eq!(read.num_players, Some(2));
+    }
+}
@@ -3,7 +3,8 @@
 //! world server.
 
 use std::{
-    net::{Ipv4Addr, SocketAddr},
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
     sync::Arc,
     time::Duration,
@@ -16,16 +17,36 @@ use dwn::{
     store::{DataStore, MessageStore},
     DWN,
 };
-
+use tokio::sync::watch;
 use tracing::{error, info};
+use wired_social::protocols::world_host::{Bans, CertificateHash, Mutes, BANS_PATH, MUTES_PATH};
 
+mod certificate_hashes;
 mod did;
+mod instance_info;
+mod moderation;
 mod world_host;
 
 #[derive(Clone)]
 pub struct ServerOptions<D: DataStore, M: MessageStore> {
+    /// DIDs banned from joining instances, shared with the world server.
+    /// Loaded from the DWN on start, and stored whenever it changes.
+    pub bans: Option<Arc<watch::Sender<Bans>>>,
+    /// Address to listen on.
+    pub bind: IpAddr,
+    /// Hashes of the world server's self-signed certificates.
+    /// Published to the DWN as a `certificate-hashes` record.
+    pub certificate_hashes: Option<watch::Receiver<Vec<CertificateHash>>>,
     pub domain: String,
     pub dwn: Arc<DWN<D, M>>,
+    /// Number of players in each instance, keyed by instance record id.
+    /// Published to the DWN as `instance-info` records.
+    pub instance_players: Option<watch::Receiver<HashMap<String, usize>>>,
+    /// Maximum number of players per instance, published alongside player counts.
+    pub max_players: Option<usize>,
+    /// DIDs muted in instances, shared with the world server.
+    /// Loaded from the DWN on start, and stored whenever it changes.
+    pub mutes: Option<Arc<watch::Sender<Mutes>>>,
     pub port: u16,
     pub remote_dwn: String,
     pub remote_sync: bool,
@@ -57,7 +78,7 @@ pub async fn start(opts: ServerOptions<impl DataStore, impl MessageStore>) -> st
         get(|| async move { Json(document.clone()) }),
     );
 
-    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), opts.port);
+    let addr = SocketAddr::new(opts.bind, opts.port);
     info!("Starting world host on {}", addr);
     let server = tokio::spawn(axum_server::bind(addr).serve(router.into_make_service()));
 
@@ -84,7 +105,44 @@ pub async fn start(opts: ServerOptions<impl DataStore, impl MessageStore>) -> st
         sync_retry(&mut actor).await;
     }
 
-    server.await??;
+    let publish_instance_info = async {
+        if let Some(instance_players) = opts.instance_players {
+            instance_info::publish_instance_info(
+                &actor,
+                &connect_url,
+                opts.max_players,
+                instance_players,
+            )
+            .await;
+        }
+    };
+
+    let publish_certificate_hashes = async {
+        if let Some(hashes) = opts.certificate_hashes {
+            certificate_hashes::publish_certificate_hashes(&actor, hashes).await;
+        }
+    };
+
+    let sync_bans = async {
+        if let Some(bans) = &opts.bans {
+            moderation::sync_did_list(&actor, BANS_PATH, bans).await;
+        }
+    };
+
+    let sync_mutes = async {
+        if let Some(mutes) = &opts.mutes {
+            moderation::sync_did_list(&actor, MUTES_PATH, mutes).await;
+        }
+    };
+
+    let (res, _, _, _, _) = tokio::join!(
+        server,
+        publish_instance_info,
+        publish_certificate_hashes,
+        sync_bans,
+        sync_mutes
+    );
+    res??;
 
     info!("Finished.");
     Ok(())
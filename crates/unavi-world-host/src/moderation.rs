@@ -0,0 +1,185 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dwn::{
+    actor::{Actor, ProcessMessageError},
+    message::{
+        descriptor::{records::RecordsFilter, Descriptor},
+        Data,
+    },
+    store::{DataStore, MessageStore},
+};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+use wired_social::protocols::world_host::{
+    world_host_protocol_url, DidList, WORLD_HOST_PROTOCOL_VERSION,
+};
+
+/// Loads the [DidList] stored at `path` into `list`, then stores any changes made to it.
+/// Used for bans and mutes. Runs until the sender is dropped.
+pub async fn sync_did_list(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    path: &str,
+    list: &watch::Sender<DidList>,
+) {
+    match read_did_list(actor, path).await {
+        Ok(Some(stored)) => {
+            info!(
+                "Loaded {} host {}, and {} for {} instances.",
+                stored.host.len(),
+                path,
+                path,
+                stored.instances.len()
+            );
+            list.send_replace(stored);
+        }
+        Ok(None) => {}
+        Err(e) => error!("Failed to load {}: {}", path, e),
+    }
+
+    // Subscribe after loading, so the stored list is not written back.
+    let mut receiver = list.subscribe();
+
+    while receiver.changed().await.is_ok() {
+        let current = receiver.borrow_and_update().clone();
+
+        match write_did_list(actor, path, &current).await {
+            Ok(()) => info!("Stored {}.", path),
+            Err(e) => error!("Failed to store {}: {}", path, e),
+        }
+    }
+}
+
+async fn read_did_list(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    path: &str,
+) -> Result<Option<DidList>, ProcessMessageError> {
+    let Some((record_id, _)) = find_record(actor, path).await? else {
+        return Ok(None);
+    };
+
+    let read = actor.read_record(record_id).process().await?;
+
+    let data = match read.record.data {
+        Some(Data::Base64(encoded)) => URL_SAFE_NO_PAD.decode(encoded).ok(),
+        _ => None,
+    };
+
+    let list = data.and_then(|data| serde_json::from_slice(&data).ok());
+
+    if list.is_none() {
+        warn!("Invalid {} record, ignoring.", path);
+    }
+
+    Ok(list)
+}
+
+/// Creates or updates the record at `path`.
+async fn write_did_list(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    path: &str,
+    list: &DidList,
+) -> Result<(), ProcessMessageError> {
+    let data = serde_json::to_vec(list).unwrap();
+
+    match find_record(actor, path).await? {
+        Some((record_id, entry_id)) => {
+            // Updates do not inherit the protocol, so it is set again
+            // for the record to still be found by its path.
+            actor
+                .update_record(record_id, entry_id)
+                .protocol(
+                    world_host_protocol_url(),
+                    WORLD_HOST_PROTOCOL_VERSION,
+                    path.to_string(),
+                )
+                .data(data)
+                .data_format("application/json".to_string())
+                .process()
+                .await?;
+        }
+        None => {
+            actor
+                .create_record()
+                .protocol(
+                    world_host_protocol_url(),
+                    WORLD_HOST_PROTOCOL_VERSION,
+                    path.to_string(),
+                )
+                .data(data)
+                .data_format("application/json".to_string())
+                .process()
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the record id and latest entry id of the record at `path`.
+async fn find_record(
+    actor: &Actor<impl DataStore, impl MessageStore>,
+    path: &str,
+) -> Result<Option<(String, String)>, ProcessMessageError> {
+    let records = actor
+        .query_records(RecordsFilter {
+            data_format: Some("application/json".to_string()),
+            protocol: Some(world_host_protocol_url()),
+            protocol_version: Some(WORLD_HOST_PROTOCOL_VERSION),
+            ..Default::default()
+        })
+        .process()
+        .await?;
+
+    let found = records.entries.iter().find(|message| {
+        if let Descriptor::RecordsWrite(descriptor) = &message.descriptor {
+            descriptor.protocol_path.as_deref() == Some(path)
+        } else {
+            false
+        }
+    });
+
+    Ok(found.map(|found| (found.record_id.clone(), found.entry_id().unwrap())))
+}
+
+#[cfg(test)]
+mod tests {
+    use wired_social::protocols::world_host::{BANS_PATH, MUTES_PATH};
+
+    use crate::world_host::tests::world_host_actor;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_did_list() {
+        let actor = world_host_actor().await;
+        assert_eq!(read_did_list(&actor, BANS_PATH).await.unwrap(), None);
+
+        let mut bans = DidList::default();
+        bans.host.insert("did:example:alice".to_string());
+        write_did_list(&actor, BANS_PATH, &bans).await.unwrap();
+        assert_eq!(
+            read_did_list(&actor, BANS_PATH).await.unwrap(),
+            Some(bans.clone())
+        );
+
+        // Updates the same record.
+        bans.instances
+            .entry("instance".to_string())
+            .or_default()
+            .insert("did:example:bob".to_string());
+        write_did_list(&actor, BANS_PATH, &bans).await.unwrap();
+        assert_eq!(
+            read_did_list(&actor, BANS_PATH).await.unwrap(),
+            Some(bans.clone())
+        );
+
+        bans.host.clear();
+        write_did_list(&actor, BANS_PATH, &bans).await.unwrap();
+        assert_eq!(
+            read_did_list(&actor, BANS_PATH).await.unwrap(),
+            Some(bans.clone())
+        );
+
+        // Each list has its own record.
+        assert_eq!(read_did_list(&actor, MUTES_PATH).await.unwrap(), None);
+    }
+}
@@ -127,3 +127,27 @@ pub async fn create_world_host(
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use dwn::{store::SurrealStore, DWN};
+    use surrealdb::{
+        engine::local::{Db, Mem},
+        Surreal,
+    };
+
+    use super::*;
+
+    /// Creates an in-memory world host, with the protocol registered.
+    pub async fn world_host_actor() -> Actor<SurrealStore<Db>, SurrealStore<Db>> {
+        let db = Surreal::new::<Mem>(()).await.unwrap();
+        let store = SurrealStore::new(db).await.unwrap();
+        let actor = Actor::new_did_key(Arc::new(DWN::from(store))).unwrap();
+
+        create_world_host(&actor, "https://127.0.0.1:3000").await;
+
+        actor
+    }
+}
@@ -0,0 +1,434 @@
+//! Authenticated HTTP JSON API for moderating the server.
+//!
+//! Requests must include the admin token as `Authorization: Bearer <token>`.
+//! The API is served over plain HTTP, so should only be exposed through a TLS proxy.
+//!
+//! - `GET /instances` - running instances and their players.
+//! - `GET /instances/:id` - players in an instance.
+//! - `POST /instances/:id/close` - remove every player and stop the instance.
+//! - `GET /instances/:id/options` - options the instance is running with, or will start with.
+//! - `PUT /instances/:id/options` - override `{ max_objects?, max_players?, tickrate? }`,
+//!   with unset fields taken from the server defaults.
+//!   Takes effect the next time it starts, so close it to apply the override now.
+//! - `DELETE /instances/:id/options` - remove the override.
+//! - `POST /instances/:id/players/:player/kick`
+//! - `GET /bans`
+//! - `POST /bans` - ban `{ did, instance? }`, removing matching players.
+//!   Without `instance`, the DID is banned from every instance.
+//! - `DELETE /bans` - unban `{ did, instance? }`.
+//! - `GET /mutes`
+//! - `POST /mutes` - mute `{ did, instance? }`, so their chat and voice are not relayed.
+//!   Without `instance`, the DID is muted in every instance.
+//! - `DELETE /mutes` - unmute `{ did, instance? }`.
+//!
+//! Bans and mutes are keyed by DID, so they apply across reconnects,
+//! and are stored by the world host.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::sync::{mpsc::UnboundedSender, oneshot, watch};
+use tracing::info;
+use wired_social::protocols::world_host::{Bans, Mutes};
+
+use crate::instance::{
+    registry::InstanceRegistry, InstanceCommand, InstanceOptions, PlayerSummary, TICKRATE,
+};
+
+#[derive(Clone, Debug)]
+pub struct AdminOptions {
+    /// Address to listen on.
+    /// Should be loopback unless behind a TLS proxy, as the token is sent in plain text.
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Bearer token required for every request.
+    pub token: String,
+}
+
+struct AdminState {
+    bans: Arc<watch::Sender<Bans>>,
+    instances: Arc<InstanceRegistry>,
+    mutes: Arc<watch::Sender<Mutes>>,
+    token: String,
+}
+
+#[derive(Serialize)]
+struct InstanceJson {
+    id: String,
+    players: Vec<PlayerJson>,
+}
+
+#[derive(Serialize)]
+struct PlayerJson {
+    id: usize,
+    avatar: Option<String>,
+    did: Option<String>,
+    muted: bool,
+    name: Option<String>,
+}
+
+impl From<PlayerSummary> for PlayerJson {
+    fn from(value: PlayerSummary) -> Self {
+        Self {
+            id: value.id,
+            avatar: value.info.avatar,
+            did: value.info.did,
+            muted: value.muted,
+            name: value.info.name,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct OptionsJson {
+    max_objects: Option<usize>,
+    max_players: Option<usize>,
+    /// Seconds per tick.
+    tickrate: Option<f32>,
+}
+
+impl OptionsJson {
+    /// Options to start an instance with, taking unset fields from the defaults.
+    /// Returns `None` if the options are invalid.
+    fn to_options(&self, defaults: &InstanceOptions) -> Option<InstanceOptions> {
+        let tickrate = self.tickrate.unwrap_or(defaults.tickrate);
+
+        // Clients send a datagram of each kind per tick, so faster ticks would be rate limited.
+        if !(TICKRATE..=1.0).contains(&tickrate) {
+            return None;
+        }
+
+        Some(InstanceOptions {
+            max_objects: self.max_objects.unwrap_or(defaults.max_objects),
+            max_players: self.max_players.or(defaults.max_players),
+            tickrate,
+            ..defaults.clone()
+        })
+    }
+}
+
+impl From<InstanceOptions> for OptionsJson {
+    fn from(value: InstanceOptions) -> Self {
+        Self {
+            max_objects: Some(value.max_objects),
+            max_players: value.max_players,
+            tickrate: Some(value.tickrate),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DidRequest {
+    did: String,
+    /// Instance record id. Applies to every instance if not set.
+    instance: Option<String>,
+}
+
+pub async fn serve(
+    opts: AdminOptions,
+    bans: Arc<watch::Sender<Bans>>,
+    mutes: Arc<watch::Sender<Mutes>>,
+    instances: Arc<InstanceRegistry>,
+) -> std::io::Result<()> {
+    let addr = SocketAddr::new(opts.bind, opts.port);
+
+    let state = Arc::new(AdminState {
+        bans,
+        instances,
+        mutes,
+        token: opts.token,
+    });
+
+    let router = Router::new()
+        .route("/instances", get(list_instances))
+        .route("/instances/:id", get(get_instance))
+        .route("/instances/:id/close", post(close_instance))
+        .route(
+            "/instances/:id/options",
+            put(set_options).get(get_options).delete(reset_options),
+        )
+        .route("/instances/:id/players/:player/kick", post(kick_player))
+        .route("/bans", get(list_bans).post(ban).delete(unban))
+        .route("/mutes", get(list_mutes).post(mute).delete(unmute))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    info!("Admin API listening on {}", addr);
+
+    axum_server::bind(addr)
+        .serve(router.into_make_service())
+        .await
+}
+
+async fn authorize(State(state): State<Arc<AdminState>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // Compared in constant time, so the token cannot be guessed from response times.
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(state.token.as_bytes())));
+
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    next.run(request).await
+}
+
+async fn list_instances(State(state): State<Arc<AdminState>>) -> Json<Vec<InstanceJson>> {
+    let mut instances = Vec::new();
+
+    for (id, instance) in state.instances.running() {
+        // Instances may stop while we are listing them.
+        if let Some(players) = list_players(&instance).await {
+            instances.push(InstanceJson { id, players });
+        }
+    }
+
+    instances.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Json(instances)
+}
+
+async fn get_instance(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Result<Json<InstanceJson>, StatusCode> {
+    let instance = state.instances.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    let players = list_players(&instance).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(InstanceJson { id, players }))
+}
+
+async fn close_instance(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    info!("Closing instance {}", id);
+
+    send(
+        &state,
+        &id,
+        InstanceCommand::Close {
+            reason: "Instance closed by an admin".to_string(),
+        },
+    )
+}
+
+async fn get_options(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Json<OptionsJson> {
+    Json(state.instances.options(&id).into())
+}
+
+async fn set_options(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+    Json(request): Json<OptionsJson>,
+) -> StatusCode {
+    let Some(opts) = request.to_options(state.instances.default_options()) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    info!("Setting options of instance {}", id);
+
+    state.instances.set_options(&id, Some(opts));
+
+    StatusCode::NO_CONTENT
+}
+
+async fn reset_options(State(state): State<Arc<AdminState>>, Path(id): Path<String>) -> StatusCode {
+    info!("Resetting options of instance {}", id);
+
+    if state.instances.set_options(&id, None) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn kick_player(
+    State(state): State<Arc<AdminState>>,
+    Path((id, player_id)): Path<(String, usize)>,
+) -> StatusCode {
+    info!("Kicking player {} from instance {}", player_id, id);
+
+    send(
+        &state,
+        &id,
+        InstanceCommand::Kick {
+            player_id,
+            reason: "Kicked by an admin".to_string(),
+        },
+    )
+}
+
+async fn list_bans(State(state): State<Arc<AdminState>>) -> Json<Bans> {
+    Json(state.bans.borrow().clone())
+}
+
+async fn ban(State(state): State<Arc<AdminState>>, Json(request): Json<DidRequest>) -> StatusCode {
+    info!("Banning {} from {:?}", request.did, request.instance);
+
+    state
+        .bans
+        .send_if_modified(|bans| bans.insert(&request.did, request.instance.as_deref()));
+
+    let command = || InstanceCommand::KickDid {
+        did: request.did.clone(),
+        reason: "Banned by an admin".to_string(),
+    };
+
+    match &request.instance {
+        Some(id) => {
+            // The ban applies even if the instance is not running.
+            send(&state, id, command());
+        }
+        None => {
+            for (_, instance) in state.instances.running() {
+                let _ = instance.send(command());
+            }
+        }
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+async fn unban(
+    State(state): State<Arc<AdminState>>,
+    Json(request): Json<DidRequest>,
+) -> StatusCode {
+    info!("Unbanning {} from {:?}", request.did, request.instance);
+
+    let removed = state
+        .bans
+        .send_if_modified(|bans| bans.remove(&request.did, request.instance.as_deref()));
+
+    if removed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn list_mutes(State(state): State<Arc<AdminState>>) -> Json<Mutes> {
+    Json(state.mutes.borrow().clone())
+}
+
+async fn mute(State(state): State<Arc<AdminState>>, Json(request): Json<DidRequest>) -> StatusCode {
+    info!("Muting {} in {:?}", request.did, request.instance);
+
+    state
+        .mutes
+        .send_if_modified(|mutes| mutes.insert(&request.did, request.instance.as_deref()));
+
+    StatusCode::NO_CONTENT
+}
+
+async fn unmute(
+    State(state): State<Arc<AdminState>>,
+    Json(request): Json<DidRequest>,
+) -> StatusCode {
+    info!("Unmuting {} in {:?}", request.did, request.instance);
+
+    let removed = state
+        .mutes
+        .send_if_modified(|mutes| mutes.remove(&request.did, request.instance.as_deref()));
+
+    if removed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Sends a command to a running instance.
+fn send(state: &AdminState, id: &str, command: InstanceCommand) -> StatusCode {
+    match state.instances.get(id) {
+        Some(instance) if instance.send(command).is_ok() => StatusCode::NO_CONTENT,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn list_players(instance: &UnboundedSender<InstanceCommand>) -> Option<Vec<PlayerJson>> {
+    let (sender, receiver) = oneshot::channel();
+    instance
+        .send(InstanceCommand::ListPlayers { sender })
+        .ok()?;
+
+    let mut players = receiver
+        .await
+        .ok()?
+        .into_iter()
+        .map(PlayerJson::from)
+        .collect::<Vec<_>>();
+
+    players.sort_by_key(|p| p.id);
+
+    Some(players)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_options() {
+        let defaults = InstanceOptions {
+            max_players: Some(8),
+            ..Default::default()
+        };
+
+        let unset = OptionsJson {
+            max_objects: None,
+            max_players: None,
+            tickrate: None,
+        };
+        let opts = unset.to_options(&defaults).unwrap();
+        assert_eq!(opts.max_objects, defaults.max_objects);
+        assert_eq!(opts.max_players, Some(8));
+        assert_eq!(opts.tickrate, defaults.tickrate);
+
+        let set = OptionsJson {
+            max_objects: Some(10),
+            max_players: Some(2),
+            tickrate: Some(0.1),
+        };
+        let opts = set.to_options(&defaults).unwrap();
+        assert_eq!(opts.max_objects, 10);
+        assert_eq!(opts.max_players, Some(2));
+        assert_eq!(opts.tickrate, 0.1);
+    }
+
+    #[test]
+    fn test_to_options_tickrate() {
+        let defaults = InstanceOptions::default();
+        let with_tickrate = |tickrate| OptionsJson {
+            max_objects: None,
+            max_players: None,
+            tickrate: Some(tickrate),
+        };
+
+        assert!(with_tickrate(TICKRATE).to_options(&defaults).is_some());
+        assert!(with_tickrate(1.0).to_options(&defaults).is_some());
+        assert!(with_tickrate(TICKRATE / 2.0)
+            .to_options(&defaults)
+            .is_none());
+        assert!(with_tickrate(2.0).to_options(&defaults).is_none());
+        assert!(with_tickrate(f32::NAN).to_options(&defaults).is_none());
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+use thiserror::Error;
+
+/// Maximum length of a chat message, in characters.
+pub const MAX_MESSAGE_LEN: usize = 500;
+
+/// Sustained number of messages a player may send per second.
+const MESSAGES_PER_SECOND: f32 = 1.0;
+/// Number of messages a player may send in a burst, above the sustained rate.
+const MAX_BURST: f32 = 5.0;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatError {
+    #[error("Banned from instance")]
+    Banned,
+    #[error("Message is empty")]
+    Empty,
+    #[error("Muted")]
+    Muted,
+    #[error("Not in instance")]
+    NotJoined,
+    #[error("Sending messages too quickly")]
+    RateLimited,
+    #[error("Message is longer than {} characters", MAX_MESSAGE_LEN)]
+    TooLong,
+}
+
+/// Per-connection chat rate limiter.
+pub struct ChatLimiter {
+    last_refill: Option<Instant>,
+    tokens: f32,
+}
+
+impl Default for ChatLimiter {
+    fn default() -> Self {
+        Self {
+            last_refill: None,
+            tokens: MAX_BURST,
+        }
+    }
+}
+
+impl ChatLimiter {
+    /// Validates a message, consuming a token from the rate limiter.
+    /// Returns the message to send.
+    pub fn check(&mut self, text: &str, now: Instant) -> Result<String, ChatError> {
+        let text = sanitize(text)?;
+
+        if let Some(last) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last).as_secs_f32();
+            self.tokens = (self.tokens + elapsed * MESSAGES_PER_SECOND).min(MAX_BURST);
+        }
+        self.last_refill = Some(now);
+
+        if self.tokens < 1.0 {
+            return Err(ChatError::RateLimited);
+        }
+
+        self.tokens -= 1.0;
+
+        Ok(text)
+    }
+}
+
+/// Removes control characters and surrounding whitespace.
+fn sanitize(text: &str) -> Result<String, ChatError> {
+    let text = text
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+
+    if text.is_empty() {
+        return Err(ChatError::Empty);
+    }
+
+    if text.chars().count() > MAX_MESSAGE_LEN {
+        return Err(ChatError::TooLong);
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("  hello\u{7}\n"), Ok("hello".to_string()));
+        assert_eq!(sanitize(" \n\t"), Err(ChatError::Empty));
+        assert_eq!(
+            sanitize(&"é".repeat(MAX_MESSAGE_LEN)).map(|s| s.chars().count()),
+            Ok(MAX_MESSAGE_LEN)
+        );
+        assert_eq!(
+            sanitize(&"a".repeat(MAX_MESSAGE_LEN + 1)),
+            Err(ChatError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limiter = ChatLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..MAX_BURST as usize {
+            assert!(limiter.check("hi", now).is_ok());
+        }
+        assert_eq!(limiter.check("hi", now), Err(ChatError::RateLimited));
+
+        // Invalid messages do not use up the limit.
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check("", later), Err(ChatError::Empty));
+        assert!(limiter.check("hi", later).is_ok());
+        assert_eq!(limiter.check("hi", later), Err(ChatError::RateLimited));
+    }
+}
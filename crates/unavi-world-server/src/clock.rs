@@ -0,0 +1,111 @@
+//! Server time, shared by every instance and connection.
+//! Each instance ticks at its own rate from when it starts, but its ticks are numbered
+//! from when the server started, so a tick multiplied by the instance's period is server time.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ServerClock {
+    start: Instant,
+}
+
+impl Default for ServerClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    /// Time since the server started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Tick schedule of a single instance.
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceClock {
+    /// Ticks of `period` between the server and the instance starting.
+    offset: u32,
+    /// Duration of a tick, rounded to whole microseconds so tick boundaries are exact.
+    period: Duration,
+    start: Instant,
+}
+
+impl InstanceClock {
+    /// Clock for an instance starting now.
+    /// `tickrate` is in seconds per tick.
+    pub fn new(server: &ServerClock, tickrate: f32) -> Self {
+        Self::starting_at(server, tickrate, Instant::now())
+    }
+
+    fn starting_at(server: &ServerClock, tickrate: f32, start: Instant) -> Self {
+        let period = (tickrate as f64 * 1_000_000.0).round().max(1.0) as u128;
+        let elapsed = (start - server.start).as_micros();
+
+        Self {
+            // Rounded to the nearest tick, so stamps are within half a tick of server time.
+            offset: ((elapsed + period / 2) / period) as u32,
+            period: Duration::from_micros(period as u64),
+            start,
+        }
+    }
+
+    /// Duration of a tick.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// When the first tick starts.
+    pub fn first_tick(&self) -> Instant {
+        self.start + self.period
+    }
+
+    /// Tick that `at` falls within, counted from when the server started.
+    pub fn tick_at(&self, at: Instant) -> u32 {
+        let ticks = at.saturating_duration_since(self.start).as_micros() / self.period.as_micros();
+        self.offset.wrapping_add(ticks as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_at() {
+        let server = ServerClock::new();
+        let clock = InstanceClock::starting_at(&server, 0.05, server.start);
+
+        assert_eq!(clock.tick_at(server.start), 0);
+        assert_eq!(clock.tick_at(server.start + Duration::from_millis(49)), 0);
+        assert_eq!(clock.tick_at(server.start + Duration::from_millis(50)), 1);
+        assert_eq!(clock.tick_at(server.start + Duration::from_secs(60)), 1200);
+    }
+
+    #[test]
+    fn test_offset() {
+        let server = ServerClock::new();
+
+        // Starting between server ticks, at its own rate.
+        let start = server.start + Duration::from_millis(1234);
+        let clock = InstanceClock::starting_at(&server, 0.1, start);
+
+        assert_eq!(clock.first_tick(), start + Duration::from_millis(100));
+        assert_eq!(clock.tick_at(start), 12);
+        assert_eq!(clock.tick_at(clock.first_tick()), 13);
+        assert_eq!(clock.tick_at(start + Duration::from_secs(10)), 112);
+
+        // Stamps stay within half a tick of server time.
+        let at = start + Duration::from_secs(10);
+        let stamped = clock.period() * clock.tick_at(at);
+        let server_time = at - server.start;
+        assert!(server_time.abs_diff(stamped) <= clock.period() / 2);
+    }
+}
@@ -4,32 +4,34 @@ use capnp_rpc::{rpc_twoparty_capnp::Side, twoparty::VatNetwork, RpcSystem};
 use dwn::{
     actor::Actor,
     store::{DataStore, MessageStore},
-    DWN,
 };
 use tracing::{debug, error};
 
 use wired_world::world_server_capnp::world_server::Client;
+use xwt_core::session::stream::TupleFor;
 use xwt_futures_io::{read::ReadCompat, write::WriteCompat};
-use xwt_wtransport::{Connection, RecvStream, SendStream};
+use xwt_netsim::SimulatedSession;
+use xwt_wtransport::Connection;
 
 use crate::{global_context::GlobalContext, rpc::world_server::WorldServer};
 
+use super::context::ConnectionContext;
+
 pub async fn handle_bi_stream<D: DataStore + 'static, M: MessageStore + 'static>(
-    connection_id: usize,
     context: Arc<GlobalContext>,
-    dwn: Arc<DWN<D, M>>,
-    (send, recv): (SendStream, RecvStream),
+    actor: Arc<Actor<D, M>>,
+    ctx: ConnectionContext,
+    (send, recv): TupleFor<SimulatedSession<Connection>>,
 ) {
-    let actor = Arc::new(Actor::new_did_key(dwn.clone()).unwrap());
-
     let rpc_client: Client = capnp_rpc::new_client(WorldServer {
         actor,
         context,
-        player_id: connection_id,
+        ctx,
+        nonce: None,
     });
 
-    let reader = ReadCompat::<Connection>::new(recv);
-    let writer = WriteCompat::<Connection>::new(send);
+    let reader = ReadCompat::<SimulatedSession<Connection>>::new(recv);
+    let writer = WriteCompat::<SimulatedSession<Connection>>::new(send);
 
     let network = VatNetwork::new(reader, writer, Side::Server, Default::default());
     let rpc_system = RpcSystem::new(Box::new(network), Some(rpc_client.client));
@@ -0,0 +1,215 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    sync::Arc,
+};
+
+use rand::RngCore;
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tracing::debug;
+use wired_world::world_server_capnp::player_events;
+
+use crate::{
+    chat::ChatLimiter,
+    instance::{
+        registry::InstanceRegistry, InstanceCommand, JoinInstanceError, OutgoingEvent, PlayerInfo,
+        Transform,
+    },
+};
+
+use super::{
+    local_ids::LocalIds,
+    resume::{EventQueue, ParkedSession, ResumeToken, RESUME_TOKEN_LEN},
+    snapshot::SnapshotEncoder,
+};
+
+/// Number of times to retry joining an instance that closed while we were joining it.
+const JOIN_ATTEMPTS: usize = 3;
+
+/// Connection state shared with the connection's RPC server.
+/// Must not be borrowed across an await point.
+#[derive(Clone)]
+pub struct ConnectionContext {
+    pub chat: Rc<RefCell<ChatLimiter>>,
+    /// Player info, with the verified DID once authenticated.
+    pub info: Rc<RefCell<PlayerInfo>>,
+    /// Command channels of joined instances, keyed by instance record id.
+    pub instances: Rc<RefCell<HashMap<String, UnboundedSender<InstanceCommand>>>>,
+    pub local_ids: Rc<RefCell<LocalIds>>,
+    /// Changes if another session is resumed.
+    player_id: Rc<Cell<usize>>,
+    resume_token: Rc<RefCell<Option<ResumeToken>>>,
+    /// Passes the events of a resumed session to the connection.
+    resumed: UnboundedSender<EventQueue>,
+    /// Sender for events from instances to this connection.
+    sender: Rc<RefCell<UnboundedSender<OutgoingEvent>>>,
+    pub snapshots: Rc<RefCell<SnapshotEncoder>>,
+    pub subscriber: Rc<RefCell<Option<player_events::Client>>>,
+    /// Latest transform published by the player.
+    pub transform: Rc<RefCell<Transform>>,
+}
+
+impl ConnectionContext {
+    pub fn new(
+        player_id: usize,
+        sender: UnboundedSender<OutgoingEvent>,
+        resumed: UnboundedSender<EventQueue>,
+        delta_snapshots: bool,
+    ) -> Self {
+        Self {
+            chat: Rc::default(),
+            info: Rc::default(),
+            instances: Rc::default(),
+            local_ids: Rc::default(),
+            player_id: Rc::new(Cell::new(player_id)),
+            resume_token: Rc::default(),
+            resumed,
+            sender: Rc::new(RefCell::new(sender)),
+            snapshots: Rc::new(RefCell::new(SnapshotEncoder::new(delta_snapshots))),
+            subscriber: Rc::default(),
+            transform: Rc::default(),
+        }
+    }
+
+    pub fn player_id(&self) -> usize {
+        self.player_id.get()
+    }
+
+    /// Token for resuming this session after a disconnect.
+    /// Generated on first use.
+    pub fn resume_token(&self) -> ResumeToken {
+        *self.resume_token.borrow_mut().get_or_insert_with(|| {
+            let mut token = [0; RESUME_TOKEN_LEN];
+            rand::thread_rng().fill_bytes(&mut token);
+            token
+        })
+    }
+
+    /// Takes the connection's state, so another connection can resume it.
+    /// Returns `None` if there is nothing to resume.
+    pub fn park(&self, events: EventQueue) -> Option<(ResumeToken, ParkedSession)> {
+        let token = (*self.resume_token.borrow())?;
+
+        if self.instances.borrow().is_empty() {
+            return None;
+        }
+
+        let session = ParkedSession {
+            events,
+            info: self.info.borrow().clone(),
+            instances: self.instances.take(),
+            local_ids: self.local_ids.take(),
+            player_id: self.player_id(),
+            sender: self.sender.borrow().clone(),
+            transform: self.transform.borrow().clone(),
+        };
+
+        Some((token, session))
+    }
+
+    /// Takes over a parked session, as if its connection never closed.
+    pub fn resume(&self, token: ResumeToken, session: ParkedSession) {
+        *self.info.borrow_mut() = session.info;
+        *self.instances.borrow_mut() = session.instances;
+        *self.local_ids.borrow_mut() = session.local_ids;
+        self.player_id.set(session.player_id);
+        *self.resume_token.borrow_mut() = Some(token);
+        *self.sender.borrow_mut() = session.sender;
+        *self.transform.borrow_mut() = session.transform;
+
+        if self.resumed.send(session.events).is_err() {
+            debug!("Connection closed.");
+        }
+    }
+
+    pub async fn join(
+        &self,
+        registry: &Arc<InstanceRegistry>,
+        id: String,
+    ) -> Result<(), JoinInstanceError> {
+        if self.instances.borrow().contains_key(&id) {
+            return Ok(());
+        }
+
+        for _ in 0..JOIN_ATTEMPTS {
+            let instance = registry.get_or_spawn(&id);
+            let (result, receiver) = oneshot::channel();
+
+            let command = InstanceCommand::Join {
+                info: self.info.borrow().clone(),
+                player_id: self.player_id(),
+                result,
+                sender: self.sender.borrow().clone(),
+                transform: self.transform.borrow().clone(),
+            };
+
+            if instance.send(command).is_err() {
+                continue;
+            }
+
+            match receiver.await {
+                Ok(Ok(())) => {
+                    self.instances.borrow_mut().insert(id, instance);
+                    return Ok(());
+                }
+                Ok(Err(JoinInstanceError::Closed)) | Err(_) => continue,
+                Ok(Err(e)) => return Err(e),
+            }
+        }
+
+        Err(JoinInstanceError::Closed)
+    }
+
+    pub fn leave(&self, id: &str) {
+        if let Some(instance) = self.instances.borrow_mut().remove(id) {
+            send_command(
+                &instance,
+                InstanceCommand::Leave {
+                    player_id: self.player_id(),
+                },
+            );
+        }
+    }
+
+    /// Leaves every joined instance.
+    pub fn leave_all(&self) {
+        let ids = self.instances.borrow().keys().cloned().collect::<Vec<_>>();
+
+        for id in ids {
+            self.leave(&id);
+        }
+    }
+
+    /// Sends the player's current info to every joined instance.
+    pub fn update_info(&self) {
+        let info = self.info.borrow().clone();
+
+        self.broadcast(|| InstanceCommand::SetPlayerInfo {
+            info: info.clone(),
+            player_id: self.player_id(),
+        });
+    }
+
+    pub fn set_transform(&self, transform: Transform) {
+        *self.transform.borrow_mut() = transform.clone();
+
+        self.broadcast(|| InstanceCommand::SetTransform {
+            player_id: self.player_id(),
+            transform: transform.clone(),
+        });
+    }
+
+    /// Sends a command to every joined instance.
+    pub fn broadcast(&self, mut command: impl FnMut() -> InstanceCommand) {
+        for instance in self.instances.borrow().values() {
+            send_command(instance, command());
+        }
+    }
+}
+
+fn send_command(instance: &UnboundedSender<InstanceCommand>, command: InstanceCommand) {
+    if instance.send(command).is_err() {
+        debug!("Instance closed.");
+    }
+}
@@ -1,14 +1,19 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use capnp::message::ReaderOptions;
 use thiserror::Error;
-use tokio::sync::mpsc::error::SendError;
-use wired_world::datagram_capnp;
+use wired_world::datagram_capnp::{animation_state, client_datagram, pose, pose_transform};
 use xwt_wtransport::Datagram;
 
 use crate::{
     global_context::GlobalContext,
-    update_loop::{IncomingCommand, IncomingEvent, Transform},
+    instance::{AnimationState, InstanceCommand, Pose, PoseTransform, Transform, VoiceFrame},
+    rpc::world_server::read_object_state,
+};
+
+use super::{
+    context::ConnectionContext,
+    validation::{DatagramValidator, Violation},
 };
 
 #[derive(Error, Debug)]
@@ -16,39 +21,194 @@ pub enum HandleDiagramError {
     #[error(transparent)]
     Capnp(#[from] capnp::Error),
     #[error(transparent)]
-    Send(#[from] SendError<IncomingEvent>),
+    NotInSchema(#[from] capnp::NotInSchema),
+    #[error(transparent)]
+    Violation(#[from] Violation),
 }
 
 pub async fn handle_datagram(
-    player_id: usize,
     context: Arc<GlobalContext>,
+    ctx: &ConnectionContext,
+    validator: &mut DatagramValidator,
     dgram: Datagram,
+) -> Result<(), HandleDiagramError> {
+    let now = Instant::now();
+
+    context.metrics.datagram_received(dgram.as_ref().len());
+
+    // Datagrams that cannot be read are subject to the violation policy, like any other.
+    match read_datagram(&context, ctx, validator, &dgram, now) {
+        Err(HandleDiagramError::Capnp(_) | HandleDiagramError::NotInSchema(_)) => {
+            validator.check_malformed(&context.datagram_stats)?;
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+fn read_datagram(
+    context: &GlobalContext,
+    ctx: &ConnectionContext,
+    validator: &mut DatagramValidator,
+    dgram: &Datagram,
+    now: Instant,
 ) -> Result<(), HandleDiagramError> {
     let msg = capnp::serialize_packed::read_message(dgram.as_ref(), ReaderOptions::default())?;
-    let transform = msg.get_root::<datagram_capnp::publish_transform::Reader>()?;
-
-    let translation = transform.get_translation()?;
-    let translation = [
-        translation.get_x(),
-        translation.get_y(),
-        translation.get_z(),
-    ];
-
-    let rotation = transform.get_rotation()?;
-    let rotation = [
-        rotation.get_x(),
-        rotation.get_y(),
-        rotation.get_z(),
-        rotation.get_w(),
-    ];
-
-    context.sender.send(IncomingEvent {
-        command: IncomingCommand::SetTransform(Transform {
-            translation,
-            rotation,
-        }),
-        player_id,
-    })?;
+    let root = msg.get_root::<client_datagram::Reader>()?;
+    let datagram = root.which()?;
+
+    // Object states have their own budget, as owners send one for each object every tick.
+    let allowed = match datagram {
+        client_datagram::ObjectState(_) => {
+            validator.check_object_rate(now, &context.datagram_stats)?
+        }
+        // Clients ack every snapshot chunk, so how many they send depends on our chunking.
+        // Acks are cheap to handle, and only acknowledge chunks we sent.
+        client_datagram::SnapshotAck(_) => true,
+        _ => validator.check_rate(now, &context.datagram_stats)?,
+    };
+
+    if !allowed {
+        return Ok(());
+    }
+
+    match datagram {
+        client_datagram::PublishTransform(transform) => {
+            let transform = transform?;
+
+            let translation = transform.get_translation()?;
+            let translation = [
+                translation.get_x(),
+                translation.get_y(),
+                translation.get_z(),
+            ];
+
+            let rotation = transform.get_rotation()?;
+            let rotation = [
+                rotation.get_x(),
+                rotation.get_y(),
+                rotation.get_z(),
+                rotation.get_w(),
+            ];
+
+            let transform = Transform {
+                translation,
+                rotation,
+            };
+
+            let Some(transform) =
+                validator.check_transform(transform, now, &context.datagram_stats)?
+            else {
+                return Ok(());
+            };
+
+            ctx.set_transform(transform);
+        }
+        client_datagram::ObjectState(state) => {
+            let (id, state) = read_object_state(state?)?;
+
+            if !validator.check_object_state(&state, &context.datagram_stats)? {
+                return Ok(());
+            }
+
+            let player_id = ctx.player_id();
+
+            // Object ids are unique across instances, so only the instance
+            // containing the object will accept the update.
+            ctx.broadcast(|| InstanceCommand::SetObjectState {
+                id,
+                player_id,
+                state: state.clone(),
+            });
+        }
+        client_datagram::PublishPose(pose) => {
+            let pose = read_pose(pose?)?;
+
+            if !validator.check_pose(&pose, &context.datagram_stats)? {
+                return Ok(());
+            }
+
+            let pose = Arc::new(pose);
+            let player_id = ctx.player_id();
+
+            ctx.broadcast(|| InstanceCommand::SetPose {
+                player_id,
+                pose: pose.clone(),
+            });
+        }
+        client_datagram::PublishAnimation(state) => {
+            // Every value is quantized, so there is nothing to validate.
+            let state = read_animation_state(state?);
+            let player_id = ctx.player_id();
+
+            ctx.broadcast(|| InstanceCommand::SetAnimation { player_id, state });
+        }
+        client_datagram::SnapshotAck(ack) => {
+            let ack = ack?;
+            ctx.snapshots
+                .borrow_mut()
+                .ack(ack.get_sequence(), ack.get_chunk());
+        }
+        client_datagram::Voice(frame) => {
+            let frame = frame?;
+            let data = frame.get_data()?;
+
+            if !validator.check_voice(data.len(), &context.datagram_stats)? {
+                return Ok(());
+            }
+
+            let frame = VoiceFrame {
+                sequence: frame.get_sequence(),
+                data: data.into(),
+            };
+            let player_id = ctx.player_id();
+
+            ctx.broadcast(|| InstanceCommand::Voice {
+                player_id,
+                frame: frame.clone(),
+            });
+        }
+    }
 
     Ok(())
 }
+
+fn read_animation_state(reader: animation_state::Reader) -> AnimationState {
+    AnimationState {
+        menu_open: reader.get_menu_open(),
+        falling: reader.get_falling(),
+        emote: reader.get_emote(),
+        velocity: [
+            reader.get_velocity_x(),
+            reader.get_velocity_y(),
+            reader.get_velocity_z(),
+        ],
+    }
+}
+
+fn read_pose(reader: pose::Reader) -> capnp::Result<Pose> {
+    let read_transform = |reader: pose_transform::Reader| PoseTransform {
+        translation: [reader.get_x(), reader.get_y(), reader.get_z()],
+        rotation: reader.get_rotation(),
+    };
+
+    Ok(Pose {
+        head: reader
+            .has_head()
+            .then(|| reader.get_head().map(read_transform))
+            .transpose()?,
+        left_hand: reader
+            .has_left_hand()
+            .then(|| reader.get_left_hand().map(read_transform))
+            .transpose()?,
+        right_hand: reader
+            .has_right_hand()
+            .then(|| reader.get_right_hand().map(read_transform))
+            .transpose()?,
+        bones: reader
+            .get_bones()?
+            .iter()
+            .map(|bone| (bone.get_bone(), bone.get_rotation()))
+            .collect(),
+    })
+}
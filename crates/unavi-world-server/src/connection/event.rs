@@ -1,72 +1,293 @@
-use std::collections::{btree_map::Entry, BTreeMap};
-
 use anyhow::Result;
+use capnp::capability::Promise;
 
-use wired_world::datagram_capnp;
+use tracing::{debug, error};
+use wired_world::{
+    datagram_capnp::{animation_state, pose, pose_transform, server_datagram},
+    world_server_capnp::object_owner,
+};
 use xwt_core::base::Session;
 
-use crate::update_loop::OutgoingEvent;
+use crate::{
+    instance::{AnimationState, OutgoingEvent, Pose, PoseTransform},
+    metrics::ServerMetrics,
+    rpc::world_server::{write_object_state, write_player_info},
+};
 
-#[derive(Default)]
-pub struct EventContext {
-    local_ids: BTreeMap<usize, u16>,
-    local_ids_rev: BTreeMap<u16, usize>,
-    next_id: u16,
-}
+use super::context::ConnectionContext;
 
 pub async fn handle_event(
     event: OutgoingEvent,
-    ctx: &mut EventContext,
+    ctx: &ConnectionContext,
+    metrics: &ServerMetrics,
     session: &impl Session,
 ) -> Result<()> {
     match event {
-        OutgoingEvent::PlayerJoined { id } => {
-            if let Entry::Vacant(e) = ctx.local_ids.entry(id) {
-                e.insert(ctx.next_id);
-                ctx.local_ids_rev.insert(ctx.next_id, id);
-
-                if ctx.next_id == u16::MAX {
-                    ctx.next_id = 0;
-                } else {
-                    ctx.next_id += 1;
-                }
-
-                while ctx.local_ids_rev.contains_key(&ctx.next_id) {
-                    ctx.next_id += 1;
-                }
+        OutgoingEvent::ChatMessage {
+            instance,
+            player,
+            did,
+            text,
+        } => {
+            let Some(local_id) = ctx.local_ids.borrow().local(player) else {
+                return Ok(());
+            };
+
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.chat_message_request();
+                request.get().set_record_id(instance.as_str());
+
+                let mut message = request.get().init_message();
+                message.set_player(local_id);
+                message.set_did(did.unwrap_or_default());
+                message.set_text(text);
+
+                send_request(request.send().promise);
+            }
+        }
+        OutgoingEvent::PlayerJoined { id, info } => {
+            let (local_id, new) = ctx.local_ids.borrow_mut().insert(id);
+
+            // Already announced through another shared instance.
+            if !new {
+                return Ok(());
+            }
+
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.player_joined_request();
+                write_player_info(request.get().init_player(), local_id, info);
+                send_request(request.send().promise);
             }
         }
         OutgoingEvent::PlayerLeft { id } => {
-            if let Some(local_id) = ctx.local_ids.remove(&id) {
-                ctx.local_ids_rev.remove(&local_id);
+            let Some(local_id) = ctx.local_ids.borrow_mut().remove(id) else {
+                return Ok(());
+            };
+
+            ctx.snapshots.borrow_mut().remove_player(local_id);
+
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.player_left_request();
+                request.get().set_id(local_id);
+                send_request(request.send().promise);
             }
         }
-        OutgoingEvent::Transforms(transforms) => {
-            let mut buf = Vec::new();
+        OutgoingEvent::PlayerUpdated { id, info } => {
+            let Some(local_id) = ctx.local_ids.borrow().local(id) else {
+                return Ok(());
+            };
 
-            for (transform, player_id) in transforms.into_iter().zip(ctx.local_ids.values()) {
-                let mut msg = capnp::message::Builder::new_default();
-                let mut root = msg.init_root::<datagram_capnp::receive_transform::Builder>();
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.player_updated_request();
+                write_player_info(request.get().init_player(), local_id, info);
+                send_request(request.send().promise);
+            }
+        }
+        OutgoingEvent::Animation { player, state } => {
+            let Some(local_id) = ctx.local_ids.borrow().local(player) else {
+                return Ok(());
+            };
 
-                root.set_player_id(*player_id);
+            let mut msg = capnp::message::Builder::new_default();
+            let mut root = msg.init_root::<server_datagram::Builder>().init_animation();
+            root.set_player_id(local_id);
+            write_animation_state(root.init_state(), &state);
 
-                let mut translation = root.reborrow().init_translation();
-                translation.set_x(transform.translation[0]);
-                translation.set_y(transform.translation[1]);
-                translation.set_z(transform.translation[2]);
+            let mut datagram = Vec::new();
+            capnp::serialize_packed::write_message(&mut datagram, &msg)?;
 
-                let mut rotation = root.init_rotation();
-                rotation.set_x(transform.rotation[0]);
-                rotation.set_y(transform.rotation[1]);
-                rotation.set_z(transform.rotation[2]);
-                rotation.set_w(transform.rotation[3]);
+            session.send_datagram(&datagram).await?;
+            metrics.datagram_sent(datagram.len());
+        }
+        OutgoingEvent::Pose { player, pose } => {
+            let Some(local_id) = ctx.local_ids.borrow().local(player) else {
+                return Ok(());
+            };
+
+            let mut msg = capnp::message::Builder::new_default();
+            let mut root = msg.init_root::<server_datagram::Builder>().init_pose();
+            root.set_player_id(local_id);
+            write_pose(root.init_pose(), &pose);
 
-                capnp::serialize_packed::write_message(&mut buf, &msg)?;
+            let mut datagram = Vec::new();
+            capnp::serialize_packed::write_message(&mut datagram, &msg)?;
+
+            session.send_datagram(&datagram).await?;
+            metrics.datagram_sent(datagram.len());
+        }
+        OutgoingEvent::ObjectSpawned {
+            instance,
+            id,
+            object,
+        } => {
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.object_spawned_request();
+                request.get().set_record_id(instance.as_str());
 
-                session.send_datagram(&buf).await?;
+                let mut info = request.get().init_object();
+                info.set_id(id);
+                info.set_data(&object.data);
+                info.set_transferable(object.transferable);
+                write_object_owner(info.reborrow().init_owner(), ctx, object.owner);
+                write_object_state(info.init_state(), id, &object.state);
+
+                send_request(request.send().promise);
+            }
+        }
+        OutgoingEvent::ObjectDespawned { instance, id } => {
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.object_despawned_request();
+                request.get().set_record_id(instance.as_str());
+                request.get().set_id(id);
+                send_request(request.send().promise);
             }
         }
+        OutgoingEvent::ObjectOwnerChanged {
+            instance,
+            id,
+            owner,
+        } => {
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.object_owner_changed_request();
+                request.get().set_record_id(instance.as_str());
+                request.get().set_id(id);
+                write_object_owner(request.get().init_owner(), ctx, owner);
+                send_request(request.send().promise);
+            }
+        }
+        OutgoingEvent::ObjectState { id, state } => {
+            let mut msg = capnp::message::Builder::new_default();
+            let root = msg.init_root::<server_datagram::Builder>();
+            write_object_state(root.init_object_state(), id, &state);
+
+            let mut datagram = Vec::new();
+            capnp::serialize_packed::write_message(&mut datagram, &msg)?;
+
+            session.send_datagram(&datagram).await?;
+            metrics.datagram_sent(datagram.len());
+        }
+        OutgoingEvent::Kicked { instance, reason } => {
+            debug!("Kicked from {}: {}", instance, reason);
+
+            // The instance has already removed us, so there is no need to leave.
+            ctx.instances.borrow_mut().remove(&instance);
+
+            if let Some(subscriber) = ctx.subscriber.borrow().as_ref() {
+                let mut request = subscriber.kicked_request();
+                request.get().set_record_id(instance.as_str());
+                request.get().set_reason(reason.as_str());
+                send_request(request.send().promise);
+            }
+        }
+        OutgoingEvent::Transforms {
+            origin,
+            tick,
+            transforms,
+        } => {
+            let transforms = {
+                let local_ids = ctx.local_ids.borrow();
+                transforms
+                    .into_iter()
+                    .filter_map(|(id, transform)| local_ids.local(id).map(|l| (l, transform)))
+                    .collect::<Vec<_>>()
+            };
+
+            if transforms.is_empty() {
+                return Ok(());
+            }
+
+            let datagrams = ctx
+                .snapshots
+                .borrow_mut()
+                .encode(tick, origin, &transforms)?;
+
+            for datagram in datagrams {
+                session.send_datagram(&datagram).await?;
+                metrics.datagram_sent(datagram.len());
+            }
+        }
+        OutgoingEvent::Voice { player, frame } => {
+            let Some(local_id) = ctx.local_ids.borrow().local(player) else {
+                return Ok(());
+            };
+
+            let mut msg = capnp::message::Builder::new_default();
+            let mut root = msg.init_root::<server_datagram::Builder>().init_voice();
+            root.set_player_id(local_id);
+
+            let mut voice = root.init_frame();
+            voice.set_sequence(frame.sequence);
+            voice.set_data(&frame.data);
+
+            let mut datagram = Vec::new();
+            capnp::serialize_packed::write_message(&mut datagram, &msg)?;
+
+            session.send_datagram(&datagram).await?;
+            metrics.datagram_sent(datagram.len());
+        }
     };
 
     Ok(())
 }
+
+fn write_animation_state(mut builder: animation_state::Builder, state: &AnimationState) {
+    builder.set_menu_open(state.menu_open);
+    builder.set_falling(state.falling);
+    builder.set_emote(state.emote);
+    builder.set_velocity_x(state.velocity[0]);
+    builder.set_velocity_y(state.velocity[1]);
+    builder.set_velocity_z(state.velocity[2]);
+}
+
+fn write_pose(mut builder: pose::Builder, pose: &Pose) {
+    let write_transform = |mut builder: pose_transform::Builder, transform: &PoseTransform| {
+        builder.set_x(transform.translation[0]);
+        builder.set_y(transform.translation[1]);
+        builder.set_z(transform.translation[2]);
+        builder.set_rotation(transform.rotation);
+    };
+
+    if let Some(head) = &pose.head {
+        write_transform(builder.reborrow().init_head(), head);
+    }
+    if let Some(hand) = &pose.left_hand {
+        write_transform(builder.reborrow().init_left_hand(), hand);
+    }
+    if let Some(hand) = &pose.right_hand {
+        write_transform(builder.reborrow().init_right_hand(), hand);
+    }
+
+    let mut bones = builder.init_bones(pose.bones.len() as u32);
+    for (i, (bone, rotation)) in pose.bones.iter().enumerate() {
+        let mut builder = bones.reborrow().get(i as u32);
+        builder.set_bone(*bone);
+        builder.set_rotation(*rotation);
+    }
+}
+
+fn write_object_owner(
+    mut builder: object_owner::Builder,
+    ctx: &ConnectionContext,
+    owner: Option<usize>,
+) {
+    match owner {
+        Some(owner) if owner == ctx.player_id() => builder.set_you(()),
+        Some(owner) => match ctx.local_ids.borrow().local(owner) {
+            Some(local_id) => builder.set_player(local_id),
+            // Owners are in the same instance, so should always have a local id.
+            None => builder.set_none(()),
+        },
+        None => builder.set_none(()),
+    }
+}
+
+/// Sends a request to the subscriber without waiting for the response.
+/// Requests to the same capability are delivered in order.
+fn send_request<T: 'static>(promise: Promise<T, capnp::Error>) {
+    tokio::task::spawn_local(async move {
+        match promise.await {
+            Ok(_) => debug!("Event delivered."),
+            Err(e) => error!("Failed to deliver event: {}", e),
+        }
+    });
+}
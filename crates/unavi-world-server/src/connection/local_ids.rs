@@ -0,0 +1,113 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+/// Maps global player ids to u16 ids local to a connection.
+/// Local ids are sent over the wire instead of the global ids, to keep datagrams small.
+/// Ids are reference counted, as the same player may be seen through multiple instances.
+#[derive(Default)]
+pub struct LocalIds {
+    ids: BTreeMap<usize, (u16, usize)>,
+    ids_rev: BTreeMap<u16, usize>,
+    next_id: u16,
+}
+
+impl LocalIds {
+    /// Assigns a local id to the player, or increments its reference count.
+    /// Returns the local id, and whether it was newly assigned.
+    pub fn insert(&mut self, id: usize) -> (u16, bool) {
+        match self.ids.entry(id) {
+            Entry::Occupied(mut e) => {
+                let (local_id, count) = e.get_mut();
+                *count += 1;
+                (*local_id, false)
+            }
+            Entry::Vacant(e) => {
+                let local_id = self.next_id;
+                e.insert((local_id, 1));
+                self.ids_rev.insert(local_id, id);
+
+                self.next_id = self.next_id.wrapping_add(1);
+
+                while self.ids_rev.contains_key(&self.next_id) {
+                    self.next_id = self.next_id.wrapping_add(1);
+                }
+
+                (local_id, true)
+            }
+        }
+    }
+
+    /// Decrements the player's reference count.
+    /// Returns the local id if it was released.
+    pub fn remove(&mut self, id: usize) -> Option<u16> {
+        let Entry::Occupied(mut e) = self.ids.entry(id) else {
+            return None;
+        };
+
+        let (_, count) = e.get_mut();
+        *count -= 1;
+
+        if *count > 0 {
+            return None;
+        }
+
+        let (local_id, _) = e.remove();
+        self.ids_rev.remove(&local_id);
+        Some(local_id)
+    }
+
+    pub fn local(&self, id: usize) -> Option<u16> {
+        self.ids.get(&id).map(|(local_id, _)| *local_id)
+    }
+
+    pub fn global(&self, local_id: u16) -> Option<usize> {
+        self.ids_rev.get(&local_id).copied()
+    }
+
+    /// Iterates over (global id, local id) pairs, ordered by global id.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u16)> + '_ {
+        self.ids.iter().map(|(id, (local_id, _))| (*id, *local_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_ids() {
+        let mut ids = LocalIds::default();
+
+        assert_eq!(ids.insert(10), (0, true));
+        assert_eq!(ids.insert(20), (1, true));
+        assert_eq!(ids.insert(10), (0, false));
+
+        assert_eq!(ids.local(20), Some(1));
+        assert_eq!(ids.global(1), Some(20));
+
+        // Still referenced once.
+        assert_eq!(ids.remove(10), None);
+        assert_eq!(ids.local(10), Some(0));
+
+        assert_eq!(ids.remove(10), Some(0));
+        assert_eq!(ids.local(10), None);
+        assert_eq!(ids.global(0), None);
+    }
+
+    #[test]
+    fn test_wrapping() {
+        let mut ids = LocalIds {
+            next_id: u16::MAX,
+            ..Default::default()
+        };
+
+        assert_eq!(ids.insert(0).0, u16::MAX);
+        assert_eq!(ids.insert(1).0, 0);
+
+        // Skips ids that are still in use.
+        ids.remove(0);
+        ids.next_id = u16::MAX - 1;
+        assert_eq!(ids.insert(2).0, u16::MAX - 1);
+        assert_eq!(ids.insert(3).0, u16::MAX);
+        assert_eq!(ids.insert(4).0, 1);
+    }
+}
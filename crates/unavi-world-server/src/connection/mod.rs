@@ -2,41 +2,87 @@ use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use dwn::{
+    actor::Actor,
     store::{DataStore, MessageStore},
-    DWN,
 };
-use tracing::{error, info, info_span, Instrument};
+use tokio::{sync::mpsc::UnboundedReceiver, time::Instant};
+use tracing::{debug, error, info, info_span, Instrument};
 
 use xwt_core::{
     endpoint::accept::{Accepting, Request},
     session::{datagram::Receive, stream::AcceptBi},
 };
+use xwt_netsim::SimulatedSession;
 
-use crate::{
-    global_context::GlobalContext,
-    update_loop::{IncomingCommand, IncomingEvent},
-    NewConnection,
-};
+use crate::{global_context::GlobalContext, NewConnection};
+
+use self::{context::ConnectionContext, resume::EventQueue};
 
 mod bi_stream;
+pub mod context;
 mod datagram;
 mod event;
+pub mod local_ids;
+pub mod resume;
+mod snapshot;
+pub mod validation;
 
 pub async fn handle_connection<D: DataStore + 'static, M: MessageStore + 'static>(
     new_connection: NewConnection,
     context: Arc<GlobalContext>,
-    dwn: Arc<DWN<D, M>>,
+    actor: Arc<Actor<D, M>>,
 ) -> Result<()> {
-    let player_id = new_connection.id;
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut events = EventQueue::new(receiver);
+    let (send_resumed, mut recv_resumed) = tokio::sync::mpsc::unbounded_channel();
+    let ctx = ConnectionContext::new(
+        new_connection.id,
+        sender,
+        send_resumed,
+        context.delta_snapshots,
+    );
 
-    if let Err(e) = handle_connection_impl(new_connection, context.clone(), dwn).await {
+    if let Err(e) = handle_connection_impl(
+        new_connection,
+        context.clone(),
+        actor,
+        ctx.clone(),
+        &mut events,
+        &mut recv_resumed,
+    )
+    .await
+    {
         error!("Connection failed: {}", e);
     }
 
-    context.sender.send(IncomingEvent {
-        command: IncomingCommand::Disconnect,
-        player_id,
-    })?;
+    // A session may have been resumed just before closing.
+    while let Ok(resumed) = recv_resumed.try_recv() {
+        events = resumed;
+    }
+
+    // Keep the player in their instances, in case they reconnect.
+    let Some((token, parked)) = ctx.park(events) else {
+        ctx.leave_all();
+        return Ok(());
+    };
+
+    debug!("Parking session for {:?}", resume::RESUME_GRACE_PERIOD);
+    let generation = context.sessions.park(token, parked);
+
+    tokio::task::spawn_local(async move {
+        let deadline = Instant::now() + resume::RESUME_GRACE_PERIOD;
+        let mut interval = tokio::time::interval(resume::COMPACT_INTERVAL);
+
+        // Stops early if the session is resumed, or misses too many events.
+        while Instant::now() < deadline && context.sessions.compact(&token, generation) {
+            interval.tick().await;
+        }
+
+        if let Some(parked) = context.sessions.expire(&token, generation) {
+            debug!("Parked session expired.");
+            parked.leave_all();
+        }
+    });
 
     Ok(())
 }
@@ -44,7 +90,10 @@ pub async fn handle_connection<D: DataStore + 'static, M: MessageStore + 'static
 async fn handle_connection_impl<D: DataStore + 'static, M: MessageStore + 'static>(
     new_connection: NewConnection,
     context: Arc<GlobalContext>,
-    dwn: Arc<DWN<D, M>>,
+    actor: Arc<Actor<D, M>>,
+    ctx: ConnectionContext,
+    events: &mut EventQueue,
+    resumed: &mut UnboundedReceiver<EventQueue>,
 ) -> Result<()> {
     info!("Waiting for session request...");
     let session_request = new_connection.incoming_session.wait_accept().await?;
@@ -55,35 +104,33 @@ async fn handle_connection_impl<D: DataStore + 'static, M: MessageStore + 'stati
         session_request.0.path()
     );
     let session = session_request.ok().await?;
+    let session = SimulatedSession::new(session, context.network_conditions.clone());
 
-    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
-
-    context.sender.send(IncomingEvent {
-        player_id: new_connection.id,
-        command: IncomingCommand::NewPlayer { sender },
-    })?;
-
-    let mut event_context = event::EventContext::default();
+    let mut validator = validation::DatagramValidator::new(context.validation.clone());
 
     loop {
         let context = context.clone();
-        let dwn = dwn.clone();
+        let actor = actor.clone();
 
         tokio::select! {
-            event = receiver.recv() => {
+            event = events.recv() => {
                 let event = event.ok_or(anyhow!("Event channel closed"))?;
-                event::handle_event(event, &mut event_context, &session).await?;
+                event::handle_event(event, &ctx, &context.metrics, &session).await?;
+            }
+            Some(resumed) = resumed.recv() => {
+                info!("Resumed session.");
+                *events = resumed;
             }
             stream = session.accept_bi() => {
                 let stream = stream?;
                 info!("Accepted bi stream.");
                 tokio::task::spawn_local(
-                    bi_stream::handle_bi_stream(new_connection.id, context, dwn, stream).instrument(info_span!("bi"))
+                    bi_stream::handle_bi_stream(context, actor, ctx.clone(), stream).instrument(info_span!("bi"))
                 );
             }
             dgram = session.receive_datagram() => {
                 let dgram = dgram?;
-                datagram::handle_datagram(new_connection.id, context, dgram).instrument(info_span!("dgram")).await?;
+                datagram::handle_datagram(context, &ctx, &mut validator, dgram).instrument(info_span!("dgram")).await?;
             }
         }
     }
@@ -0,0 +1,219 @@
+//! Keeps disconnected players in their instances for a grace period,
+//! so they can resume their session without other players seeing them leave.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::debug;
+
+use crate::instance::{InstanceCommand, OutgoingEvent, PlayerInfo, Transform};
+
+use super::local_ids::LocalIds;
+
+/// How long a disconnected player is kept in their instances.
+pub const RESUME_GRACE_PERIOD: Duration = Duration::from_secs(30);
+/// How often the events of a parked session are compacted.
+pub const COMPACT_INTERVAL: Duration = Duration::from_secs(1);
+/// Maximum reliable events kept for a parked session.
+/// Sessions that miss more are expired, as resuming would flood the client.
+pub const MAX_BUFFERED_EVENTS: usize = 1024;
+
+pub const RESUME_TOKEN_LEN: usize = 32;
+
+pub type ResumeToken = [u8; RESUME_TOKEN_LEN];
+
+/// Events from instances to a connection.
+pub struct EventQueue {
+    /// Events taken from `receiver` while parked, oldest first.
+    buffered: VecDeque<OutgoingEvent>,
+    receiver: UnboundedReceiver<OutgoingEvent>,
+}
+
+impl EventQueue {
+    pub fn new(receiver: UnboundedReceiver<OutgoingEvent>) -> Self {
+        Self {
+            buffered: VecDeque::default(),
+            receiver,
+        }
+    }
+
+    /// Cancel safe, like [UnboundedReceiver::recv].
+    pub async fn recv(&mut self) -> Option<OutgoingEvent> {
+        match self.buffered.pop_front() {
+            Some(event) => Some(event),
+            None => self.receiver.recv().await,
+        }
+    }
+
+    /// Moves pending reliable events into the buffer, and drops the rest.
+    /// Returns whether the buffer is within [MAX_BUFFERED_EVENTS].
+    pub fn compact(&mut self) -> bool {
+        while let Ok(event) = self.receiver.try_recv() {
+            if event.is_reliable() {
+                self.buffered.push_back(event);
+            }
+        }
+
+        self.buffered.len() <= MAX_BUFFERED_EVENTS
+    }
+}
+
+/// State of a disconnected connection, waiting to be resumed.
+pub struct ParkedSession {
+    /// Events from instances are kept here until the session is resumed.
+    pub events: EventQueue,
+    pub info: PlayerInfo,
+    pub instances: HashMap<String, UnboundedSender<InstanceCommand>>,
+    pub local_ids: LocalIds,
+    pub player_id: usize,
+    pub sender: UnboundedSender<OutgoingEvent>,
+    pub transform: Transform,
+}
+
+impl ParkedSession {
+    /// Leaves every instance, for when the session will not be resumed.
+    pub fn leave_all(self) {
+        for instance in self.instances.values() {
+            if instance
+                .send(InstanceCommand::Leave {
+                    player_id: self.player_id,
+                })
+                .is_err()
+            {
+                debug!("Instance closed.");
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ResumeStore {
+    next_generation: AtomicU64,
+    sessions: Mutex<HashMap<ResumeToken, (u64, ParkedSession)>>,
+}
+
+impl ResumeStore {
+    /// Stores a session until it is resumed or expires.
+    /// Returns the generation to pass to [`ResumeStore::expire`].
+    pub fn park(&self, token: ResumeToken, session: ParkedSession) -> u64 {
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+
+        if let Some((_, replaced)) = self
+            .sessions
+            .lock()
+            .unwrap()
+            .insert(token, (generation, session))
+        {
+            replaced.leave_all();
+        }
+
+        generation
+    }
+
+    /// Compacts the events of a parked session, so they do not build up while it is parked.
+    /// Returns whether the session is still parked, and within the event limit.
+    pub fn compact(&self, token: &ResumeToken, generation: u64) -> bool {
+        match self.sessions.lock().unwrap().get_mut(token) {
+            Some((current, session)) if *current == generation => session.events.compact(),
+            _ => false,
+        }
+    }
+
+    /// Takes a session to resume it.
+    pub fn take(&self, token: &ResumeToken) -> Option<ParkedSession> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .remove(token)
+            .map(|(_, session)| session)
+    }
+
+    /// Takes a session once its grace period is over.
+    /// Returns `None` if it was resumed, even if it has since been parked again.
+    pub fn expire(&self, token: &ResumeToken, generation: u64) -> Option<ParkedSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        match sessions.get(token) {
+            Some((current, _)) if *current == generation => {
+                sessions.remove(token).map(|(_, session)| session)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    fn parked(player_id: usize) -> ParkedSession {
+        let (sender, receiver) = unbounded_channel();
+
+        ParkedSession {
+            events: EventQueue::new(receiver),
+            info: PlayerInfo::default(),
+            instances: HashMap::default(),
+            local_ids: LocalIds::default(),
+            player_id,
+            sender,
+            transform: Transform::default(),
+        }
+    }
+
+    #[test]
+    fn test_resume() {
+        let store = ResumeStore::default();
+        let token = [1; RESUME_TOKEN_LEN];
+
+        let generation = store.park(token, parked(5));
+        assert_eq!(store.take(&token).map(|s| s.player_id), Some(5));
+        assert!(store.take(&token).is_none());
+
+        // Parked again after resuming, so the old timer must not expire it.
+        let new_generation = store.park(token, parked(5));
+        assert!(store.expire(&token, generation).is_none());
+        assert!(store.expire(&token, new_generation).is_some());
+        assert!(store.take(&token).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let (sender, receiver) = unbounded_channel();
+        let mut events = EventQueue::new(receiver);
+
+        sender.send(OutgoingEvent::PlayerLeft { id: 1 }).unwrap();
+        sender
+            .send(OutgoingEvent::Transforms {
+                origin: [0.0; 3],
+                tick: 0,
+                transforms: Vec::new(),
+            })
+            .unwrap();
+        assert!(events.compact());
+        sender.send(OutgoingEvent::PlayerLeft { id: 2 }).unwrap();
+
+        // Transforms are dropped, and order is kept.
+        assert!(matches!(
+            events.recv().await,
+            Some(OutgoingEvent::PlayerLeft { id: 1 })
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(OutgoingEvent::PlayerLeft { id: 2 })
+        ));
+
+        for id in 0..=MAX_BUFFERED_EVENTS {
+            sender.send(OutgoingEvent::PlayerLeft { id }).unwrap();
+        }
+        assert!(!events.compact());
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+
+use wired_world::{datagram_capnp::server_datagram, quantize::QuantizedTransform};
+
+use crate::instance::Transform;
+
+/// Maximum datagram size that is safe to send without fragmentation.
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+/// Upper bound on the packed size of everything but the player list.
+const SNAPSHOT_HEADER_SIZE: usize = 96;
+/// Upper bound on the packed size of a single `QuantizedTransform`.
+/// 3 words, plus the worst case packing overhead of one tag byte per word.
+const TRANSFORM_SIZE: usize = 27;
+pub const PLAYERS_PER_DATAGRAM: usize = (MAX_DATAGRAM_SIZE - SNAPSHOT_HEADER_SIZE) / TRANSFORM_SIZE;
+
+/// Number of sent snapshots to remember, for processing acks.
+const HISTORY_LEN: usize = 64;
+/// Maximum sequence offset to delta encode against.
+/// Clients must keep at least this many snapshots per player.
+pub const MAX_BASELINE_OFFSET: u32 = 32;
+
+/// Encodes transform snapshots for a single connection.
+pub struct SnapshotEncoder {
+    /// Latest acknowledged transform of each player.
+    acked: HashMap<u16, (u32, QuantizedTransform)>,
+    delta: bool,
+    history: VecDeque<SentSnapshot>,
+    origin: [f32; 3],
+    sequence: u32,
+}
+
+struct SentSnapshot {
+    chunks: Vec<Vec<(u16, QuantizedTransform)>>,
+    sequence: u32,
+}
+
+impl SnapshotEncoder {
+    pub fn new(delta: bool) -> Self {
+        Self {
+            acked: HashMap::default(),
+            delta,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            origin: [0.0; 3],
+            sequence: 0,
+        }
+    }
+
+    /// Encodes transforms into one or more datagrams, stamped with the instance tick.
+    pub fn encode(
+        &mut self,
+        tick: u32,
+        origin: [f32; 3],
+        transforms: &[(u16, Transform)],
+    ) -> capnp::Result<Vec<Vec<u8>>> {
+        if origin != self.origin {
+            // Baselines are relative to the old origin.
+            self.acked.clear();
+            self.origin = origin;
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+        let sequence = self.sequence;
+
+        let mut datagrams = Vec::new();
+        let mut sent = SentSnapshot {
+            chunks: Vec::new(),
+            sequence,
+        };
+
+        for (chunk_idx, chunk) in transforms.chunks(PLAYERS_PER_DATAGRAM).enumerate() {
+            let mut msg = capnp::message::Builder::new_default();
+            let mut snapshot = msg.init_root::<server_datagram::Builder>().init_snapshot();
+
+            snapshot.set_sequence(sequence);
+            snapshot.set_chunk(chunk_idx as u8);
+            snapshot.set_tick(tick);
+
+            let mut msg_origin = snapshot.reborrow().init_origin();
+            msg_origin.set_x(origin[0]);
+            msg_origin.set_y(origin[1]);
+            msg_origin.set_z(origin[2]);
+
+            let mut players = snapshot.init_players(chunk.len() as u32);
+            let mut sent_chunk = Vec::with_capacity(chunk.len());
+
+            for (i, (player_id, transform)) in chunk.iter().enumerate() {
+                let quantized =
+                    QuantizedTransform::new(transform.translation, transform.rotation, origin);
+
+                let (baseline, encoded) = match self.baseline(*player_id) {
+                    Some((offset, baseline)) => (offset, quantized.delta(&baseline)),
+                    None => (0, quantized),
+                };
+
+                let mut player = players.reborrow().get(i as u32);
+                player.set_player_id(*player_id);
+                player.set_baseline(baseline);
+                player.set_x(encoded.translation[0]);
+                player.set_y(encoded.translation[1]);
+                player.set_z(encoded.translation[2]);
+                player.set_rotation(encoded.rotation);
+
+                sent_chunk.push((*player_id, quantized));
+            }
+
+            let mut buf = Vec::new();
+            capnp::serialize_packed::write_message(&mut buf, &msg)?;
+            datagrams.push(buf);
+
+            sent.chunks.push(sent_chunk);
+        }
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(sent);
+
+        Ok(datagrams)
+    }
+
+    /// Marks a snapshot chunk as received by the client.
+    pub fn ack(&mut self, sequence: u32, chunk: u8) {
+        let Some(sent) = self.history.iter().find(|s| s.sequence == sequence) else {
+            return;
+        };
+
+        let Some(chunk) = sent.chunks.get(chunk as usize) else {
+            return;
+        };
+
+        for (player_id, quantized) in chunk {
+            let newer = match self.acked.get(player_id) {
+                Some((acked_sequence, _)) => is_newer(sequence, *acked_sequence),
+                None => true,
+            };
+
+            if newer {
+                self.acked.insert(*player_id, (sequence, *quantized));
+            }
+        }
+    }
+
+    /// Forgets acknowledged state for a player, so its local id can be reused.
+    pub fn remove_player(&mut self, player_id: u16) {
+        self.acked.remove(&player_id);
+    }
+
+    /// Gets the baseline to delta encode a player's transform against, for the next sequence.
+    fn baseline(&self, player_id: u16) -> Option<(u8, QuantizedTransform)> {
+        if !self.delta {
+            return None;
+        }
+
+        let (acked_sequence, quantized) = self.acked.get(&player_id)?;
+        let offset = self.sequence.wrapping_sub(*acked_sequence);
+
+        if offset == 0 || offset > MAX_BASELINE_OFFSET {
+            return None;
+        }
+
+        Some((offset as u8, *quantized))
+    }
+}
+
+/// Compares sequence numbers, accounting for wrapping.
+fn is_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use capnp::message::ReaderOptions;
+
+    use super::*;
+
+    fn read(datagram: &[u8]) -> Vec<(u16, u8, [i32; 3])> {
+        let msg =
+            capnp::serialize_packed::read_message(datagram, ReaderOptions::default()).unwrap();
+        let root = msg.get_root::<server_datagram::Reader>().unwrap();
+
+        let server_datagram::Snapshot(snapshot) = root.which().unwrap() else {
+            panic!("Not a snapshot");
+        };
+        let snapshot = snapshot.unwrap();
+
+        snapshot
+            .get_players()
+            .unwrap()
+            .iter()
+            .map(|p| {
+                (
+                    p.get_player_id(),
+                    p.get_baseline(),
+                    [p.get_x(), p.get_y(), p.get_z()],
+                )
+            })
+            .collect()
+    }
+
+    fn transforms(count: u16, x: f32) -> Vec<(u16, Transform)> {
+        (0..count)
+            .map(|id| {
+                (
+                    id,
+                    Transform {
+                        translation: [x, 1.0, 0.0],
+                        rotation: [0.0, 0.0, 0.0, 1.0],
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunking() {
+        let mut encoder = SnapshotEncoder::new(false);
+
+        let datagrams = encoder.encode(0, [0.0; 3], &transforms(100, 0.0)).unwrap();
+        assert_eq!(datagrams.len(), 100usize.div_ceil(PLAYERS_PER_DATAGRAM));
+
+        for datagram in datagrams {
+            assert!(datagram.len() <= MAX_DATAGRAM_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_delta() {
+        let mut encoder = SnapshotEncoder::new(true);
+
+        let first = encoder.encode(0, [0.0; 3], &transforms(2, 1.0)).unwrap();
+        assert_eq!(read(&first[0])[0], (0, 0, [1000, 1000, 0]));
+
+        // Not yet acked.
+        let second = encoder.encode(0, [0.0; 3], &transforms(2, 1.5)).unwrap();
+        assert_eq!(read(&second[0])[0], (0, 0, [1500, 1000, 0]));
+
+        encoder.ack(2, 0);
+
+        let third = encoder.encode(0, [0.0; 3], &transforms(2, 1.5)).unwrap();
+        assert_eq!(read(&third[0])[0], (0, 1, [0, 0, 0]));
+
+        // Deltas compress well.
+        assert!(third[0].len() < second[0].len());
+
+        // Origin change invalidates baselines.
+        let fourth = encoder
+            .encode(0, [1.0, 0.0, 0.0], &transforms(2, 1.5))
+            .unwrap();
+        assert_eq!(read(&fourth[0])[0], (0, 0, [500, 1000, 0]));
+    }
+
+    #[test]
+    fn test_tick() {
+        let mut encoder = SnapshotEncoder::new(false);
+
+        for datagram in encoder.encode(42, [0.0; 3], &transforms(100, 0.0)).unwrap() {
+            let msg = capnp::serialize_packed::read_message(
+                datagram.as_slice(),
+                ReaderOptions::default(),
+            )
+            .unwrap();
+            let root = msg.get_root::<server_datagram::Reader>().unwrap();
+
+            let server_datagram::Snapshot(snapshot) = root.which().unwrap() else {
+                panic!("Not a snapshot");
+            };
+            assert_eq!(snapshot.unwrap().get_tick(), 42);
+        }
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer(2, 1));
+        assert!(!is_newer(1, 2));
+        assert!(!is_newer(1, 1));
+        assert!(is_newer(0, u32::MAX));
+    }
+}
@@ -0,0 +1,632 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use crate::instance::{
+    objects::{ObjectState, MAX_OWNED_OBJECTS},
+    Pose, Transform, TICKRATE,
+};
+
+/// Largest possible Opus packet, in bytes.
+pub const MAX_VOICE_FRAME_LEN: usize = 1275;
+/// Number of humanoid bones a pose may contain.
+pub const MAX_POSE_BONES: usize = 52;
+
+/// What to do with a datagram that fails validation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ViolationPolicy {
+    /// Discard the datagram.
+    #[default]
+    Drop,
+    /// Correct the datagram where possible, discarding it otherwise.
+    Clamp,
+    /// Close the connection.
+    Disconnect,
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationOptions {
+    /// Sustained number of datagrams a client may send per second.
+    pub max_datagrams_per_second: f32,
+    /// Number of datagrams a client may send in a burst, above the sustained rate.
+    pub max_burst: f32,
+    /// Sustained number of object states a client may send per second.
+    /// Limited separately from other datagrams, as each owned object is sent every tick.
+    pub max_object_states_per_second: f32,
+    /// Number of object states a client may send in a burst, above the sustained rate.
+    pub max_object_burst: f32,
+    /// Maximum distance from the origin, in meters.
+    pub max_coordinate: f32,
+    /// Maximum speed between transforms, in meters per second.
+    pub max_speed: f32,
+    pub policy: ViolationPolicy,
+    /// Consecutive transforms that may move too fast before the latest is accepted,
+    /// so players that teleport are not stuck where they were.
+    /// Accepted transforms still count as violations.
+    pub teleport_after: u32,
+    /// Minimum time between accepting transforms that move too fast.
+    pub teleport_cooldown: Duration,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self {
+            // Transforms, poses and animation states at 20 ticks per second,
+            // and voice frames at 50 per second, with headroom.
+            max_datagrams_per_second: 200.0,
+            max_burst: 60.0,
+            // Every owned object at the fastest tickrate.
+            max_object_states_per_second: MAX_OWNED_OBJECTS as f32 / TICKRATE,
+            max_object_burst: MAX_OWNED_OBJECTS as f32 * 3.0,
+            max_coordinate: 100_000.0,
+            max_speed: 50.0,
+            policy: ViolationPolicy::Drop,
+            // A second of transforms at 20 ticks per second.
+            teleport_after: 20,
+            teleport_cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    #[error("Datagram rate limit exceeded")]
+    RateLimited,
+    #[error("Transform contains non-finite values")]
+    NonFinite,
+    #[error("Transform is out of bounds")]
+    OutOfBounds,
+    #[error("Rotation is not normalized")]
+    Unnormalized,
+    #[error("Transform moved too fast")]
+    TooFast,
+    #[error("Pose is invalid")]
+    InvalidPose,
+    #[error("Object state is invalid")]
+    InvalidObjectState,
+    #[error("Voice frame is too large")]
+    VoiceTooLarge,
+    #[error("Datagram is malformed")]
+    Malformed,
+}
+
+/// Server-wide datagram counters, for monitoring.
+#[derive(Debug, Default)]
+pub struct DatagramStats {
+    pub accepted: AtomicU64,
+    pub clamped: AtomicU64,
+    pub dropped: AtomicU64,
+    pub rate_limited: AtomicU64,
+    pub disconnected: AtomicU64,
+}
+
+impl DatagramStats {
+    fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Per-connection datagram validation state.
+pub struct DatagramValidator {
+    datagrams: TokenBucket,
+    last_transform: Option<(Instant, Transform)>,
+    object_states: TokenBucket,
+    opts: ValidationOptions,
+    /// When a transform moving too fast was last accepted.
+    last_teleport: Option<Instant>,
+    /// Number of consecutive transforms moving too fast.
+    too_fast: u32,
+}
+
+struct TokenBucket {
+    burst: f32,
+    last_refill: Option<Instant>,
+    per_second: f32,
+    tokens: f32,
+}
+
+impl TokenBucket {
+    fn new(per_second: f32, burst: f32) -> Self {
+        Self {
+            burst,
+            last_refill: None,
+            per_second,
+            tokens: burst,
+        }
+    }
+
+    /// Consumes a token, returning whether one was available.
+    fn take(&mut self, now: Instant) -> bool {
+        if let Some(last) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last).as_secs_f32();
+            self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst);
+        }
+        self.last_refill = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl DatagramValidator {
+    pub fn new(opts: ValidationOptions) -> Self {
+        Self {
+            datagrams: TokenBucket::new(opts.max_datagrams_per_second, opts.max_burst),
+            last_transform: None,
+            object_states: TokenBucket::new(
+                opts.max_object_states_per_second,
+                opts.max_object_burst,
+            ),
+            last_teleport: None,
+            opts,
+            too_fast: 0,
+        }
+    }
+
+    /// Consumes a token from the rate limiter.
+    /// Returns whether the datagram should be processed.
+    pub fn check_rate(&mut self, now: Instant, stats: &DatagramStats) -> Result<bool, Violation> {
+        if self.datagrams.take(now) {
+            return Ok(true);
+        }
+
+        DatagramStats::increment(&stats.rate_limited);
+        self.violation(Violation::RateLimited, stats).map(|_| false)
+    }
+
+    /// Consumes a token from the object state rate limiter.
+    /// Returns whether the object state should be processed.
+    pub fn check_object_rate(
+        &mut self,
+        now: Instant,
+        stats: &DatagramStats,
+    ) -> Result<bool, Violation> {
+        if self.object_states.take(now) {
+            return Ok(true);
+        }
+
+        DatagramStats::increment(&stats.rate_limited);
+        self.violation(Violation::RateLimited, stats).map(|_| false)
+    }
+
+    /// Validates a transform published by the client.
+    /// Returns the transform to use, or `None` if it should be dropped.
+    pub fn check_transform(
+        &mut self,
+        transform: Transform,
+        now: Instant,
+        stats: &DatagramStats,
+    ) -> Result<Option<Transform>, Violation> {
+        let mut transform = transform;
+        let mut clamped = false;
+
+        let finite = transform.translation.iter().all(|v| v.is_finite())
+            && transform.rotation.iter().all(|v| v.is_finite());
+        if !finite {
+            // Nothing sensible to clamp to.
+            return self.violation(Violation::NonFinite, stats).map(|_| None);
+        }
+
+        let len = transform.rotation.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if (len - 1.0).abs() > 0.01 {
+            if !self.violation(Violation::Unnormalized, stats)? || len < f32::EPSILON {
+                return Ok(None);
+            }
+
+            transform.rotation = transform.rotation.map(|v| v / len);
+            clamped = true;
+        }
+
+        let max = self.opts.max_coordinate;
+        if transform.translation.iter().any(|v| v.abs() > max) {
+            if !self.violation(Violation::OutOfBounds, stats)? {
+                return Ok(None);
+            }
+
+            transform.translation = transform.translation.map(|v| v.clamp(-max, max));
+            clamped = true;
+        }
+
+        if let Some((last_time, last)) = &self.last_transform {
+            let elapsed = now.saturating_duration_since(*last_time).as_secs_f32();
+            let max_distance = self.opts.max_speed * elapsed;
+
+            let delta = [
+                transform.translation[0] - last.translation[0],
+                transform.translation[1] - last.translation[1],
+                transform.translation[2] - last.translation[2],
+            ];
+            let distance = delta.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+            if distance > max_distance {
+                self.too_fast += 1;
+
+                let teleported = self.too_fast >= self.opts.teleport_after
+                    && self.last_teleport.is_none_or(|last| {
+                        now.saturating_duration_since(last) >= self.opts.teleport_cooldown
+                    });
+
+                let clamp = self.violation(Violation::TooFast, stats)?;
+
+                if teleported {
+                    // Rather than leaving the player stuck where they were.
+                    self.last_teleport = Some(now);
+                    self.too_fast = 0;
+                } else if clamp {
+                    let scale = max_distance / distance;
+                    transform.translation = [
+                        last.translation[0] + delta[0] * scale,
+                        last.translation[1] + delta[1] * scale,
+                        last.translation[2] + delta[2] * scale,
+                    ];
+                    clamped = true;
+                } else {
+                    return Ok(None);
+                }
+            } else {
+                self.too_fast = 0;
+            }
+        }
+
+        if clamped {
+            DatagramStats::increment(&stats.clamped);
+        }
+        DatagramStats::increment(&stats.accepted);
+
+        self.last_transform = Some((now, transform.clone()));
+
+        Ok(Some(transform))
+    }
+
+    /// Validates the state of a networked object.
+    /// Objects may move freely, so only finite values within bounds are checked.
+    pub fn check_object_state(
+        &mut self,
+        state: &ObjectState,
+        stats: &DatagramStats,
+    ) -> Result<bool, Violation> {
+        let max = self.opts.max_coordinate;
+
+        let valid = state
+            .transform
+            .translation
+            .iter()
+            .all(|v| v.is_finite() && v.abs() <= max)
+            && state.transform.rotation.iter().all(|v| v.is_finite())
+            && state
+                .linear_velocity
+                .iter()
+                .chain(state.angular_velocity.iter())
+                .all(|v| v.is_finite());
+
+        if !valid {
+            return self
+                .violation(Violation::InvalidObjectState, stats)
+                .map(|_| false);
+        }
+
+        DatagramStats::increment(&stats.accepted);
+
+        Ok(true)
+    }
+
+    /// Validates the bones of a pose.
+    /// Transforms are quantized, so cannot be out of range.
+    pub fn check_pose(&mut self, pose: &Pose, stats: &DatagramStats) -> Result<bool, Violation> {
+        let valid = pose.bones.len() <= MAX_POSE_BONES
+            && pose
+                .bones
+                .iter()
+                .all(|(bone, _)| (*bone as usize) < MAX_POSE_BONES);
+
+        if !valid {
+            return self.violation(Violation::InvalidPose, stats).map(|_| false);
+        }
+
+        DatagramStats::increment(&stats.accepted);
+
+        Ok(true)
+    }
+
+    /// Validates the size of a voice frame.
+    /// Returns whether the frame should be relayed.
+    pub fn check_voice(&mut self, len: usize, stats: &DatagramStats) -> Result<bool, Violation> {
+        if len > MAX_VOICE_FRAME_LEN {
+            // Audio cannot be clamped.
+            return self
+                .violation(Violation::VoiceTooLarge, stats)
+                .map(|_| false);
+        }
+
+        DatagramStats::increment(&stats.accepted);
+
+        Ok(true)
+    }
+
+    /// Applies the violation policy to a datagram that could not be read.
+    pub fn check_malformed(&mut self, stats: &DatagramStats) -> Result<(), Violation> {
+        // Malformed datagrams cannot be clamped.
+        self.violation(Violation::Malformed, stats).map(|_| ())
+    }
+
+    /// Applies the violation policy.
+    /// Returns whether the datagram may be clamped, or an error if the connection should close.
+    fn violation(&self, violation: Violation, stats: &DatagramStats) -> Result<bool, Violation> {
+        match self.opts.policy {
+            ViolationPolicy::Drop => {
+                DatagramStats::increment(&stats.dropped);
+                Ok(false)
+            }
+            ViolationPolicy::Clamp => Ok(true),
+            ViolationPolicy::Disconnect => {
+                DatagramStats::increment(&stats.disconnected);
+                Err(violation)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn transform(x: f32) -> Transform {
+        Transform {
+            translation: [x, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    fn validator(policy: ViolationPolicy) -> DatagramValidator {
+        DatagramValidator::new(ValidationOptions {
+            policy,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let stats = DatagramStats::default();
+        let mut validator = DatagramValidator::new(ValidationOptions {
+            max_datagrams_per_second: 10.0,
+            max_burst: 2.0,
+            ..Default::default()
+        });
+
+        let now = Instant::now();
+        assert_eq!(validator.check_rate(now, &stats), Ok(true));
+        assert_eq!(validator.check_rate(now, &stats), Ok(true));
+        assert_eq!(validator.check_rate(now, &stats), Ok(false));
+
+        let later = now + Duration::from_millis(100);
+        assert_eq!(validator.check_rate(later, &stats), Ok(true));
+        assert_eq!(validator.check_rate(later, &stats), Ok(false));
+
+        assert_eq!(stats.rate_limited.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_object_rate() {
+        let stats = DatagramStats::default();
+        let mut validator = validator(ViolationPolicy::Disconnect);
+        let now = Instant::now();
+
+        // An owner at the limit, sending every object and its own datagrams each tick.
+        for tick in 0..100 {
+            let now = now + Duration::from_secs_f32(TICKRATE) * tick;
+
+            for _ in 0..MAX_OWNED_OBJECTS {
+                assert_eq!(validator.check_object_rate(now, &stats), Ok(true));
+            }
+
+            for _ in 0..4 {
+                assert_eq!(validator.check_rate(now, &stats), Ok(true));
+            }
+        }
+
+        // Sending faster is still limited, once the burst is used up.
+        let last = now + Duration::from_secs_f32(TICKRATE) * 99;
+        let limited = (0..MAX_OWNED_OBJECTS * 3)
+            .map(|_| validator.check_object_rate(last, &stats))
+            .any(|result| result == Err(Violation::RateLimited));
+        assert!(limited);
+    }
+
+    #[test]
+    fn test_voice() {
+        let stats = DatagramStats::default();
+        let mut clamp = validator(ViolationPolicy::Clamp);
+        assert_eq!(clamp.check_voice(MAX_VOICE_FRAME_LEN, &stats), Ok(true));
+        assert_eq!(
+            clamp.check_voice(MAX_VOICE_FRAME_LEN + 1, &stats),
+            Ok(false)
+        );
+
+        let mut disconnect = validator(ViolationPolicy::Disconnect);
+        assert_eq!(
+            disconnect.check_voice(MAX_VOICE_FRAME_LEN + 1, &stats),
+            Err(Violation::VoiceTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let stats = DatagramStats::default();
+        assert_eq!(
+            validator(ViolationPolicy::Drop).check_malformed(&stats),
+            Ok(())
+        );
+        assert_eq!(
+            validator(ViolationPolicy::Clamp).check_malformed(&stats),
+            Ok(())
+        );
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 1);
+
+        assert_eq!(
+            validator(ViolationPolicy::Disconnect).check_malformed(&stats),
+            Err(Violation::Malformed)
+        );
+        assert_eq!(stats.disconnected.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_object_state() {
+        let stats = DatagramStats::default();
+        let mut validator = validator(ViolationPolicy::Disconnect);
+
+        let mut state = ObjectState::default();
+        assert_eq!(validator.check_object_state(&state, &stats), Ok(true));
+
+        state.angular_velocity[1] = f32::INFINITY;
+        assert_eq!(
+            validator.check_object_state(&state, &stats),
+            Err(Violation::InvalidObjectState)
+        );
+    }
+
+    #[test]
+    fn test_pose() {
+        let stats = DatagramStats::default();
+        let mut validator = validator(ViolationPolicy::Disconnect);
+
+        let mut pose = Pose {
+            bones: vec![(0, 0), (MAX_POSE_BONES as u8 - 1, 0)],
+            ..Default::default()
+        };
+        assert_eq!(validator.check_pose(&pose, &stats), Ok(true));
+
+        pose.bones.push((MAX_POSE_BONES as u8, 0));
+        assert_eq!(
+            validator.check_pose(&pose, &stats),
+            Err(Violation::InvalidPose)
+        );
+    }
+
+    #[test]
+    fn test_drop() {
+        let stats = DatagramStats::default();
+        let mut validator = validator(ViolationPolicy::Drop);
+        let now = Instant::now();
+
+        let nan = Transform {
+            translation: [f32::NAN, 0.0, 0.0],
+            ..transform(0.0)
+        };
+        assert_eq!(validator.check_transform(nan, now, &stats), Ok(None));
+
+        assert!(validator
+            .check_transform(transform(0.0), now, &stats)
+            .unwrap()
+            .is_some());
+
+        // Teleport.
+        let later = now + Duration::from_millis(50);
+        assert_eq!(
+            validator.check_transform(transform(100.0), later, &stats),
+            Ok(None)
+        );
+
+        assert_eq!(stats.accepted.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_teleport() {
+        let stats = DatagramStats::default();
+        let mut validator = DatagramValidator::new(ValidationOptions {
+            teleport_after: 3,
+            teleport_cooldown: Duration::from_secs(10),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let tick = Duration::from_millis(50);
+
+        assert!(validator
+            .check_transform(transform(0.0), now, &stats)
+            .unwrap()
+            .is_some());
+
+        // Accepted after repeated violations.
+        for i in 1..3 {
+            assert_eq!(
+                validator.check_transform(transform(100.0), now + tick * i, &stats),
+                Ok(None)
+            );
+        }
+        assert_eq!(
+            validator.check_transform(transform(100.0), now + tick * 3, &stats),
+            Ok(Some(transform(100.0)))
+        );
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 3);
+
+        // Moving normally from the new position.
+        assert_eq!(
+            validator.check_transform(transform(101.0), now + tick * 4, &stats),
+            Ok(Some(transform(101.0)))
+        );
+
+        // Not accepted again until the cooldown is over.
+        let mut at = now + tick * 4;
+        for _ in 0..10 {
+            at += tick;
+            assert_eq!(
+                validator.check_transform(transform(500.0), at, &stats),
+                Ok(None)
+            );
+        }
+
+        let later = now + Duration::from_secs(11);
+        assert_eq!(
+            validator.check_transform(transform(500.0), later, &stats),
+            Ok(Some(transform(500.0)))
+        );
+    }
+
+    #[test]
+    fn test_clamp() {
+        let stats = DatagramStats::default();
+        let mut validator = validator(ViolationPolicy::Clamp);
+        let now = Instant::now();
+
+        let unnormalized = Transform {
+            rotation: [0.0, 0.0, 0.0, 2.0],
+            ..transform(0.0)
+        };
+        let clamped = validator
+            .check_transform(unnormalized, now, &stats)
+            .unwrap()
+            .unwrap();
+        assert_eq!(clamped.rotation, [0.0, 0.0, 0.0, 1.0]);
+
+        let later = now + Duration::from_secs(1);
+        let clamped = validator
+            .check_transform(transform(100.0), later, &stats)
+            .unwrap()
+            .unwrap();
+        assert!((clamped.translation[0] - 50.0).abs() < 1e-4);
+
+        assert_eq!(stats.clamped.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let stats = DatagramStats::default();
+        let mut validator = validator(ViolationPolicy::Disconnect);
+
+        let far = transform(1e9);
+        assert_eq!(
+            validator.check_transform(far, Instant::now(), &stats),
+            Err(Violation::OutOfBounds)
+        );
+        assert_eq!(stats.disconnected.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,8 +1,27 @@
-use tokio::sync::mpsc::UnboundedSender;
+use std::sync::Arc;
 
-use crate::update_loop::IncomingEvent;
+use tokio::sync::watch;
+use wired_social::protocols::world_host::Bans;
+use xwt_netsim::Conditions;
+
+use crate::{
+    connection::{
+        resume::ResumeStore,
+        validation::{DatagramStats, ValidationOptions},
+    },
+    instance::registry::InstanceRegistry,
+    metrics::ServerMetrics,
+};
 
 pub struct GlobalContext {
-    pub sender: UnboundedSender<IncomingEvent>,
+    pub bans: Arc<watch::Sender<Bans>>,
+    pub datagram_stats: Arc<DatagramStats>,
+    pub delta_snapshots: bool,
+    pub instances: Arc<InstanceRegistry>,
+    pub metrics: Arc<ServerMetrics>,
+    pub network_conditions: Conditions,
+    /// Sessions of disconnected players, waiting to be resumed.
+    pub sessions: ResumeStore,
+    pub validation: ValidationOptions,
     pub world_host_did: String,
 }
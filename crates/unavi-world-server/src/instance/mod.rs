@@ -0,0 +1,367 @@
+//! Each instance is simulated by its own task, with its own command channel and tick,
+//! so a busy instance cannot delay the others.
+//! Tasks are spread across the worker threads of the runtime they are spawned on.
+
+use std::{sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::{
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::debug;
+
+use crate::{chat::ChatError, clock::InstanceClock, interest::InterestSettings};
+
+use self::{
+    objects::{NetworkedObject, ObjectError, ObjectState, MAX_INSTANCE_OBJECTS},
+    registry::InstanceRegistry,
+    state::InstanceState,
+};
+
+pub mod objects;
+pub mod registry;
+mod state;
+
+pub const TICKRATE: f32 = 1.0 / 20.0;
+
+/// How long an instance may be empty before its task is stopped.
+const EMPTY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct InstanceOptions {
+    pub interest: InterestSettings,
+    /// Maximum number of networked objects, across all players.
+    pub max_objects: usize,
+    pub max_players: Option<usize>,
+    /// Seconds per tick.
+    pub tickrate: f32,
+}
+
+impl Default for InstanceOptions {
+    fn default() -> Self {
+        Self {
+            interest: InterestSettings::default(),
+            max_objects: MAX_INSTANCE_OBJECTS,
+            max_players: None,
+            tickrate: TICKRATE,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PlayerInfo {
+    pub avatar: Option<String>,
+    pub did: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+/// Quantized transform relative to a player, see [wired_world::quantize].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoseTransform {
+    pub translation: [i16; 3],
+    pub rotation: u32,
+}
+
+/// Extended pose of a player's avatar.
+/// Kept quantized, as the server only relays it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pose {
+    pub head: Option<PoseTransform>,
+    pub left_hand: Option<PoseTransform>,
+    pub right_hand: Option<PoseTransform>,
+    /// Bone indices and their rotations.
+    pub bones: Vec<(u8, u32)>,
+}
+
+/// Discrete animation state of a player's avatar.
+/// Kept quantized, as the server only relays it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AnimationState {
+    pub menu_open: bool,
+    pub falling: bool,
+    /// 0 if not emoting.
+    pub emote: u8,
+    /// Velocity in centimeters per second.
+    pub velocity: [i16; 3],
+}
+
+/// Opus encoded voice audio.
+#[derive(Clone, Debug)]
+pub struct VoiceFrame {
+    pub sequence: u16,
+    /// Shared between recipients.
+    pub data: Arc<[u8]>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerSummary {
+    pub id: usize,
+    pub info: PlayerInfo,
+    pub muted: bool,
+}
+
+#[derive(Debug)]
+pub enum InstanceCommand {
+    /// Removes every player, and stops the instance.
+    Close {
+        reason: String,
+    },
+    /// Removes an object owned by the player, or with no owner.
+    DespawnObject {
+        id: u32,
+        player_id: usize,
+        result: oneshot::Sender<Result<(), ObjectError>>,
+    },
+    /// Get info about a player, if the requesting player is also in the instance.
+    GetPlayer {
+        id: usize,
+        player_id: usize,
+        sender: oneshot::Sender<Option<PlayerInfo>>,
+    },
+    /// Get all other players, if the requesting player is in the instance.
+    GetPlayers {
+        player_id: usize,
+        sender: oneshot::Sender<Vec<(usize, PlayerInfo)>>,
+    },
+    Join {
+        info: PlayerInfo,
+        player_id: usize,
+        result: oneshot::Sender<Result<(), JoinInstanceError>>,
+        sender: UnboundedSender<OutgoingEvent>,
+        transform: Transform,
+    },
+    /// Removes a player from the instance.
+    Kick {
+        player_id: usize,
+        reason: String,
+    },
+    /// Removes all players authenticated as `did` from the instance.
+    KickDid {
+        did: String,
+        reason: String,
+    },
+    Leave {
+        player_id: usize,
+    },
+    /// Get all players, for administration.
+    ListPlayers {
+        sender: oneshot::Sender<Vec<PlayerSummary>>,
+    },
+    /// Takes ownership of an object.
+    RequestOwnership {
+        id: u32,
+        player_id: usize,
+        result: oneshot::Sender<Result<(), ObjectError>>,
+    },
+    /// Sends a chat message to the other players.
+    SendMessage {
+        player_id: usize,
+        result: oneshot::Sender<Result<(), ChatError>>,
+        text: String,
+    },
+    /// Updates an object owned by the player.
+    /// Ignored if the player is not the owner, as datagrams may arrive after a transfer.
+    SetObjectState {
+        id: u32,
+        player_id: usize,
+        state: ObjectState,
+    },
+    /// Relays an animation state to players that can see it.
+    SetAnimation {
+        player_id: usize,
+        state: AnimationState,
+    },
+    SetPlayerInfo {
+        info: PlayerInfo,
+        player_id: usize,
+    },
+    /// Relays a pose to nearby players.
+    SetPose {
+        player_id: usize,
+        pose: Arc<Pose>,
+    },
+    SetTransform {
+        player_id: usize,
+        transform: Transform,
+    },
+    /// Adds an object, owned by the player.
+    SpawnObject {
+        id: u32,
+        object: NetworkedObject,
+        player_id: usize,
+        result: oneshot::Sender<Result<(), ObjectError>>,
+    },
+    /// Relays a voice frame to nearby players.
+    Voice {
+        player_id: usize,
+        frame: VoiceFrame,
+    },
+}
+
+#[derive(Debug)]
+pub enum OutgoingEvent {
+    Animation {
+        player: usize,
+        state: AnimationState,
+    },
+    ChatMessage {
+        instance: String,
+        player: usize,
+        /// Verified DID of the sender.
+        did: Option<String>,
+        text: String,
+    },
+    PlayerJoined {
+        id: usize,
+        info: PlayerInfo,
+    },
+    PlayerLeft {
+        id: usize,
+    },
+    PlayerUpdated {
+        id: usize,
+        info: PlayerInfo,
+    },
+    Pose {
+        player: usize,
+        pose: Arc<Pose>,
+    },
+    ObjectSpawned {
+        instance: String,
+        id: u32,
+        object: NetworkedObject,
+    },
+    ObjectDespawned {
+        instance: String,
+        id: u32,
+    },
+    ObjectOwnerChanged {
+        instance: String,
+        id: u32,
+        owner: Option<usize>,
+    },
+    ObjectState {
+        id: u32,
+        state: ObjectState,
+    },
+    /// The player was removed from an instance by an admin.
+    Kicked {
+        instance: String,
+        reason: String,
+    },
+    /// Transforms of nearby players, keyed by player id.
+    Transforms {
+        /// Origin that translations are quantized relative to.
+        origin: [f32; 3],
+        /// Instance tick, counted from when the server started.
+        tick: u32,
+        transforms: Vec<(usize, Transform)>,
+    },
+    Voice {
+        player: usize,
+        frame: VoiceFrame,
+    },
+}
+
+impl OutgoingEvent {
+    /// Whether the event is sent reliably, rather than as a datagram.
+    /// Datagram events are superseded by later ones, so may be dropped.
+    pub fn is_reliable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Animation { .. }
+                | Self::Pose { .. }
+                | Self::ObjectState { .. }
+                | Self::Transforms { .. }
+                | Self::Voice { .. }
+        )
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum JoinInstanceError {
+    #[error("Instance is full")]
+    Full,
+    /// The instance task stopped before processing the request.
+    #[error("Instance is closed")]
+    Closed,
+}
+
+async fn run_instance(
+    id: String,
+    mut receiver: UnboundedReceiver<InstanceCommand>,
+    registry: Arc<InstanceRegistry>,
+    opts: InstanceOptions,
+) {
+    debug!("Starting instance.");
+
+    let mut state = InstanceState::new(
+        id.clone(),
+        opts.interest,
+        opts.max_players,
+        opts.max_objects,
+        registry.mutes(),
+    );
+    let mut empty_since = Some(Instant::now());
+
+    let clock = InstanceClock::new(registry.clock(), opts.tickrate);
+    let mut interval =
+        tokio::time::interval_at(Instant::from_std(clock.first_tick()), clock.period());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            at = interval.tick() => {
+                let start = Instant::now();
+                // From the scheduled time, so skipped ticks are counted.
+                state.tick(clock.tick_at(at.into_std()));
+                registry.metrics().tick_duration.observe(start.elapsed());
+
+                if !state.is_empty() {
+                    empty_since = None;
+                    continue;
+                }
+
+                let since = *empty_since.get_or_insert_with(Instant::now);
+
+                if since.elapsed() >= EMPTY_TIMEOUT && registry.close(&id, &mut receiver) {
+                    break;
+                }
+            }
+            command = receiver.recv() => {
+                let Some(command) = command else {
+                    break;
+                };
+
+                let close = matches!(command, InstanceCommand::Close { .. });
+
+                state.handle(command);
+                registry.set_player_count(&id, state.len());
+
+                if close {
+                    registry.force_close(&id, &mut receiver);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Reject anything that raced with closing, so the sender can retry on a new task.
+    while let Ok(command) = receiver.try_recv() {
+        if let InstanceCommand::Join { result, .. } = command {
+            let _ = result.send(Err(JoinInstanceError::Closed));
+        }
+    }
+
+    debug!("Stopped instance.");
+}
@@ -0,0 +1,328 @@
+//! Networked objects, shared between the players of an instance.
+//! Each object has at most one owner, which is the only player allowed to update its state.
+//! Objects without an owner are despawned after [ORPHAN_GRACE_PERIOD], unless persistent.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use super::Transform;
+
+/// Maximum size of an object's application data, in bytes.
+pub const MAX_OBJECT_DATA_LEN: usize = 1024;
+/// Maximum number of objects a player may own in an instance.
+pub const MAX_OWNED_OBJECTS: usize = 64;
+/// Default maximum number of objects in an instance.
+pub const MAX_INSTANCE_OBJECTS: usize = 1024;
+/// How long an object may be without an owner before it is despawned.
+pub const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectState {
+    pub transform: Transform,
+    pub linear_velocity: [f32; 3],
+    pub angular_velocity: [f32; 3],
+}
+
+#[derive(Clone, Debug)]
+pub struct NetworkedObject {
+    /// Application defined, such as what to spawn.
+    pub data: Vec<u8>,
+    pub owner: Option<usize>,
+    /// Whether the object is kept without an owner.
+    pub persistent: bool,
+    /// Latest state, sent to players that join later.
+    pub state: ObjectState,
+    /// Whether other players may take ownership while it is owned.
+    pub transferable: bool,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectError {
+    #[error("Object data is larger than {} bytes", MAX_OBJECT_DATA_LEN)]
+    DataTooLarge,
+    #[error("Object not found")]
+    NotFound,
+    #[error("Not in instance")]
+    NotJoined,
+    #[error("Object is owned by another player")]
+    NotOwner,
+    #[error("Object cannot be transferred")]
+    NotTransferable,
+    #[error("Owning too many objects")]
+    TooManyObjects,
+    #[error("Instance has too many objects")]
+    InstanceFull,
+}
+
+pub struct Objects {
+    max_objects: usize,
+    objects: HashMap<u32, NetworkedObject>,
+    /// When each object without an owner lost its owner.
+    orphaned: HashMap<u32, Instant>,
+}
+
+impl Default for Objects {
+    fn default() -> Self {
+        Self::new(MAX_INSTANCE_OBJECTS)
+    }
+}
+
+impl Objects {
+    pub fn new(max_objects: usize) -> Self {
+        Self {
+            max_objects,
+            objects: HashMap::default(),
+            orphaned: HashMap::default(),
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&NetworkedObject> {
+        self.objects.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &NetworkedObject)> {
+        self.objects.iter().map(|(id, object)| (*id, object))
+    }
+
+    /// Adds an object, owned by `player_id`.
+    pub fn spawn(
+        &mut self,
+        player_id: usize,
+        id: u32,
+        object: NetworkedObject,
+    ) -> Result<(), ObjectError> {
+        if object.data.len() > MAX_OBJECT_DATA_LEN {
+            return Err(ObjectError::DataTooLarge);
+        }
+
+        if self.owned(player_id).count() >= MAX_OWNED_OBJECTS {
+            return Err(ObjectError::TooManyObjects);
+        }
+
+        if self.objects.len() >= self.max_objects {
+            return Err(ObjectError::InstanceFull);
+        }
+
+        self.objects.insert(
+            id,
+            NetworkedObject {
+                owner: Some(player_id),
+                ..object
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Removes an object, if it is owned by `player_id` or has no owner.
+    pub fn despawn(&mut self, player_id: usize, id: u32) -> Result<(), ObjectError> {
+        let object = self.objects.get(&id).ok_or(ObjectError::NotFound)?;
+
+        if object.owner.is_some_and(|owner| owner != player_id) {
+            return Err(ObjectError::NotOwner);
+        }
+
+        self.objects.remove(&id);
+        self.orphaned.remove(&id);
+
+        Ok(())
+    }
+
+    /// Gives ownership of an object to `player_id`.
+    /// Returns whether the owner changed.
+    pub fn take_ownership(&mut self, player_id: usize, id: u32) -> Result<bool, ObjectError> {
+        let owned = self.owned(player_id).count();
+        let object = self.objects.get_mut(&id).ok_or(ObjectError::NotFound)?;
+
+        match object.owner {
+            Some(owner) if owner == player_id => return Ok(false),
+            Some(_) if !object.transferable => return Err(ObjectError::NotTransferable),
+            _ => {}
+        }
+
+        if owned >= MAX_OWNED_OBJECTS {
+            return Err(ObjectError::TooManyObjects);
+        }
+
+        object.owner = Some(player_id);
+        self.orphaned.remove(&id);
+
+        Ok(true)
+    }
+
+    /// Updates the state of an object owned by `player_id`.
+    pub fn set_state(
+        &mut self,
+        player_id: usize,
+        id: u32,
+        state: ObjectState,
+    ) -> Result<&NetworkedObject, ObjectError> {
+        let object = self.objects.get_mut(&id).ok_or(ObjectError::NotFound)?;
+
+        if object.owner != Some(player_id) {
+            return Err(ObjectError::NotOwner);
+        }
+
+        object.state = state;
+
+        Ok(object)
+    }
+
+    /// Removes `player_id` as the owner of its objects, returning their ids.
+    /// The objects are kept for the grace period, so others can take ownership.
+    pub fn release_all(&mut self, player_id: usize, now: Instant) -> Vec<u32> {
+        let ids = self.owned(player_id).collect::<Vec<_>>();
+
+        for id in ids.iter() {
+            if let Some(object) = self.objects.get_mut(id) {
+                object.owner = None;
+
+                if !object.persistent {
+                    self.orphaned.insert(*id, now);
+                }
+            }
+        }
+
+        ids
+    }
+
+    /// Removes objects that have been without an owner for the grace period,
+    /// returning their ids.
+    pub fn despawn_orphans(&mut self, now: Instant) -> Vec<u32> {
+        let ids = self
+            .orphaned
+            .iter()
+            .filter(|(_, since)| now.saturating_duration_since(**since) >= ORPHAN_GRACE_PERIOD)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in ids.iter() {
+            self.orphaned.remove(id);
+            self.objects.remove(id);
+        }
+
+        ids
+    }
+
+    fn owned(&self, player_id: usize) -> impl Iterator<Item = u32> + '_ {
+        self.objects
+            .iter()
+            .filter(move |(_, object)| object.owner == Some(player_id))
+            .map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(transferable: bool) -> NetworkedObject {
+        NetworkedObject {
+            data: Vec::new(),
+            owner: None,
+            persistent: false,
+            state: ObjectState::default(),
+            transferable,
+        }
+    }
+
+    #[test]
+    fn test_ownership() {
+        let mut objects = Objects::default();
+
+        objects.spawn(0, 1, object(true)).unwrap();
+        objects.spawn(0, 2, object(false)).unwrap();
+        assert_eq!(objects.get(1).unwrap().owner, Some(0));
+
+        assert_eq!(
+            objects.set_state(1, 1, ObjectState::default()).unwrap_err(),
+            ObjectError::NotOwner
+        );
+        assert_eq!(objects.take_ownership(1, 1), Ok(true));
+        assert_eq!(objects.take_ownership(1, 1), Ok(false));
+        assert!(objects.set_state(1, 1, ObjectState::default()).is_ok());
+        assert_eq!(
+            objects.set_state(0, 1, ObjectState::default()).unwrap_err(),
+            ObjectError::NotOwner
+        );
+
+        assert_eq!(
+            objects.take_ownership(1, 2),
+            Err(ObjectError::NotTransferable)
+        );
+        assert_eq!(objects.despawn(1, 2), Err(ObjectError::NotOwner));
+
+        // Released objects can be taken, even if not transferable.
+        assert_eq!(objects.release_all(0, Instant::now()), vec![2]);
+        assert_eq!(objects.take_ownership(1, 2), Ok(true));
+        assert_eq!(objects.despawn(1, 2), Ok(()));
+        assert_eq!(objects.take_ownership(1, 2), Err(ObjectError::NotFound));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut objects = Objects::default();
+
+        let large = NetworkedObject {
+            data: vec![0; MAX_OBJECT_DATA_LEN + 1],
+            ..object(true)
+        };
+        assert_eq!(objects.spawn(0, 0, large), Err(ObjectError::DataTooLarge));
+
+        for id in 0..MAX_OWNED_OBJECTS as u32 {
+            objects.spawn(0, id, object(true)).unwrap();
+        }
+        assert_eq!(
+            objects.spawn(0, u32::MAX, object(true)),
+            Err(ObjectError::TooManyObjects)
+        );
+
+        let mut objects = Objects::new(2);
+        objects.spawn(0, 0, object(true)).unwrap();
+        objects.spawn(1, 1, object(true)).unwrap();
+        assert_eq!(
+            objects.spawn(2, 2, object(true)),
+            Err(ObjectError::InstanceFull)
+        );
+    }
+
+    #[test]
+    fn test_despawn_orphans() {
+        let mut objects = Objects::default();
+        let now = Instant::now();
+
+        objects.spawn(0, 0, object(true)).unwrap();
+        objects.spawn(0, 1, object(true)).unwrap();
+        let persistent = NetworkedObject {
+            persistent: true,
+            ..object(true)
+        };
+        objects.spawn(0, 2, persistent).unwrap();
+        objects.release_all(0, now);
+
+        // Taken by another player within the grace period.
+        assert_eq!(objects.take_ownership(1, 1), Ok(true));
+
+        assert!(objects
+            .despawn_orphans(now + Duration::from_secs(1))
+            .is_empty());
+
+        let later = now + ORPHAN_GRACE_PERIOD;
+        assert_eq!(objects.despawn_orphans(later), vec![0]);
+        assert!(objects.get(0).is_none());
+        assert!(objects.get(1).is_some());
+        assert!(objects.get(2).is_some());
+
+        // Orphaned again, so the grace period restarts.
+        objects.release_all(1, later);
+        assert!(objects.despawn_orphans(later).is_empty());
+        assert_eq!(
+            objects.despawn_orphans(later + ORPHAN_GRACE_PERIOD),
+            vec![1]
+        );
+    }
+}
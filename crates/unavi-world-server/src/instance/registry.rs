@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::{
+    runtime::Handle,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        watch,
+    },
+};
+use tracing::{info_span, Instrument};
+use wired_social::protocols::world_host::Mutes;
+
+use crate::{clock::ServerClock, metrics::ServerMetrics};
+
+use super::{run_instance, InstanceCommand, InstanceOptions};
+
+/// Spawns instance tasks on demand, and routes players to them.
+pub struct InstanceRegistry {
+    clock: ServerClock,
+    /// Updated with the number of players in each instance.
+    instance_players: Option<Arc<watch::Sender<HashMap<String, usize>>>>,
+    instances: Mutex<HashMap<String, RunningInstance>>,
+    metrics: Arc<ServerMetrics>,
+    /// Muted players can still move, but their chat and voice are not relayed.
+    mutes: watch::Receiver<Mutes>,
+    next_object_id: AtomicU32,
+    /// Default options for instances without an override.
+    opts: InstanceOptions,
+    /// Options for specific instances, keyed by instance record id.
+    overrides: Mutex<HashMap<String, InstanceOptions>>,
+    runtime: Handle,
+}
+
+struct RunningInstance {
+    /// Options the instance was started with.
+    opts: InstanceOptions,
+    sender: UnboundedSender<InstanceCommand>,
+}
+
+impl InstanceRegistry {
+    /// Instance tasks are spawned onto `runtime`.
+    pub fn new(
+        opts: InstanceOptions,
+        instance_players: Option<Arc<watch::Sender<HashMap<String, usize>>>>,
+        metrics: Arc<ServerMetrics>,
+        mutes: watch::Receiver<Mutes>,
+        runtime: Handle,
+    ) -> Self {
+        Self {
+            clock: ServerClock::new(),
+            instance_players,
+            instances: Mutex::default(),
+            metrics,
+            mutes,
+            next_object_id: AtomicU32::default(),
+            opts,
+            overrides: Mutex::default(),
+            runtime,
+        }
+    }
+
+    /// Options for instances without an override.
+    pub fn default_options(&self) -> &InstanceOptions {
+        &self.opts
+    }
+
+    /// Options of an instance.
+    /// If it is running, these are the options it was started with,
+    /// otherwise the options it will start with.
+    pub fn options(&self, id: &str) -> InstanceOptions {
+        if let Some(instance) = self.instances.lock().unwrap().get(id) {
+            if !instance.sender.is_closed() {
+                return instance.opts.clone();
+            }
+        }
+
+        self.next_options(id)
+    }
+
+    /// Options an instance will start with.
+    fn next_options(&self, id: &str) -> InstanceOptions {
+        self.overrides
+            .lock()
+            .unwrap()
+            .get(id)
+            .unwrap_or(&self.opts)
+            .clone()
+    }
+
+    /// Overrides the default options of an instance, or removes its override if `None`.
+    /// Takes effect the next time the instance starts.
+    /// Returns whether the instance had an override.
+    pub fn set_options(&self, id: &str, opts: Option<InstanceOptions>) -> bool {
+        let mut overrides = self.overrides.lock().unwrap();
+
+        match opts {
+            Some(opts) => overrides.insert(id.to_string(), opts),
+            None => overrides.remove(id),
+        }
+        .is_some()
+    }
+
+    /// Time since the server started, which instance ticks are numbered from.
+    pub fn clock(&self) -> &ServerClock {
+        &self.clock
+    }
+
+    pub(super) fn metrics(&self) -> &ServerMetrics {
+        &self.metrics
+    }
+
+    pub(super) fn mutes(&self) -> watch::Receiver<Mutes> {
+        self.mutes.clone()
+    }
+
+    /// Allocates an id for a networked object.
+    /// Ids are unique across instances, so datagrams do not need to specify an instance.
+    pub fn next_object_id(&self) -> u32 {
+        self.next_object_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Gets the command channel of a running instance.
+    pub fn get(&self, id: &str) -> Option<UnboundedSender<InstanceCommand>> {
+        self.instances
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|instance| !instance.sender.is_closed())
+            .map(|instance| instance.sender.clone())
+    }
+
+    /// Command channels of all running instances, keyed by instance record id.
+    pub fn running(&self) -> Vec<(String, UnboundedSender<InstanceCommand>)> {
+        self.instances
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, instance)| !instance.sender.is_closed())
+            .map(|(id, instance)| (id.clone(), instance.sender.clone()))
+            .collect()
+    }
+
+    /// Gets the command channel of an instance, starting it if needed.
+    pub fn get_or_spawn(self: &Arc<Self>, id: &str) -> UnboundedSender<InstanceCommand> {
+        let mut instances = self.instances.lock().unwrap();
+
+        if let Some(instance) = instances.get(id) {
+            if !instance.sender.is_closed() {
+                return instance.sender.clone();
+            }
+        }
+
+        let opts = self.next_options(id);
+
+        let (sender, receiver) = unbounded_channel();
+        instances.insert(
+            id.to_string(),
+            RunningInstance {
+                opts: opts.clone(),
+                sender: sender.clone(),
+            },
+        );
+
+        self.runtime.spawn(
+            run_instance(id.to_string(), receiver, self.clone(), opts)
+                .instrument(info_span!("Instance", id)),
+        );
+
+        sender
+    }
+
+    /// Removes an instance, unless it has pending commands.
+    /// Returns whether the instance was closed.
+    pub(super) fn close(
+        &self,
+        id: &str,
+        receiver: &mut UnboundedReceiver<InstanceCommand>,
+    ) -> bool {
+        let mut instances = self.instances.lock().unwrap();
+
+        // Holding the lock prevents new senders being handed out, but existing ones
+        // may still send before the receiver closes. Those are rejected by the task.
+        if !receiver.is_empty() {
+            return false;
+        }
+
+        self.remove(&mut instances, id, receiver);
+        true
+    }
+
+    /// Removes an instance, even if it has pending commands.
+    pub(super) fn force_close(&self, id: &str, receiver: &mut UnboundedReceiver<InstanceCommand>) {
+        let mut instances = self.instances.lock().unwrap();
+        self.remove(&mut instances, id, receiver);
+    }
+
+    fn remove(
+        &self,
+        instances: &mut HashMap<String, RunningInstance>,
+        id: &str,
+        receiver: &mut UnboundedReceiver<InstanceCommand>,
+    ) {
+        receiver.close();
+        instances.remove(id);
+
+        if let Some(instance_players) = &self.instance_players {
+            instance_players.send_if_modified(|counts| counts.remove(id).is_some());
+        }
+    }
+
+    pub(super) fn set_player_count(&self, id: &str, count: usize) {
+        if let Some(instance_players) = &self.instance_players {
+            instance_players.send_if_modified(|counts| {
+                if counts.get(id) == Some(&count) {
+                    false
+                } else {
+                    counts.insert(id.to_string(), count);
+                    true
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{runtime::Builder, sync::oneshot};
+
+    use crate::instance::{JoinInstanceError, OutgoingEvent, PlayerInfo, Transform};
+
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let registry = InstanceRegistry::new(
+            InstanceOptions::default(),
+            None,
+            Arc::default(),
+            watch::channel(Mutes::default()).1,
+            runtime.handle().clone(),
+        );
+
+        assert_eq!(registry.options("a").max_players, None);
+
+        let opts = InstanceOptions {
+            max_players: Some(4),
+            tickrate: 0.1,
+            ..Default::default()
+        };
+        assert!(!registry.set_options("a", Some(opts)));
+
+        assert_eq!(registry.options("a").max_players, Some(4));
+        assert_eq!(registry.options("a").tickrate, 0.1);
+        assert_eq!(registry.options("b").max_players, None);
+
+        assert!(registry.set_options("a", None));
+        assert!(!registry.set_options("a", None));
+        assert_eq!(registry.options("a").max_players, None);
+    }
+
+    #[test]
+    fn test_player_count() {
+        let runtime = Builder::new_current_thread().enable_time().build().unwrap();
+        let (instance_players, mut counts) = watch::channel(HashMap::default());
+        let registry = Arc::new(InstanceRegistry::new(
+            InstanceOptions {
+                max_players: Some(1),
+                ..Default::default()
+            },
+            Some(Arc::new(instance_players)),
+            Arc::default(),
+            watch::channel(Mutes::default()).1,
+            runtime.handle().clone(),
+        ));
+
+        runtime.block_on(async {
+            let instance = registry.get_or_spawn("a");
+
+            let join = |player_id| {
+                let (result, joined) = oneshot::channel();
+                let (sender, events) = unbounded_channel::<OutgoingEvent>();
+                instance
+                    .send(InstanceCommand::Join {
+                        info: PlayerInfo::default(),
+                        player_id,
+                        result,
+                        sender,
+                        transform: Transform::default(),
+                    })
+                    .unwrap();
+                (joined, events)
+            };
+
+            let (joined, _events) = join(0);
+            assert!(joined.await.unwrap().is_ok());
+            counts.changed().await.unwrap();
+            assert_eq!(counts.borrow_and_update().get("a"), Some(&1));
+
+            // Rejected joins are not counted.
+            let (joined, _) = join(1);
+            assert!(matches!(
+                joined.await.unwrap(),
+                Err(JoinInstanceError::Full)
+            ));
+            assert!(!counts.has_changed().unwrap());
+
+            instance
+                .send(InstanceCommand::Leave { player_id: 0 })
+                .unwrap();
+            counts.changed().await.unwrap();
+            assert_eq!(counts.borrow_and_update().get("a"), Some(&0));
+        });
+    }
+}
@@ -0,0 +1,1113 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use tokio::sync::{mpsc::UnboundedSender, watch};
+use tracing::debug;
+use wired_social::protocols::world_host::Mutes;
+
+use crate::{
+    chat::ChatError,
+    interest::{is_relevant, InterestSettings, SpatialGrid},
+};
+
+use super::{
+    objects::{NetworkedObject, ObjectError, ObjectState, Objects},
+    AnimationState, InstanceCommand, JoinInstanceError, OutgoingEvent, PlayerInfo, PlayerSummary,
+    Pose, Transform, VoiceFrame,
+};
+
+/// Simulation state of a single instance.
+pub struct InstanceState {
+    /// Player positions as of the last tick.
+    grid: SpatialGrid,
+    /// Instance record id.
+    id: String,
+    interest: InterestSettings,
+    max_players: Option<usize>,
+    /// Muted DIDs, shared with the admin API and stored by the world host.
+    mutes: watch::Receiver<Mutes>,
+    objects: Objects,
+    /// Origin for quantizing player translations, so they stay small near the instance.
+    /// Set by the first player to join.
+    origin: Option<[f32; 3]>,
+    players: HashMap<usize, Player>,
+    /// Current tick, see [crate::clock::InstanceClock].
+    tick: u32,
+}
+
+struct Player {
+    info: PlayerInfo,
+    sender: UnboundedSender<OutgoingEvent>,
+    transform: Transform,
+}
+
+impl InstanceState {
+    pub fn new(
+        id: String,
+        interest: InterestSettings,
+        max_players: Option<usize>,
+        max_objects: usize,
+        mutes: watch::Receiver<Mutes>,
+    ) -> Self {
+        Self {
+            grid: SpatialGrid::new(interest.cell_size),
+            id,
+            interest,
+            max_players,
+            mutes,
+            objects: Objects::new(max_objects),
+            origin: None,
+            players: HashMap::default(),
+            tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    pub fn handle(&mut self, command: InstanceCommand) {
+        match command {
+            InstanceCommand::Close { reason } => {
+                let ids = self.players.keys().copied().collect::<Vec<_>>();
+
+                for id in ids {
+                    self.kick(id, &reason);
+                }
+            }
+            InstanceCommand::DespawnObject {
+                id,
+                player_id,
+                result,
+            } => {
+                let res = self.despawn_object(player_id, id);
+
+                if result.send(res).is_err() {
+                    debug!("Despawn request dropped.");
+                }
+            }
+            InstanceCommand::GetPlayer {
+                id,
+                player_id,
+                sender,
+            } => {
+                let info = if self.players.contains_key(&player_id) {
+                    self.players.get(&id).map(|player| player.info.clone())
+                } else {
+                    None
+                };
+
+                if sender.send(info).is_err() {
+                    debug!("Player request dropped.");
+                }
+            }
+            InstanceCommand::GetPlayers { player_id, sender } => {
+                let infos = if self.players.contains_key(&player_id) {
+                    self.players
+                        .iter()
+                        .filter(|(id, _)| **id != player_id)
+                        .map(|(id, player)| (*id, player.info.clone()))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
+                if sender.send(infos).is_err() {
+                    debug!("Players request dropped.");
+                }
+            }
+            InstanceCommand::Join {
+                info,
+                player_id,
+                result,
+                sender,
+                transform,
+            } => {
+                let res = self.join(player_id, info, transform, sender);
+
+                if result.send(res).is_err() {
+                    debug!("Join request dropped.");
+                }
+            }
+            InstanceCommand::Kick { player_id, reason } => self.kick(player_id, &reason),
+            InstanceCommand::KickDid { did, reason } => {
+                let ids = self
+                    .players
+                    .iter()
+                    .filter(|(_, player)| player.info.did.as_deref() == Some(did.as_str()))
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>();
+
+                for id in ids {
+                    self.kick(id, &reason);
+                }
+            }
+            InstanceCommand::Leave { player_id } => self.leave(player_id),
+            InstanceCommand::ListPlayers { sender } => {
+                let players = self
+                    .players
+                    .iter()
+                    .map(|(id, player)| PlayerSummary {
+                        id: *id,
+                        info: player.info.clone(),
+                        muted: self.is_muted(*id),
+                    })
+                    .collect();
+
+                if sender.send(players).is_err() {
+                    debug!("Players request dropped.");
+                }
+            }
+            InstanceCommand::RequestOwnership {
+                id,
+                player_id,
+                result,
+            } => {
+                let res = self.request_ownership(player_id, id);
+
+                if result.send(res).is_err() {
+                    debug!("Ownership request dropped.");
+                }
+            }
+            InstanceCommand::SendMessage {
+                player_id,
+                result,
+                text,
+            } => {
+                let res = self.send_message(player_id, text);
+
+                if result.send(res).is_err() {
+                    debug!("Message request dropped.");
+                }
+            }
+            InstanceCommand::SetObjectState {
+                id,
+                player_id,
+                state,
+            } => self.set_object_state(player_id, id, state),
+            InstanceCommand::SetPlayerInfo { info, player_id } => {
+                self.set_player_info(player_id, info)
+            }
+            InstanceCommand::SetTransform {
+                player_id,
+                transform,
+            } => self.set_transform(player_id, transform),
+            InstanceCommand::SetPose { player_id, pose } => self.relay_pose(player_id, pose),
+            InstanceCommand::SetAnimation { player_id, state } => {
+                self.relay_animation(player_id, state)
+            }
+            InstanceCommand::SpawnObject {
+                id,
+                object,
+                player_id,
+                result,
+            } => {
+                let res = self.spawn_object(player_id, id, object);
+
+                if result.send(res).is_err() {
+                    debug!("Spawn request dropped.");
+                }
+            }
+            InstanceCommand::Voice { player_id, frame } => self.relay_voice(player_id, frame),
+        }
+    }
+
+    pub fn join(
+        &mut self,
+        player_id: usize,
+        info: PlayerInfo,
+        transform: Transform,
+        sender: UnboundedSender<OutgoingEvent>,
+    ) -> Result<(), JoinInstanceError> {
+        if self.players.contains_key(&player_id) {
+            return Ok(());
+        }
+
+        if let Some(max) = self.max_players {
+            if self.players.len() >= max {
+                return Err(JoinInstanceError::Full);
+            }
+        }
+
+        self.origin
+            .get_or_insert_with(|| transform.translation.map(f32::round));
+
+        for (other_id, other) in self.players.iter() {
+            send(
+                &other.sender,
+                OutgoingEvent::PlayerJoined {
+                    id: player_id,
+                    info: info.clone(),
+                },
+            );
+            send(
+                &sender,
+                OutgoingEvent::PlayerJoined {
+                    id: *other_id,
+                    info: other.info.clone(),
+                },
+            );
+        }
+
+        for (id, object) in self.objects.iter() {
+            send(
+                &sender,
+                OutgoingEvent::ObjectSpawned {
+                    instance: self.id.clone(),
+                    id,
+                    object: object.clone(),
+                },
+            );
+        }
+
+        self.players.insert(
+            player_id,
+            Player {
+                info,
+                sender,
+                transform,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn leave(&mut self, player_id: usize) {
+        let Some(player) = self.players.remove(&player_id) else {
+            return;
+        };
+
+        for (other_id, other) in self.players.iter() {
+            send(&other.sender, OutgoingEvent::PlayerLeft { id: player_id });
+            send(&player.sender, OutgoingEvent::PlayerLeft { id: *other_id });
+        }
+
+        // Objects stay in the instance for a while, for other players to take.
+        for id in self.objects.release_all(player_id, Instant::now()) {
+            self.broadcast(None, || OutgoingEvent::ObjectOwnerChanged {
+                instance: self.id.clone(),
+                id,
+                owner: None,
+            });
+        }
+    }
+
+    /// Removes a player, telling them why.
+    pub fn kick(&mut self, player_id: usize, reason: &str) {
+        let Some(sender) = self.players.get(&player_id).map(|p| p.sender.clone()) else {
+            return;
+        };
+
+        self.leave(player_id);
+
+        send(
+            &sender,
+            OutgoingEvent::Kicked {
+                instance: self.id.clone(),
+                reason: reason.to_string(),
+            },
+        );
+    }
+
+    /// Mutes are keyed by DID, so they apply across connections.
+    /// Players without a DID cannot be muted.
+    pub fn is_muted(&self, player_id: usize) -> bool {
+        self.players
+            .get(&player_id)
+            .and_then(|player| player.info.did.as_deref())
+            .is_some_and(|did| self.mutes.borrow().contains(did, &self.id))
+    }
+
+    /// Relays a chat message to every other player.
+    pub fn send_message(&self, player_id: usize, text: String) -> Result<(), ChatError> {
+        let player = self.players.get(&player_id).ok_or(ChatError::NotJoined)?;
+
+        if self.is_muted(player_id) {
+            return Err(ChatError::Muted);
+        }
+
+        for (other_id, other) in self.players.iter() {
+            if *other_id != player_id {
+                send(
+                    &other.sender,
+                    OutgoingEvent::ChatMessage {
+                        instance: self.id.clone(),
+                        player: player_id,
+                        did: player.info.did.clone(),
+                        text: text.clone(),
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_player_info(&mut self, player_id: usize, info: PlayerInfo) {
+        let Some(player) = self.players.get_mut(&player_id) else {
+            return;
+        };
+
+        player.info = info.clone();
+
+        for (other_id, other) in self.players.iter() {
+            if *other_id != player_id {
+                send(
+                    &other.sender,
+                    OutgoingEvent::PlayerUpdated {
+                        id: player_id,
+                        info: info.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn set_transform(&mut self, player_id: usize, transform: Transform) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.transform = transform;
+        }
+    }
+
+    pub fn spawn_object(
+        &mut self,
+        player_id: usize,
+        id: u32,
+        object: NetworkedObject,
+    ) -> Result<(), ObjectError> {
+        if !self.players.contains_key(&player_id) {
+            return Err(ObjectError::NotJoined);
+        }
+
+        self.objects.spawn(player_id, id, object)?;
+
+        let object = self.objects.get(id).cloned().ok_or(ObjectError::NotFound)?;
+
+        self.broadcast(Some(player_id), || OutgoingEvent::ObjectSpawned {
+            instance: self.id.clone(),
+            id,
+            object: object.clone(),
+        });
+
+        Ok(())
+    }
+
+    pub fn despawn_object(&mut self, player_id: usize, id: u32) -> Result<(), ObjectError> {
+        if !self.players.contains_key(&player_id) {
+            return Err(ObjectError::NotJoined);
+        }
+
+        self.objects.despawn(player_id, id)?;
+
+        self.broadcast(Some(player_id), || OutgoingEvent::ObjectDespawned {
+            instance: self.id.clone(),
+            id,
+        });
+
+        Ok(())
+    }
+
+    pub fn request_ownership(&mut self, player_id: usize, id: u32) -> Result<(), ObjectError> {
+        if !self.players.contains_key(&player_id) {
+            return Err(ObjectError::NotJoined);
+        }
+
+        if self.objects.take_ownership(player_id, id)? {
+            self.broadcast(Some(player_id), || OutgoingEvent::ObjectOwnerChanged {
+                instance: self.id.clone(),
+                id,
+                owner: Some(player_id),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Relays an object's state to players near it.
+    pub fn set_object_state(&mut self, player_id: usize, id: u32, state: ObjectState) {
+        let translation = state.transform.translation;
+
+        if let Err(e) = self.objects.set_state(player_id, id, state.clone()) {
+            debug!("Ignoring state for object {}: {}", id, e);
+            return;
+        }
+
+        let nearby = self.grid.query(translation, self.interest.far_distance);
+
+        for (other_id, _) in nearby {
+            if other_id == player_id {
+                continue;
+            }
+
+            if let Some(other) = self.players.get(&other_id) {
+                send(
+                    &other.sender,
+                    OutgoingEvent::ObjectState {
+                        id,
+                        state: state.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Sends an event to every player, except `exclude`.
+    fn broadcast(&self, exclude: Option<usize>, event: impl Fn() -> OutgoingEvent) {
+        for (id, player) in self.players.iter() {
+            if Some(*id) != exclude {
+                send(&player.sender, event());
+            }
+        }
+    }
+
+    /// Sends a voice frame to players within hearing distance.
+    pub fn relay_voice(&self, player_id: usize, frame: VoiceFrame) {
+        if self.is_muted(player_id) {
+            return;
+        }
+
+        self.send_nearby(player_id, self.interest.voice_distance, || {
+            OutgoingEvent::Voice {
+                player: player_id,
+                frame: frame.clone(),
+            }
+        });
+    }
+
+    /// Sends a pose to players close enough to see it.
+    pub fn relay_pose(&self, player_id: usize, pose: Arc<Pose>) {
+        self.send_nearby(player_id, self.interest.near_distance, || {
+            OutgoingEvent::Pose {
+                player: player_id,
+                pose: pose.clone(),
+            }
+        });
+    }
+
+    /// Sends an animation state to players that can see the avatar.
+    pub fn relay_animation(&self, player_id: usize, state: AnimationState) {
+        self.send_nearby(player_id, self.interest.far_distance, || {
+            OutgoingEvent::Animation {
+                player: player_id,
+                state,
+            }
+        });
+    }
+
+    /// Sends an event to other players within `distance` of a player.
+    /// Uses positions from the last tick, like transforms.
+    fn send_nearby(&self, player_id: usize, distance: f32, event: impl Fn() -> OutgoingEvent) {
+        let Some(player) = self.players.get(&player_id) else {
+            return;
+        };
+
+        let nearby = self.grid.query(player.transform.translation, distance);
+
+        for (other_id, _) in nearby {
+            if other_id == player_id {
+                continue;
+            }
+
+            // May have left since the last tick.
+            if let Some(other) = self.players.get(&other_id) {
+                send(&other.sender, event());
+            }
+        }
+    }
+
+    /// Sends each player the transforms of nearby players.
+    pub fn tick(&mut self, tick: u32) {
+        self.tick = tick;
+
+        // Connections leave when they close, but may have raced with joining.
+        let closed = self
+            .players
+            .iter()
+            .filter(|(_, player)| player.sender.is_closed())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in closed {
+            self.leave(id);
+        }
+
+        for id in self.objects.despawn_orphans(Instant::now()) {
+            self.broadcast(None, || OutgoingEvent::ObjectDespawned {
+                instance: self.id.clone(),
+                id,
+            });
+        }
+
+        self.grid = SpatialGrid::new(self.interest.cell_size);
+
+        for (id, player) in self.players.iter() {
+            self.grid.insert(*id, player.transform.translation);
+        }
+
+        let origin = self.origin.unwrap_or_default();
+        let relevant = self.relevant_players();
+
+        for (id, ids) in relevant {
+            if ids.is_empty() {
+                continue;
+            }
+
+            let transforms = ids
+                .into_iter()
+                .map(|other_id| (other_id, self.players[&other_id].transform.clone()))
+                .collect();
+
+            send(
+                &self.players[&id].sender,
+                OutgoingEvent::Transforms {
+                    origin,
+                    tick,
+                    transforms,
+                },
+            );
+        }
+    }
+
+    /// Finds which players' transforms should be sent to each player this tick.
+    fn relevant_players(&self) -> Vec<(usize, Vec<usize>)> {
+        self.players
+            .iter()
+            .map(|(id, player)| {
+                let ids = self
+                    .grid
+                    .query(player.transform.translation, self.interest.far_distance)
+                    .into_iter()
+                    .filter(|(other_id, distance_squared)| {
+                        other_id != id
+                            && is_relevant(&self.interest, *distance_squared, *other_id, self.tick)
+                    })
+                    .map(|(other_id, _)| other_id)
+                    .collect();
+
+                (*id, ids)
+            })
+            .collect()
+    }
+}
+
+/// Sends an event to a player.
+/// Failures are ignored, as the player's connection will leave the instance when it closes.
+fn send(sender: &UnboundedSender<OutgoingEvent>, event: OutgoingEvent) {
+    if sender.send(event).is_err() {
+        debug!("Dropped event for closed connection.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use crate::instance::objects::MAX_INSTANCE_OBJECTS;
+
+    use super::*;
+
+    fn no_mutes() -> watch::Receiver<Mutes> {
+        watch::channel(Mutes::default()).1
+    }
+
+    fn join(
+        state: &mut InstanceState,
+        id: usize,
+        translation: [f32; 3],
+    ) -> Result<UnboundedReceiver<OutgoingEvent>, JoinInstanceError> {
+        let (sender, receiver) = unbounded_channel();
+        let transform = Transform {
+            translation,
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        };
+        state.join(id, PlayerInfo::default(), transform, sender)?;
+        Ok(receiver)
+    }
+
+    fn transform_ids(receiver: &mut UnboundedReceiver<OutgoingEvent>) -> Vec<usize> {
+        let mut ids = Vec::new();
+
+        while let Ok(event) = receiver.try_recv() {
+            if let OutgoingEvent::Transforms { transforms, .. } = event {
+                ids.extend(transforms.into_iter().map(|(id, _)| id));
+            }
+        }
+
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_join_leave() {
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            Some(2),
+            MAX_INSTANCE_OBJECTS,
+            no_mutes(),
+        );
+
+        let mut recv_a = join(&mut state, 0, [1.4, 0.0, 0.0]).unwrap();
+        let mut recv_b = join(&mut state, 1, [0.0; 3]).unwrap();
+        assert!(matches!(
+            join(&mut state, 2, [0.0; 3]),
+            Err(JoinInstanceError::Full)
+        ));
+
+        assert_eq!(state.origin, Some([1.0, 0.0, 0.0]));
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::PlayerJoined { id: 1, .. })
+        ));
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::PlayerJoined { id: 0, .. })
+        ));
+
+        state.leave(1);
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::PlayerLeft { id: 1 })
+        ));
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::PlayerLeft { id: 0 })
+        ));
+
+        // A closed connection does not affect other players.
+        drop(recv_a);
+        let mut recv_c = join(&mut state, 2, [0.0; 3]).unwrap();
+        assert!(matches!(
+            recv_c.try_recv(),
+            Ok(OutgoingEvent::PlayerJoined { id: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_max_players() {
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            Some(3),
+            MAX_INSTANCE_OBJECTS,
+            no_mutes(),
+        );
+
+        let _recv = (0..3)
+            .map(|id| join(&mut state, id, [0.0; 3]).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(state.len(), 3);
+
+        assert!(matches!(
+            join(&mut state, 3, [0.0; 3]),
+            Err(JoinInstanceError::Full)
+        ));
+        assert_eq!(state.len(), 3);
+
+        // Players already in the instance can join again.
+        assert!(join(&mut state, 2, [0.0; 3]).is_ok());
+
+        state.leave(0);
+        assert_eq!(state.len(), 2);
+        assert!(join(&mut state, 3, [0.0; 3]).is_ok());
+        assert_eq!(state.len(), 3);
+    }
+
+    #[test]
+    fn test_kick() {
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            None,
+            MAX_INSTANCE_OBJECTS,
+            no_mutes(),
+        );
+
+        let mut recv_a = join(&mut state, 0, [0.0; 3]).unwrap();
+        let mut recv_b = join(&mut state, 1, [0.0; 3]).unwrap();
+        recv_a.try_recv().unwrap();
+        recv_b.try_recv().unwrap();
+
+        state.kick(1, "reason");
+
+        assert_eq!(state.len(), 1);
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::PlayerLeft { id: 1 })
+        ));
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::PlayerLeft { id: 0 })
+        ));
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::Kicked { reason, .. }) if reason == "reason"
+        ));
+
+        state.handle(InstanceCommand::Close {
+            reason: String::new(),
+        });
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_send_message() {
+        let (mutes, receiver) = watch::channel(Mutes::default());
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            None,
+            MAX_INSTANCE_OBJECTS,
+            receiver,
+        );
+
+        let mut recv_a = join(&mut state, 0, [0.0; 3]).unwrap();
+        let mut recv_b = join(&mut state, 1, [0.0; 3]).unwrap();
+        recv_a.try_recv().unwrap();
+        recv_b.try_recv().unwrap();
+
+        state.send_message(0, "hello".to_string()).unwrap();
+        assert!(recv_a.try_recv().is_err());
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::ChatMessage { player: 0, text, .. }) if text == "hello"
+        ));
+
+        // Players without a DID cannot be muted.
+        mutes.send_modify(|mutes| {
+            mutes.insert("did:example:a", None);
+        });
+        state.send_message(0, "hello".to_string()).unwrap();
+        assert!(recv_b.try_recv().is_ok());
+
+        // Mutes follow the DID, so reconnecting as a new player does not unmute.
+        let info = PlayerInfo {
+            did: Some("did:example:a".to_string()),
+            ..Default::default()
+        };
+        let (sender, _recv_c) = unbounded_channel();
+        state.join(3, info, Transform::default(), sender).unwrap();
+        recv_b.try_recv().unwrap();
+
+        assert_eq!(
+            state.send_message(3, "hello".to_string()),
+            Err(ChatError::Muted)
+        );
+        assert_eq!(
+            state.send_message(2, "hello".to_string()),
+            Err(ChatError::NotJoined)
+        );
+        assert!(recv_b.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_tick_interest() {
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            None,
+            MAX_INSTANCE_OBJECTS,
+            no_mutes(),
+        );
+
+        let mut recv_a = join(&mut state, 0, [0.0; 3]).unwrap();
+        let mut recv_b = join(&mut state, 1, [10.0, 0.0, 0.0]).unwrap();
+        let mut recv_c = join(&mut state, 2, [1000.0, 0.0, 0.0]).unwrap();
+
+        state.tick(1);
+
+        assert_eq!(transform_ids(&mut recv_a), vec![1]);
+        assert_eq!(transform_ids(&mut recv_b), vec![0]);
+        assert!(transform_ids(&mut recv_c).is_empty());
+
+        // Stamped with the tick from the instance clock.
+        state.tick(5);
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::Transforms { tick: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_relay_voice() {
+        let (mutes, receiver) = watch::channel(Mutes::default());
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            None,
+            MAX_INSTANCE_OBJECTS,
+            receiver,
+        );
+
+        let mut recv_a = join(&mut state, 0, [0.0; 3]).unwrap();
+        state.set_player_info(
+            0,
+            PlayerInfo {
+                did: Some("did:example:a".to_string()),
+                ..Default::default()
+            },
+        );
+        let mut recv_b = join(&mut state, 1, [10.0, 0.0, 0.0]).unwrap();
+        let mut recv_c = join(&mut state, 2, [1000.0, 0.0, 0.0]).unwrap();
+        state.tick(1);
+
+        for receiver in [&mut recv_a, &mut recv_b, &mut recv_c] {
+            while receiver.try_recv().is_ok() {}
+        }
+
+        let voice_ids = |receiver: &mut UnboundedReceiver<OutgoingEvent>| {
+            let mut ids = Vec::new();
+            while let Ok(event) = receiver.try_recv() {
+                if let OutgoingEvent::Voice { player, frame } = event {
+                    assert_eq!(&*frame.data, &[1, 2, 3]);
+                    ids.push(player);
+                }
+            }
+            ids
+        };
+
+        let frame = VoiceFrame {
+            sequence: 0,
+            data: Arc::from([1, 2, 3].as_slice()),
+        };
+
+        state.relay_voice(0, frame.clone());
+        assert!(voice_ids(&mut recv_a).is_empty());
+        assert_eq!(voice_ids(&mut recv_b), vec![0]);
+        assert!(voice_ids(&mut recv_c).is_empty());
+
+        mutes.send_modify(|mutes| {
+            mutes.insert("did:example:a", Some(""));
+        });
+        state.relay_voice(0, frame);
+        assert!(voice_ids(&mut recv_b).is_empty());
+    }
+
+    #[test]
+    fn test_relay_pose() {
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            None,
+            MAX_INSTANCE_OBJECTS,
+            no_mutes(),
+        );
+
+        let mut recv_a = join(&mut state, 0, [0.0; 3]).unwrap();
+        let mut recv_b = join(&mut state, 1, [10.0, 0.0, 0.0]).unwrap();
+        let mut recv_c = join(&mut state, 2, [100.0, 0.0, 0.0]).unwrap();
+        state.tick(1);
+
+        for receiver in [&mut recv_a, &mut recv_b, &mut recv_c] {
+            while receiver.try_recv().is_ok() {}
+        }
+
+        let pose = Arc::new(Pose {
+            bones: vec![(5, 1)],
+            ..Default::default()
+        });
+        state.relay_pose(0, pose.clone());
+
+        assert!(recv_a.try_recv().is_err());
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::Pose { player: 0, pose: p }) if p == pose
+        ));
+        // Beyond the near distance.
+        assert!(recv_c.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_relay_animation() {
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            None,
+            MAX_INSTANCE_OBJECTS,
+            no_mutes(),
+        );
+
+        let mut recv_a = join(&mut state, 0, [0.0; 3]).unwrap();
+        let mut recv_b = join(&mut state, 1, [100.0, 0.0, 0.0]).unwrap();
+        let mut recv_c = join(&mut state, 2, [10_000.0, 0.0, 0.0]).unwrap();
+        state.tick(1);
+
+        for receiver in [&mut recv_a, &mut recv_b, &mut recv_c] {
+            while receiver.try_recv().is_ok() {}
+        }
+
+        let animation = AnimationState {
+            falling: true,
+            velocity: [0, -500, 0],
+            ..Default::default()
+        };
+        state.relay_animation(0, animation);
+
+        assert!(recv_a.try_recv().is_err());
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::Animation { player: 0, state: s }) if s == animation
+        ));
+        // Beyond the far distance.
+        assert!(recv_c.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_objects() {
+        let mut state = InstanceState::new(
+            String::new(),
+            InterestSettings::default(),
+            None,
+            MAX_INSTANCE_OBJECTS,
+            no_mutes(),
+        );
+
+        let mut recv_a = join(&mut state, 0, [0.0; 3]).unwrap();
+        let mut recv_b = join(&mut state, 1, [0.0; 3]).unwrap();
+        state.tick(1);
+
+        for receiver in [&mut recv_a, &mut recv_b] {
+            while receiver.try_recv().is_ok() {}
+        }
+
+        let object = NetworkedObject {
+            data: vec![1],
+            owner: None,
+            persistent: false,
+            state: ObjectState::default(),
+            transferable: true,
+        };
+        state.spawn_object(0, 7, object).unwrap();
+        assert!(recv_a.try_recv().is_err());
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::ObjectSpawned { id: 7, object, .. }) if object.owner == Some(0)
+        ));
+
+        // Only the owner's state is relayed.
+        state.set_object_state(1, 7, ObjectState::default());
+        assert!(recv_a.try_recv().is_err());
+        state.set_object_state(0, 7, ObjectState::default());
+        assert!(matches!(
+            recv_b.try_recv(),
+            Ok(OutgoingEvent::ObjectState { id: 7, .. })
+        ));
+
+        state.request_ownership(1, 7).unwrap();
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::ObjectOwnerChanged {
+                id: 7,
+                owner: Some(1),
+                ..
+            })
+        ));
+
+        // Late joiners receive existing objects.
+        let mut recv_c = join(&mut state, 2, [0.0; 3]).unwrap();
+        let spawned = std::iter::from_fn(|| recv_c.try_recv().ok())
+            .filter(|event| matches!(event, OutgoingEvent::ObjectSpawned { id: 7, .. }))
+            .count();
+        assert_eq!(spawned, 1);
+        while recv_a.try_recv().is_ok() {}
+
+        // Ownership is released when the owner leaves.
+        state.leave(1);
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::PlayerLeft { id: 1 })
+        ));
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::ObjectOwnerChanged {
+                id: 7,
+                owner: None,
+                ..
+            })
+        ));
+
+        assert_eq!(state.despawn_object(2, 7), Ok(()));
+        assert!(matches!(
+            recv_a.try_recv(),
+            Ok(OutgoingEvent::ObjectDespawned { id: 7, .. })
+        ));
+    }
+
+    /// Benchmark harness, driving synthetic players through the instance update logic.
+    /// Run with `cargo test -p unavi-world-server --release -- --ignored --nocapture bench`.
+    #[test]
+    #[ignore]
+    fn bench_tick() {
+        const TICKS: u32 = 20;
+
+        for (count, spacing) in [(1_000, 4.0), (5_000, 4.0), (5_000, 16.0)] {
+            let mut state = InstanceState::new(
+                String::new(),
+                InterestSettings::default(),
+                None,
+                MAX_INSTANCE_OBJECTS,
+                no_mutes(),
+            );
+            let mut receivers = Vec::with_capacity(count);
+
+            let side = (count as f32).sqrt().ceil() as usize;
+            let position = |id: usize, t: f32| {
+                [
+                    (id % side) as f32 * spacing + t.sin(),
+                    0.0,
+                    (id / side) as f32 * spacing + t.cos(),
+                ]
+            };
+
+            for id in 0..count {
+                receivers.push(join(&mut state, id, position(id, 0.0)).unwrap());
+
+                if id % 256 == 0 {
+                    for receiver in receivers.iter_mut() {
+                        while receiver.try_recv().is_ok() {}
+                    }
+                }
+            }
+
+            let mut elapsed = Duration::ZERO;
+            let mut sent = 0;
+
+            for tick in 0..TICKS {
+                for id in 0..count {
+                    state.set_transform(
+                        id,
+                        Transform {
+                            translation: position(id, tick as f32 * 0.05),
+                            rotation: [0.0, 0.0, 0.0, 1.0],
+                        },
+                    );
+                }
+
+                let start = Instant::now();
+                state.tick(tick);
+                elapsed += start.elapsed();
+
+                for receiver in receivers.iter_mut() {
+                    while let Ok(event) = receiver.try_recv() {
+                        if let OutgoingEvent::Transforms { transforms, .. } = event {
+                            sent += transforms.len();
+                        }
+                    }
+                }
+            }
+
+            println!(
+                "{} players, {}m apart: {:?} per tick, {} transforms per player per tick",
+                count,
+                spacing,
+                elapsed / TICKS,
+                sent / (count * TICKS as usize)
+            );
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Spatial interest management.
+//! Limits which player transforms are sent to each player, based on distance.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+pub struct InterestSettings {
+    /// Size of each grid cell, in meters.
+    pub cell_size: f32,
+    /// Players within this distance are sent every tick.
+    pub near_distance: f32,
+    /// Players within this distance (but beyond `near_distance`) are sent every `far_interval` ticks.
+    /// Players beyond it are not sent at all.
+    pub far_distance: f32,
+    pub far_interval: u32,
+    /// Players within this distance receive each other's voice.
+    pub voice_distance: f32,
+}
+
+impl Default for InterestSettings {
+    fn default() -> Self {
+        Self {
+            cell_size: 16.0,
+            near_distance: 32.0,
+            far_distance: 128.0,
+            far_interval: 4,
+            voice_distance: 64.0,
+        }
+    }
+}
+
+/// Uniform grid over player positions, rebuilt every tick.
+pub struct SpatialGrid {
+    /// Range of occupied cells, used to skip probing empty space.
+    bounds: Option<([i32; 3], [i32; 3])>,
+    cell_size: f32,
+    cells: HashMap<[i32; 3], Vec<(usize, [f32; 3])>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            bounds: None,
+            cell_size,
+            cells: HashMap::default(),
+        }
+    }
+
+    pub fn insert(&mut self, id: usize, position: [f32; 3]) {
+        let cell = self.cell(position);
+
+        let (min, max) = self.bounds.get_or_insert((cell, cell));
+        for i in 0..3 {
+            min[i] = min[i].min(cell[i]);
+            max[i] = max[i].max(cell[i]);
+        }
+
+        self.cells.entry(cell).or_default().push((id, position));
+    }
+
+    /// Finds all players within `radius` of `center`, along with their squared distance.
+    pub fn query(&self, center: [f32; 3], radius: f32) -> Vec<(usize, f32)> {
+        let Some((bounds_min, bounds_max)) = self.bounds else {
+            return Vec::new();
+        };
+
+        let min = self.cell(center.map(|v| v - radius));
+        let max = self.cell(center.map(|v| v + radius));
+
+        let min = [0, 1, 2].map(|i| min[i].max(bounds_min[i]));
+        let max = [0, 1, 2].map(|i| max[i].min(bounds_max[i]));
+
+        let radius_squared = radius * radius;
+
+        let mut found = Vec::new();
+
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    let Some(cell) = self.cells.get(&[x, y, z]) else {
+                        continue;
+                    };
+
+                    for (id, position) in cell {
+                        let distance_squared = distance_squared(center, *position);
+
+                        if distance_squared <= radius_squared {
+                            found.push((*id, distance_squared));
+                        }
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    fn cell(&self, position: [f32; 3]) -> [i32; 3] {
+        position.map(|v| {
+            if v.is_finite() {
+                (v / self.cell_size).floor() as i32
+            } else {
+                0
+            }
+        })
+    }
+}
+
+/// Whether a player at `distance_squared` should be sent this tick.
+/// Distant players are staggered by id, so their updates are spread across ticks.
+pub fn is_relevant(
+    settings: &InterestSettings,
+    distance_squared: f32,
+    id: usize,
+    tick: u32,
+) -> bool {
+    if distance_squared <= settings.near_distance * settings.near_distance {
+        return true;
+    }
+
+    if distance_squared > settings.far_distance * settings.far_distance {
+        return false;
+    }
+
+    let interval = settings.far_interval.max(1);
+    (tick % interval) as usize == id % interval as usize
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let dx = a[0] - b[0];
+    let dy = a[1] - b[1];
+    let dz = a[2] - b[2];
+    dx * dx + dy * dy + dz * dz
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let mut grid = SpatialGrid::new(16.0);
+        grid.insert(0, [0.0, 0.0, 0.0]);
+        grid.insert(1, [10.0, 0.0, 0.0]);
+        grid.insert(2, [-20.0, 0.0, 0.0]);
+        grid.insert(3, [100.0, 0.0, 0.0]);
+
+        let mut found = grid
+            .query([0.0, 0.0, 0.0], 20.0)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        found.sort();
+
+        assert_eq!(found, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_relevance() {
+        let settings = InterestSettings::default();
+
+        let near = 10.0f32.powi(2);
+        let far = 64.0f32.powi(2);
+        let beyond = 200.0f32.powi(2);
+
+        for tick in 0..8 {
+            assert!(is_relevant(&settings, near, 1, tick));
+            assert!(!is_relevant(&settings, beyond, 1, tick));
+        }
+
+        let sent = (0..8)
+            .filter(|tick| is_relevant(&settings, far, 1, *tick))
+            .count();
+        assert_eq!(sent, 2);
+    }
+}
@@ -1,32 +1,84 @@
 //! Server for running multiplayer instances of worlds over WebTransport.
 
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
     sync::Arc,
 };
 
 use dwn::{
+    actor::Actor,
     store::{DataStore, MessageStore},
     DWN,
 };
-use tokio::task::LocalSet;
+use tokio::{sync::watch, task::LocalSet};
 use tracing::{debug, error, info, info_span, Instrument};
-use wtransport::{Identity, ServerConfig};
+use wired_social::protocols::world_host::{Bans, CertificateHash, Mutes};
+use xwt_netsim::Conditions;
 use xwt_wtransport::IncomingSession;
 
-use crate::global_context::GlobalContext;
+use crate::{
+    connection::resume::ResumeStore,
+    global_context::GlobalContext,
+    instance::{registry::InstanceRegistry, InstanceOptions},
+    scheduler::Scheduler,
+};
+
+pub use admin::AdminOptions;
+pub use connection::validation::{DatagramStats, ValidationOptions, ViolationPolicy};
+pub use metrics::{Histogram, RpcCounts, RpcMetrics, ServerMetrics};
+pub use scheduler::ConnectionGauges;
+pub use tls::TlsOptions;
 
+mod admin;
+mod chat;
+mod clock;
 mod connection;
 mod global_context;
+mod instance;
+mod interest;
+mod metrics;
 mod rpc;
-mod update_loop;
+mod scheduler;
+mod tls;
 
 #[derive(Clone)]
 pub struct ServerOptions<D: DataStore, M: MessageStore> {
+    /// Serves the admin API, for moderation.
+    pub admin: Option<AdminOptions>,
+    /// DIDs banned from joining instances.
+    pub bans: Arc<watch::Sender<Bans>>,
+    /// Address to listen on.
+    pub bind: IpAddr,
+    /// Updated with hashes of the self-signed certificates being served,
+    /// for browsers to pass as `serverCertificateHashes`.
+    /// Empty when `tls` is set.
+    pub certificate_hashes: Option<Arc<watch::Sender<Vec<CertificateHash>>>>,
+    /// Active connections on each connection thread, for monitoring.
+    pub connection_gauges: Arc<ConnectionGauges>,
+    /// Counters for received datagrams, for monitoring.
+    pub datagram_stats: Arc<DatagramStats>,
+    /// Delta encode transform snapshots against the last snapshot acknowledged by each client.
+    pub delta_snapshots: bool,
     pub domain: String,
     pub dwn: Arc<DWN<D, M>>,
+    /// Updated with the number of players in each instance, keyed by instance record id.
+    pub instance_players: Option<Arc<watch::Sender<HashMap<String, usize>>>>,
+    /// Maximum number of players per instance.
+    pub max_players: Option<usize>,
+    /// Traffic, tick, and RPC metrics, for monitoring.
+    pub metrics: Arc<ServerMetrics>,
+    /// DIDs muted in instances.
+    pub mutes: Arc<watch::Sender<Mutes>>,
+    /// Network conditions to simulate on each connection, for testing under bad networks.
+    pub network_conditions: Conditions,
     pub port: u16,
     pub threads: Option<usize>,
+    /// Certificate to serve, reloaded when the files change.
+    /// If not set, short-lived self-signed certificates are generated and rotated.
+    pub tls: Option<TlsOptions>,
+    /// Validation of datagrams received from clients.
+    pub validation: ValidationOptions,
 }
 
 pub async fn start<D: DataStore + 'static, M: MessageStore + 'static>(
@@ -34,23 +86,49 @@ pub async fn start<D: DataStore + 'static, M: MessageStore + 'static>(
 ) -> std::io::Result<()> {
     let opts = Arc::new(opts);
 
-    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), opts.port);
-
-    let config = ServerConfig::builder()
-        .with_bind_address(address)
-        .with_identity(&Identity::self_signed([&address.to_string(), &opts.domain]).unwrap())
-        .build();
+    let address = SocketAddr::new(opts.bind, opts.port);
+    let identity = match &opts.tls {
+        Some(tls) => tls::load_identity(tls).await?,
+        None => tls::self_signed(address, &opts.domain),
+    };
+    let config = tls::server_config(address, &identity);
 
     let endpoint = wtransport::Endpoint::server(config)?;
     let endpoint = xwt_wtransport::Endpoint(endpoint);
 
-    let (send_cmd, recv_cmd) = tokio::sync::mpsc::unbounded_channel();
+    // Instance tasks run on the calling runtime, so they are spread across its worker threads.
+    let instances = InstanceRegistry::new(
+        InstanceOptions {
+            max_players: opts.max_players,
+            ..Default::default()
+        },
+        opts.instance_players.clone(),
+        opts.metrics.clone(),
+        opts.mutes.subscribe(),
+        tokio::runtime::Handle::current(),
+    );
+
+    let instances = Arc::new(instances);
 
     let context = Arc::new(GlobalContext {
-        sender: send_cmd.clone(),
+        bans: opts.bans.clone(),
+        datagram_stats: opts.datagram_stats.clone(),
+        delta_snapshots: opts.delta_snapshots,
+        instances: instances.clone(),
+        metrics: opts.metrics.clone(),
+        network_conditions: opts.network_conditions.clone(),
+        sessions: ResumeStore::default(),
+        validation: opts.validation.clone(),
         world_host_did: format!("did:web:{}", opts.domain.clone().replace(':', "%3A")),
     });
 
+    if !opts.network_conditions.is_ideal() {
+        info!(
+            "Simulating network conditions: {:?}",
+            opts.network_conditions
+        );
+    }
+
     let max_threads = std::thread::available_parallelism().unwrap().into();
     let num_threads = opts
         .threads
@@ -58,14 +136,19 @@ pub async fn start<D: DataStore + 'static, M: MessageStore + 'static>(
         .unwrap_or(max_threads);
     debug!("Spawning {} connection threads.", num_threads);
 
+    // Used to read instance records from the world host.
+    let actor = Arc::new(Actor::new_did_key(opts.dwn.clone()).unwrap());
+
     let mut threads = Vec::new();
+    let mut scheduler = Scheduler::new(opts.connection_gauges.clone(), num_threads);
 
     for thread in 0..num_threads {
         let (send_conn, mut recv_conn) = tokio::sync::mpsc::unbounded_channel::<NewConnection>();
         threads.push(send_conn);
 
         let context = context.clone();
-        let dwn = opts.dwn.clone();
+        let actor = actor.clone();
+        let gauges = opts.connection_gauges.clone();
 
         std::thread::spawn(move || {
             let span_thread = info_span!("Thread", id = thread).entered();
@@ -83,11 +166,24 @@ pub async fn start<D: DataStore + 'static, M: MessageStore + 'static>(
 
                     let span = info_span!("Connection", id = new_connection.id);
                     let context = context.clone();
-                    let dwn = dwn.clone();
+                    let actor = actor.clone();
+                    let gauges = gauges.clone();
 
                     tokio::task::spawn_local(
-                        connection::handle_connection(new_connection, context, dwn)
-                            .instrument(span),
+                        async move {
+                            if let Err(e) =
+                                connection::handle_connection(new_connection, context, actor).await
+                            {
+                                error!("{}", e);
+                            }
+
+                            gauges.decrement(thread);
+                            debug!(
+                                "Connection closed. Active connections: {:?}",
+                                gauges.threads()
+                            );
+                        }
+                        .instrument(span),
                     );
                 }
             });
@@ -99,28 +195,64 @@ pub async fn start<D: DataStore + 'static, M: MessageStore + 'static>(
         });
     }
 
-    tokio::spawn(async move {
-        if let Err(e) = update_loop::update_loop(send_cmd, recv_cmd).await {
-            panic!("{}", e);
-        };
-    });
-
     info!("Listening on {}", address);
 
-    for id in 1.. {
-        let incoming_session = IncomingSession(endpoint.accept().await);
+    let accept = async {
+        for id in 1.. {
+            let incoming_session = IncomingSession(endpoint.accept().await);
 
-        let thread_idx = num_threads % id;
+            let thread_idx = scheduler.assign();
 
-        if let Err(e) = threads[thread_idx].send(NewConnection {
-            id,
-            incoming_session,
-        }) {
-            error!("{}", e);
-        };
+            if let Err(e) = threads[thread_idx].send(NewConnection {
+                id,
+                incoming_session,
+            }) {
+                error!("{}", e);
+                opts.connection_gauges.decrement(thread_idx);
+                continue;
+            };
 
-        debug!("Connection {} sent to thread {}.", id, thread_idx)
-    }
+            debug!(
+                "Connection {} sent to thread {}. Active connections: {:?}",
+                id,
+                thread_idx,
+                opts.connection_gauges.threads()
+            );
+        }
+    };
+
+    let certificates = async {
+        match &opts.tls {
+            Some(tls) => tls::reload_on_change(&endpoint.0, address, tls).await,
+            None => {
+                tls::rotate_self_signed(
+                    &endpoint.0,
+                    address,
+                    &opts.domain,
+                    identity,
+                    opts.certificate_hashes.as_deref(),
+                )
+                .await
+            }
+        }
+    };
+
+    let admin = async {
+        if let Some(admin) = &opts.admin {
+            if let Err(e) = admin::serve(
+                admin.clone(),
+                opts.bans.clone(),
+                opts.mutes.clone(),
+                instances.clone(),
+            )
+            .await
+            {
+                error!("Admin API error: {}", e);
+            }
+        }
+    };
+
+    tokio::join!(accept, certificates, admin);
 
     info!("Finished.");
     Ok(())
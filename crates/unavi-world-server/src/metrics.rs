@@ -0,0 +1,158 @@
+//! Operational metrics, for monitoring.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    pub bytes_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub datagrams_received: AtomicU64,
+    pub datagrams_sent: AtomicU64,
+    /// Time taken to read records from the DWN.
+    pub dwn_latency: Histogram,
+    pub rpc: RpcMetrics,
+    /// Time taken to simulate one instance tick.
+    pub tick_duration: Histogram,
+}
+
+impl ServerMetrics {
+    pub fn datagram_received(&self, len: usize) {
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn datagram_sent(&self, len: usize) {
+        self.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+/// Call and error counts for each RPC method.
+#[derive(Debug, Default)]
+pub struct RpcMetrics {
+    methods: Mutex<BTreeMap<&'static str, RpcCounts>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcCounts {
+    pub calls: u64,
+    pub errors: u64,
+}
+
+impl RpcMetrics {
+    pub fn call(&self, method: &'static str) {
+        self.methods
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .calls += 1;
+    }
+
+    pub fn error(&self, method: &'static str) {
+        self.methods
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .errors += 1;
+    }
+
+    pub fn counts(&self) -> BTreeMap<&'static str, RpcCounts> {
+        self.methods.lock().unwrap().clone()
+    }
+}
+
+/// Upper bounds of histogram buckets, in seconds.
+pub const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Duration histogram with fixed buckets.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Non-cumulative counts for each bucket, with a final overflow bucket.
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let idx = BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS.len());
+
+        self.counts[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Cumulative counts for each bucket bound, excluding the implicit `+Inf` bucket.
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+
+        BUCKETS
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(bound, count)| {
+                total += count.load(Ordering::Relaxed);
+                (*bound, total)
+            })
+            .collect()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Sum of observed durations, in seconds.
+    pub fn sum(&self) -> f64 {
+        self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(200));
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(10));
+
+        let buckets = histogram.buckets();
+        assert_eq!(buckets[0], (0.0005, 1));
+        assert_eq!(buckets[5], (0.025, 2));
+        assert_eq!(buckets.last().unwrap().1, 2);
+
+        assert_eq!(histogram.count(), 3);
+        assert!((histogram.sum() - 10.0202).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rpc() {
+        let rpc = RpcMetrics::default();
+        rpc.call("join");
+        rpc.call("join");
+        rpc.error("join");
+
+        assert_eq!(
+            rpc.counts()["join"],
+            RpcCounts {
+                calls: 2,
+                errors: 1
+            }
+        );
+    }
+}
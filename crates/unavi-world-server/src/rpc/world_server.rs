@@ -1,103 +1,583 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use anyhow::{bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use capnp::capability::Promise;
 use capnp_rpc::pry;
+use didkit::{
+    ssi::{
+        did::VerificationRelationship, did_resolve::get_verification_methods, jws::verify_bytes,
+    },
+    DID_METHODS,
+};
 use dwn::{
     actor::{Actor, MessageBuilder},
-    message::descriptor::Descriptor,
+    message::{descriptor::Descriptor, Data},
     store::{DataStore, MessageStore},
 };
-use tracing::{debug, error};
-use wired_social::protocols::world_host::world_host_protocol_url;
-use wired_world::world_server_capnp::world_server::{
-    JoinParams, JoinResults, LeaveParams, LeaveResults, PlayerParams, PlayerResults, PlayersParams,
-    PlayersResults, Server, TickrateParams, TickrateResults,
+use rand::RngCore;
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tracing::debug;
+use wired_social::{protocols::world_host::world_host_protocol_url, schemas::instance::Instance};
+use wired_world::{
+    auth::{challenge_payload, NONCE_LEN},
+    datagram_capnp::object_state,
+    world_server_capnp::{
+        player_info, success,
+        world_server::{
+            AuthenticateParams, AuthenticateResults, ChallengeParams, ChallengeResults,
+            DespawnObjectParams, DespawnObjectResults, JoinParams, JoinResults, LeaveParams,
+            LeaveResults, PlayerParams, PlayerResults, PlayersParams, PlayersResults,
+            RequestOwnershipParams, RequestOwnershipResults, ResumeParams, ResumeResults,
+            SendMessageParams, SendMessageResults, Server, SetPlayerInfoParams,
+            SetPlayerInfoResults, SpawnObjectParams, SpawnObjectResults, SubscribeParams,
+            SubscribeResults, TickrateParams, TickrateResults, TimeParams, TimeResults,
+        },
+    },
 };
 
 use crate::{
+    chat::ChatError,
+    connection::{context::ConnectionContext, resume::ResumeToken},
     global_context::GlobalContext,
-    update_loop::{IncomingCommand, IncomingEvent, TICKRATE},
+    instance::{
+        objects::{NetworkedObject, ObjectError, ObjectState},
+        InstanceCommand, PlayerInfo, Transform,
+    },
+    metrics::ServerMetrics,
 };
 
 pub struct WorldServer<D: DataStore, M: MessageStore> {
     pub actor: Arc<Actor<D, M>>,
-    pub player_id: usize,
     pub context: Arc<GlobalContext>,
+    pub ctx: ConnectionContext,
+    /// Outstanding authentication challenge.
+    pub nonce: Option<Vec<u8>>,
 }
 
 impl<D: DataStore + 'static, M: MessageStore + 'static> Server for WorldServer<D, M> {
+    fn challenge(
+        &mut self,
+        _: ChallengeParams,
+        mut results: ChallengeResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "challenge", || {
+            let mut nonce = vec![0; NONCE_LEN];
+            rand::thread_rng().fill_bytes(&mut nonce);
+
+            results.get().set_nonce(&nonce);
+            self.nonce = Some(nonce);
+
+            Promise::ok(())
+        })
+    }
+
+    fn authenticate(
+        &mut self,
+        params: AuthenticateParams,
+        mut results: AuthenticateResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "authenticate", || {
+            let params = pry!(params.get());
+            let did = pry!(pry!(params.get_did()).to_string());
+            let signature = pry!(params.get_signature()).to_vec();
+
+            // Nonces are single use, so a failed attempt requires a new challenge.
+            let nonce = self.nonce.take();
+
+            let context = self.context.clone();
+            let ctx = self.ctx.clone();
+
+            Promise::from_future(async move {
+                let mut success = results.get().init_success();
+
+                let verified = match nonce {
+                    Some(nonce) => {
+                        verify_signature(&did, &context.world_host_did, &nonce, &signature).await
+                    }
+                    None => Err(anyhow::anyhow!("No challenge requested")),
+                };
+
+                match verified {
+                    Ok(_) => {
+                        debug!("Authenticated as {}", did);
+
+                        ctx.info.borrow_mut().did = Some(did);
+                        ctx.update_info();
+
+                        success.set_success(());
+                    }
+                    Err(e) => {
+                        let e = e.to_string();
+                        debug!("Authentication error {}", e);
+                        context.metrics.rpc.error("authenticate");
+                        success.init_error(e.len() as u32).push_str(&e);
+                    }
+                };
+
+                Ok(())
+            })
+        })
+    }
+
+    fn subscribe(
+        &mut self,
+        params: SubscribeParams,
+        _: SubscribeResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "subscribe", || {
+            let events = pry!(pry!(params.get()).get_events());
+
+            if self.ctx.subscriber.borrow_mut().replace(events).is_some() {
+                debug!("Replacing existing event subscriber.");
+            }
+
+            Promise::ok(())
+        })
+    }
+
     fn join(&mut self, params: JoinParams, mut results: JoinResults) -> Promise<(), capnp::Error> {
-        let params = pry!(params.get());
-        let record_id = pry!(pry!(params.get_record_id()).to_string());
+        track(self.context.clone(), "join", || {
+            let params = pry!(params.get());
+            let record_id = pry!(pry!(params.get_record_id()).to_string());
+
+            debug!("Request to join instance: {}", record_id);
+
+            let Some(did) = self.ctx.info.borrow().did.clone() else {
+                let e = "Not authenticated";
+                results
+                    .get()
+                    .init_success()
+                    .init_error(e.len() as u32)
+                    .push_str(e);
+                self.context.metrics.rpc.error("join");
+                return Promise::ok(());
+            };
+
+            if self.context.bans.borrow().contains(&did, &record_id) {
+                let e = "Banned from instance";
+                results
+                    .get()
+                    .init_success()
+                    .init_error(e.len() as u32)
+                    .push_str(e);
+                self.context.metrics.rpc.error("join");
+                return Promise::ok(());
+            }
+
+            let actor = self.actor.clone();
+            let ctx = self.ctx.clone();
+            let context = self.context.clone();
+            let instances = self.context.instances.clone();
+            let world_host_did = self.context.world_host_did.clone();
+
+            Promise::from_future(async move {
+                let mut success = results.get().init_success();
+
+                let verified =
+                    verify_instance(actor, &context.metrics, world_host_did, record_id.clone())
+                        .await
+                        .and_then(|(owner, instance)| {
+                            if instance.can_join(&owner, &did) {
+                                Ok(())
+                            } else {
+                                Err(anyhow::anyhow!("Access denied"))
+                            }
+                        });
 
-        debug!("Request to join instance: {}", record_id);
+                match verified {
+                    Ok(_) => {
+                        debug!("Instance {} verified.", record_id);
 
-        let actor = self.actor.clone();
-        let context = self.context.clone();
-        let player_id = self.player_id;
-        let world_host_did = self.context.world_host_did.clone();
+                        match ctx.join(&instances, record_id).await {
+                            Ok(_) => {
+                                success.set_success(());
+                                results.get().set_resume_token(&ctx.resume_token());
+                            }
+                            Err(e) => {
+                                let e = e.to_string();
+                                debug!("Join error {}", e);
+                                context.metrics.rpc.error("join");
+                                success.init_error(e.len() as u32).push_str(&e);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let e = e.to_string();
+                        debug!("Instance error {}", e);
+                        context.metrics.rpc.error("join");
+                        success.init_error(e.len() as u32).push_str(&e);
+                    }
+                };
+
+                Ok(())
+            })
+        })
+    }
+
+    fn resume(
+        &mut self,
+        params: ResumeParams,
+        mut results: ResumeResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "resume", || {
+            let token = pry!(pry!(params.get()).get_token());
+
+            let error = if !self.ctx.instances.borrow().is_empty() {
+                Some("Already joined an instance")
+            } else {
+                match ResumeToken::try_from(token)
+                    .ok()
+                    .and_then(|token| Some((token, self.context.sessions.take(&token)?)))
+                {
+                    Some((token, parked)) => {
+                        // Bans made while the session was parked still apply.
+                        let banned = parked.info.did.as_ref().is_some_and(|did| {
+                            let bans = self.context.bans.borrow();
+                            parked.instances.keys().any(|id| bans.contains(did, id))
+                        });
+
+                        if banned {
+                            debug!("Player {} was banned, not resuming", parked.player_id);
+                            parked.leave_all();
+                            Some("Session expired")
+                        } else {
+                            debug!("Resuming session of player {}", parked.player_id);
+                            self.ctx.resume(token, parked);
+                            None
+                        }
+                    }
+                    None => Some("Session expired"),
+                }
+            };
 
-        Promise::from_future(async move {
             let mut success = results.get().init_success();
 
-            match verify_instance(actor, world_host_did, record_id.clone()).await {
-                Ok(_) => {
-                    debug!("Instance {} verified.", record_id);
+            match error {
+                Some(e) => {
+                    self.context.metrics.rpc.error("resume");
+                    success.init_error(e.len() as u32).push_str(e);
+                }
+                None => success.set_success(()),
+            }
+
+            Promise::ok(())
+        })
+    }
 
-                    context
-                        .sender
-                        .send(IncomingEvent {
-                            command: IncomingCommand::JoinInstance { id: record_id },
-                            player_id,
-                        })
-                        .map_err(|e| {
-                            error!("Send failed: {}", e);
-                            capnp::Error::from_kind(capnp::ErrorKind::Failed)
-                        })?;
+    fn leave(&mut self, params: LeaveParams, _: LeaveResults) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "leave", || {
+            let params = pry!(params.get());
+            let record_id = pry!(pry!(params.get_record_id()).to_string());
 
-                    success.set_success(());
+            self.ctx.leave(&record_id);
+
+            Promise::ok(())
+        })
+    }
+
+    fn players(
+        &mut self,
+        _: PlayersParams,
+        mut results: PlayersResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "players", || {
+            let local_ids = self.ctx.local_ids.clone();
+            let player_id = self.ctx.player_id();
+
+            let mut receivers = Vec::new();
+            self.ctx.broadcast(|| {
+                let (sender, receiver) = oneshot::channel();
+                receivers.push(receiver);
+                InstanceCommand::GetPlayers { player_id, sender }
+            });
+
+            Promise::from_future(async move {
+                // Players in multiple shared instances are only listed once.
+                let mut players = HashMap::new();
+
+                for receiver in receivers {
+                    // Instances that closed before responding are skipped.
+                    if let Ok(found) = receiver.await {
+                        players.extend(found);
+                    }
                 }
-                Err(e) => {
-                    let e = e.to_string();
-                    debug!("Instance error {}", e);
-                    success.init_error(e.len() as u32).push_str(&e);
+
+                // Players that have not been assigned a local id yet are skipped.
+                let players = {
+                    let local_ids = local_ids.borrow();
+                    players
+                        .into_iter()
+                        .filter_map(|(id, info)| {
+                            local_ids.local(id).map(|local_id| (local_id, info))
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                let mut list = results.get().init_players(players.len() as u32);
+
+                for (i, (local_id, info)) in players.into_iter().enumerate() {
+                    write_player_info(list.reborrow().get(i as u32), local_id, info);
                 }
+
+                Ok(())
+            })
+        })
+    }
+
+    fn player(
+        &mut self,
+        params: PlayerParams,
+        mut results: PlayerResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "player", || {
+            let local_id = pry!(params.get()).get_id();
+
+            let Some(id) = self.ctx.local_ids.borrow().global(local_id) else {
+                return Promise::err(capnp::Error::failed(format!(
+                    "Player {} not found",
+                    local_id
+                )));
             };
 
-            Ok(())
+            let player_id = self.ctx.player_id();
+
+            let mut receivers = Vec::new();
+            self.ctx.broadcast(|| {
+                let (sender, receiver) = oneshot::channel();
+                receivers.push(receiver);
+                InstanceCommand::GetPlayer {
+                    id,
+                    player_id,
+                    sender,
+                }
+            });
+
+            Promise::from_future(async move {
+                let mut info = None;
+
+                for receiver in receivers {
+                    if let Ok(Some(found)) = receiver.await {
+                        info = Some(found);
+                        break;
+                    }
+                }
+
+                let info = info.ok_or_else(|| {
+                    capnp::Error::failed(format!("Player {} not found", local_id))
+                })?;
+
+                write_player_info(results.get().init_player(), local_id, info);
+
+                Ok(())
+            })
         })
     }
 
-    fn leave(&mut self, params: LeaveParams, _: LeaveResults) -> Promise<(), capnp::Error> {
-        let params = pry!(params.get());
-        let record_id = pry!(pry!(params.get_record_id()).to_string());
+    fn set_player_info(
+        &mut self,
+        params: SetPlayerInfoParams,
+        _: SetPlayerInfoResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "set_player_info", || {
+            let params = pry!(params.get());
+            let avatar = pry!(pry!(params.get_avatar()).to_string());
+            let name = pry!(pry!(params.get_name()).to_string());
+
+            {
+                let mut info = self.ctx.info.borrow_mut();
+                info.avatar = Some(avatar).filter(|s| !s.is_empty());
+                info.name = Some(name).filter(|s| !s.is_empty());
+            }
+
+            self.ctx.update_info();
+
+            Promise::ok(())
+        })
+    }
+
+    fn send_message(
+        &mut self,
+        params: SendMessageParams,
+        mut results: SendMessageResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "send_message", || {
+            let params = pry!(params.get());
+            let record_id = pry!(pry!(params.get_record_id()).to_string());
+            let text = pry!(pry!(params.get_text()).to_str());
+
+            let checked =
+                self.ctx
+                    .instances
+                    .borrow()
+                    .get(&record_id)
+                    .cloned()
+                    .ok_or(ChatError::NotJoined)
+                    .and_then(|instance| {
+                        let banned = self.ctx.info.borrow().did.as_ref().is_some_and(|did| {
+                            self.context.bans.borrow().contains(did, &record_id)
+                        });
+
+                        if banned {
+                            return Err(ChatError::Banned);
+                        }
+
+                        let text = self.ctx.chat.borrow_mut().check(text, Instant::now())?;
+
+                        Ok((instance, text))
+                    });
+
+            let context = self.context.clone();
+            let player_id = self.ctx.player_id();
 
-        let context = self.context.clone();
-        let player_id = self.player_id;
+            Promise::from_future(async move {
+                let res = match checked {
+                    Ok((instance, text)) => {
+                        let (result, receiver) = oneshot::channel();
 
-        Promise::from_future(async move {
-            context
-                .sender
-                .send(IncomingEvent {
-                    command: IncomingCommand::LeaveInstance { id: record_id },
+                        let command = InstanceCommand::SendMessage {
+                            player_id,
+                            result,
+                            text,
+                        };
+
+                        if instance.send(command).is_ok() {
+                            receiver.await.unwrap_or(Err(ChatError::NotJoined))
+                        } else {
+                            Err(ChatError::NotJoined)
+                        }
+                    }
+                    Err(e) => Err(e),
+                };
+
+                let mut success = results.get().init_success();
+
+                match res {
+                    Ok(()) => success.set_success(()),
+                    Err(e) => {
+                        debug!("Message rejected: {}", e);
+                        context.metrics.rpc.error("send_message");
+
+                        let e = e.to_string();
+                        success.init_error(e.len() as u32).push_str(&e);
+                    }
+                }
+
+                Ok(())
+            })
+        })
+    }
+
+    fn spawn_object(
+        &mut self,
+        params: SpawnObjectParams,
+        mut results: SpawnObjectResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "spawn_object", || {
+            let params = pry!(params.get());
+            let record_id = pry!(pry!(params.get_record_id()).to_string());
+            let (_, state) = pry!(read_object_state(pry!(params.get_state())));
+
+            let id = self.context.instances.next_object_id();
+            let object = NetworkedObject {
+                data: pry!(params.get_data()).to_vec(),
+                owner: None,
+                // Spawned by players, so removed once abandoned.
+                persistent: false,
+                state,
+                transferable: params.get_transferable(),
+            };
+
+            let instance = self.ctx.instances.borrow().get(&record_id).cloned();
+            let context = self.context.clone();
+            let player_id = self.ctx.player_id();
+
+            Promise::from_future(async move {
+                let res = object_command(instance, |result| InstanceCommand::SpawnObject {
+                    id,
+                    object,
                     player_id,
+                    result,
                 })
-                .map_err(|e| {
-                    error!("Send failed: {}", e);
-                    capnp::Error::from_kind(capnp::ErrorKind::Failed)
-                })?;
+                .await;
 
-            Ok(())
+                if res.is_ok() {
+                    results.get().set_id(id);
+                }
+
+                write_object_result(results.get().init_success(), &context, "spawn_object", res);
+
+                Ok(())
+            })
         })
     }
 
-    fn players(&mut self, _: PlayersParams, _: PlayersResults) -> Promise<(), capnp::Error> {
-        todo!();
+    fn despawn_object(
+        &mut self,
+        params: DespawnObjectParams,
+        mut results: DespawnObjectResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "despawn_object", || {
+            let params = pry!(params.get());
+            let record_id = pry!(pry!(params.get_record_id()).to_string());
+            let id = params.get_id();
+
+            let instance = self.ctx.instances.borrow().get(&record_id).cloned();
+            let context = self.context.clone();
+            let player_id = self.ctx.player_id();
+
+            Promise::from_future(async move {
+                let res = object_command(instance, |result| InstanceCommand::DespawnObject {
+                    id,
+                    player_id,
+                    result,
+                })
+                .await;
+
+                write_object_result(
+                    results.get().init_success(),
+                    &context,
+                    "despawn_object",
+                    res,
+                );
+
+                Ok(())
+            })
+        })
     }
 
-    fn player(&mut self, _: PlayerParams, _: PlayerResults) -> Promise<(), capnp::Error> {
-        todo!();
+    fn request_ownership(
+        &mut self,
+        params: RequestOwnershipParams,
+        mut results: RequestOwnershipResults,
+    ) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "request_ownership", || {
+            let params = pry!(params.get());
+            let record_id = pry!(pry!(params.get_record_id()).to_string());
+            let id = params.get_id();
+
+            let instance = self.ctx.instances.borrow().get(&record_id).cloned();
+            let context = self.context.clone();
+            let player_id = self.ctx.player_id();
+
+            Promise::from_future(async move {
+                let res = object_command(instance, |result| InstanceCommand::RequestOwnership {
+                    id,
+                    player_id,
+                    result,
+                })
+                .await;
+
+                write_object_result(
+                    results.get().init_success(),
+                    &context,
+                    "request_ownership",
+                    res,
+                );
+
+                Ok(())
+            })
+        })
     }
 
     fn tickrate(
@@ -105,22 +585,196 @@ impl<D: DataStore + 'static, M: MessageStore + 'static> Server for WorldServer<D
         _: TickrateParams,
         mut results: TickrateResults,
     ) -> Promise<(), capnp::Error> {
-        results.get().set_tickrate(TICKRATE);
-        Promise::ok(())
+        track(self.context.clone(), "tickrate", || {
+            // Instances may tick at different rates, so use the one that was joined.
+            let id = self.ctx.instances.borrow().keys().next().cloned();
+            let Some(id) = id else {
+                return Promise::err(capnp::Error::failed("Not in an instance".to_string()));
+            };
+
+            results
+                .get()
+                .set_tickrate(self.context.instances.options(&id).tickrate);
+            Promise::ok(())
+        })
+    }
+
+    fn time(&mut self, _: TimeParams, mut results: TimeResults) -> Promise<(), capnp::Error> {
+        track(self.context.clone(), "time", || {
+            let elapsed = self.context.instances.clock().elapsed();
+            results.get().set_time(elapsed.as_micros() as u64);
+            Promise::ok(())
+        })
+    }
+}
+
+/// Counts a call to `method`, and counts an error if it fails.
+fn track(
+    context: Arc<GlobalContext>,
+    method: &'static str,
+    call: impl FnOnce() -> Promise<(), capnp::Error>,
+) -> Promise<(), capnp::Error> {
+    context.metrics.rpc.call(method);
+
+    let promise = call();
+
+    Promise::from_future(async move {
+        let res = promise.await;
+
+        if res.is_err() {
+            context.metrics.rpc.error(method);
+        }
+
+        res
+    })
+}
+
+pub fn write_player_info(mut builder: player_info::Builder, local_id: u16, info: PlayerInfo) {
+    builder.set_id(local_id);
+    builder.set_avatar(info.avatar.unwrap_or_default());
+    builder.set_did(info.did.unwrap_or_default());
+    builder.set_name(info.name.unwrap_or_default());
+}
+
+pub fn read_object_state(reader: object_state::Reader) -> capnp::Result<(u32, ObjectState)> {
+    let translation = reader.get_translation()?;
+    let rotation = reader.get_rotation()?;
+    let linear = reader.get_linear_velocity()?;
+    let angular = reader.get_angular_velocity()?;
+
+    let state = ObjectState {
+        transform: Transform {
+            translation: [
+                translation.get_x(),
+                translation.get_y(),
+                translation.get_z(),
+            ],
+            rotation: [
+                rotation.get_x(),
+                rotation.get_y(),
+                rotation.get_z(),
+                rotation.get_w(),
+            ],
+        },
+        linear_velocity: [linear.get_x(), linear.get_y(), linear.get_z()],
+        angular_velocity: [angular.get_x(), angular.get_y(), angular.get_z()],
+    };
+
+    Ok((reader.get_id(), state))
+}
+
+pub fn write_object_state(mut builder: object_state::Builder, id: u32, state: &ObjectState) {
+    builder.set_id(id);
+
+    let [x, y, z] = state.transform.translation;
+    let mut translation = builder.reborrow().init_translation();
+    translation.set_x(x);
+    translation.set_y(y);
+    translation.set_z(z);
+
+    let [x, y, z, w] = state.transform.rotation;
+    let mut rotation = builder.reborrow().init_rotation();
+    rotation.set_x(x);
+    rotation.set_y(y);
+    rotation.set_z(z);
+    rotation.set_w(w);
+
+    let [x, y, z] = state.linear_velocity;
+    let mut linear = builder.reborrow().init_linear_velocity();
+    linear.set_x(x);
+    linear.set_y(y);
+    linear.set_z(z);
+
+    let [x, y, z] = state.angular_velocity;
+    let mut angular = builder.init_angular_velocity();
+    angular.set_x(x);
+    angular.set_y(y);
+    angular.set_z(z);
+}
+
+/// Sends an object command to an instance, waiting for the result.
+async fn object_command(
+    instance: Option<UnboundedSender<InstanceCommand>>,
+    command: impl FnOnce(oneshot::Sender<Result<(), ObjectError>>) -> InstanceCommand,
+) -> Result<(), ObjectError> {
+    let instance = instance.ok_or(ObjectError::NotJoined)?;
+    let (result, receiver) = oneshot::channel();
+
+    if instance.send(command(result)).is_err() {
+        return Err(ObjectError::NotJoined);
+    }
+
+    receiver.await.unwrap_or(Err(ObjectError::NotJoined))
+}
+
+fn write_object_result(
+    mut success: success::Builder,
+    context: &GlobalContext,
+    method: &'static str,
+    res: Result<(), ObjectError>,
+) {
+    match res {
+        Ok(()) => success.set_success(()),
+        Err(e) => {
+            debug!("Object request rejected: {}", e);
+            context.metrics.rpc.error(method);
+
+            let e = e.to_string();
+            success.init_error(e.len() as u32).push_str(&e);
+        }
     }
 }
 
-/// Verifies the provided `record_id` is a valid instance.
+/// Verifies `signature` was created over the challenge payload for `nonce` from this server,
+/// using one of the DID's authentication keys.
+async fn verify_signature(
+    did: &str,
+    world_host_did: &str,
+    nonce: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let methods = get_verification_methods(
+        did,
+        VerificationRelationship::Authentication,
+        DID_METHODS.to_resolver(),
+    )
+    .await?;
+
+    let payload = challenge_payload(world_host_did, nonce);
+
+    for method in methods.values() {
+        let Some(jwk) = &method.public_key_jwk else {
+            continue;
+        };
+
+        let Some(algorithm) = jwk.get_algorithm() else {
+            continue;
+        };
+
+        if verify_bytes(algorithm, &payload, jwk, signature).is_ok() {
+            return Ok(());
+        }
+    }
+
+    bail!("Invalid signature")
+}
+
+/// Verifies the provided `record_id` is a valid instance, returning its owner and data.
+/// The owner is the author of the record, whose signature the world host checked when storing it.
 async fn verify_instance(
     actor: Arc<Actor<impl DataStore, impl MessageStore>>,
+    metrics: &ServerMetrics,
     world_host_did: String,
     record_id: String,
-) -> Result<()> {
+) -> Result<(String, Instance)> {
+    let start = Instant::now();
     let read = actor
         .read_record(record_id)
         .target(world_host_did)
         .process()
-        .await?;
+        .await;
+    metrics.dwn_latency.observe(start.elapsed());
+    let read = read?;
     debug!("Found record {}", read.record.record_id);
 
     let descriptor = match &read.record.descriptor {
@@ -141,5 +795,18 @@ async fn verify_instance(
         bail!("Invalid descriptor")
     }
 
-    Ok(())
+    let Some(owner) = read.record.author() else {
+        bail!("Instance record not signed")
+    };
+
+    let instance = match &read.record.data {
+        Some(Data::Base64(encoded)) => {
+            let data = URL_SAFE_NO_PAD.decode(encoded)?;
+            serde_json::from_slice(&data)?
+        }
+        Some(Data::Encrypted(_)) => bail!("Instance data encrypted"),
+        None => bail!("Instance data not found"),
+    };
+
+    Ok((owner, instance))
 }
@@ -0,0 +1,107 @@
+//! Distributes connections across connection threads.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, RwLock,
+};
+
+/// Number of active connections on each connection thread, for monitoring.
+#[derive(Debug, Default)]
+pub struct ConnectionGauges {
+    threads: RwLock<Box<[AtomicUsize]>>,
+}
+
+impl ConnectionGauges {
+    /// Active connections on each thread.
+    /// Empty until the server has started.
+    pub fn threads(&self) -> Vec<usize> {
+        self.threads
+            .read()
+            .unwrap()
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    pub fn total(&self) -> usize {
+        self.threads().into_iter().sum()
+    }
+
+    fn reset(&self, num_threads: usize) {
+        *self.threads.write().unwrap() = (0..num_threads).map(|_| AtomicUsize::new(0)).collect();
+    }
+
+    fn increment(&self, thread: usize) {
+        if let Some(count) = self.threads.read().unwrap().get(thread) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks a connection on `thread` as finished.
+    pub(crate) fn decrement(&self, thread: usize) {
+        if let Some(count) = self.threads.read().unwrap().get(thread) {
+            count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Assigns new connections to the thread with the fewest active connections.
+pub struct Scheduler {
+    gauges: Arc<ConnectionGauges>,
+    /// Where to start searching, so ties are spread round-robin.
+    next: usize,
+}
+
+impl Scheduler {
+    pub fn new(gauges: Arc<ConnectionGauges>, num_threads: usize) -> Self {
+        gauges.reset(num_threads);
+        Self { gauges, next: 0 }
+    }
+
+    /// Picks a thread for a new connection, and counts the connection against it.
+    /// The connection must be released with [`ConnectionGauges::decrement`] once it finishes.
+    pub fn assign(&mut self) -> usize {
+        let loads = self.gauges.threads();
+        let thread = least_loaded(&loads, self.next);
+
+        self.gauges.increment(thread);
+        self.next = (thread + 1) % loads.len().max(1);
+
+        thread
+    }
+}
+
+/// Finds the index with the lowest load, preferring the first found from `start`.
+fn least_loaded(loads: &[usize], start: usize) -> usize {
+    (0..loads.len())
+        .map(|i| (start + i) % loads.len())
+        .min_by_key(|i| loads[*i])
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_loaded() {
+        assert_eq!(least_loaded(&[3, 1, 2], 0), 1);
+        assert_eq!(least_loaded(&[1, 1, 1], 2), 2);
+        assert_eq!(least_loaded(&[], 0), 0);
+    }
+
+    #[test]
+    fn test_assign() {
+        let gauges = Arc::new(ConnectionGauges::default());
+        let mut scheduler = Scheduler::new(gauges.clone(), 3);
+
+        let assigned = (0..6).map(|_| scheduler.assign()).collect::<Vec<_>>();
+        assert_eq!(assigned, vec![0, 1, 2, 0, 1, 2]);
+        assert_eq!(gauges.threads(), vec![2, 2, 2]);
+
+        gauges.decrement(1);
+        gauges.decrement(1);
+        assert_eq!(scheduler.assign(), 1);
+        assert_eq!(gauges.total(), 5);
+    }
+}
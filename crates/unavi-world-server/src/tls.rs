@@ -0,0 +1,298 @@
+//! TLS identities.
+//! Certificates are either loaded from disk and reloaded when they change,
+//! or self-signed and rotated on a schedule.
+
+use std::{
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use tokio::sync::watch;
+use tracing::{error, info};
+use wired_social::protocols::world_host::CertificateHash;
+use wtransport::{endpoint::endpoint_side::Server, Endpoint, Identity, ServerConfig};
+
+/// How often to check certificate files for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long each self-signed certificate is served for.
+/// Self-signed certificates are valid for 14 days, the maximum browsers accept for
+/// `serverCertificateHashes`. Each is generated one interval before it is served,
+/// so it expires at least two days after being replaced.
+const ROTATION_INTERVAL: Duration = Duration::from_secs(6 * 24 * 60 * 60);
+
+#[derive(Clone, Debug)]
+pub struct TlsOptions {
+    /// PEM encoded certificate chain.
+    pub cert: PathBuf,
+    /// PEM encoded private key.
+    pub key: PathBuf,
+}
+
+pub async fn load_identity(tls: &TlsOptions) -> std::io::Result<Identity> {
+    Identity::load_pemfiles(&tls.cert, &tls.key)
+        .await
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Generates a short-lived ECDSA certificate.
+pub fn self_signed(address: SocketAddr, domain: &str) -> Identity {
+    Identity::self_signed([&address.to_string(), domain]).unwrap()
+}
+
+pub fn server_config(address: SocketAddr, identity: &Identity) -> ServerConfig {
+    ServerConfig::builder()
+        .with_bind_address(address)
+        .with_identity(identity)
+        .build()
+}
+
+/// Reloads the endpoint's certificates whenever the files are modified.
+/// Existing connections are unaffected.
+pub async fn reload_on_change(endpoint: &Endpoint<Server>, address: SocketAddr, tls: &TlsOptions) {
+    let mut watcher = CertificateWatcher::new(tls);
+
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let identity = match watcher.poll().await {
+            Ok(Some(identity)) => identity,
+            Ok(None) => continue,
+            Err(e) => {
+                // Files may be mid-write, so try again next interval.
+                error!("Failed to load certificates: {}", e);
+                continue;
+            }
+        };
+
+        match endpoint.reload_config(server_config(address, &identity), false) {
+            Ok(()) => info!("Reloaded certificates."),
+            Err(e) => error!("Failed to reload certificates: {}", e),
+        }
+    }
+}
+
+/// Tracks when certificate files were modified.
+struct CertificateWatcher<'a> {
+    modified: [Option<SystemTime>; 2],
+    tls: &'a TlsOptions,
+}
+
+impl<'a> CertificateWatcher<'a> {
+    fn new(tls: &'a TlsOptions) -> Self {
+        Self {
+            modified: modified_times(tls),
+            tls,
+        }
+    }
+
+    /// Loads the certificates if the files were modified since they were last loaded.
+    /// Files that fail to load are loaded again on the next poll.
+    async fn poll(&mut self) -> std::io::Result<Option<Identity>> {
+        let current = modified_times(self.tls);
+        if current == self.modified {
+            return Ok(None);
+        }
+
+        let identity = load_identity(self.tls).await?;
+        self.modified = current;
+
+        Ok(Some(identity))
+    }
+}
+
+/// Replaces the endpoint's self-signed certificate every [`ROTATION_INTERVAL`].
+///
+/// Hashes of the current and next certificates are sent to `hashes`, so clients that
+/// fetched them shortly before a rotation can still connect after it.
+pub async fn rotate_self_signed(
+    endpoint: &Endpoint<Server>,
+    address: SocketAddr,
+    domain: &str,
+    current: Identity,
+    hashes: Option<&watch::Sender<Vec<CertificateHash>>>,
+) {
+    let mut current = current;
+    let mut next = self_signed(address, domain);
+
+    let mut interval = tokio::time::interval(ROTATION_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        if let Some(hashes) = hashes {
+            hashes.send_replace(vec![certificate_hash(&current), certificate_hash(&next)]);
+        }
+
+        interval.tick().await;
+
+        match endpoint.reload_config(server_config(address, &next), false) {
+            Ok(()) => {
+                info!("Rotated self-signed certificate.");
+                current = next;
+            }
+            Err(e) => error!("Failed to rotate certificate: {}", e),
+        }
+
+        next = self_signed(address, domain);
+    }
+}
+
+/// Hash of the identity's leaf certificate.
+pub fn certificate_hash(identity: &Identity) -> CertificateHash {
+    let leaf = &identity.certificate_chain().as_slice()[0];
+    CertificateHash::sha256(leaf.hash().as_ref())
+}
+
+fn modified_times(tls: &TlsOptions) -> [Option<SystemTime>; 2] {
+    [&tls.cert, &tls.key].map(|path| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, net::Ipv4Addr, path::Path};
+
+    use wtransport::{tls::Sha256Digest, ClientConfig};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("unavi-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes the identity to the files, marking them as modified at `modified`.
+    async fn store_identity(tls: &TlsOptions, identity: &Identity, modified: SystemTime) {
+        identity
+            .certificate_chain()
+            .store_pemfile(&tls.cert)
+            .await
+            .unwrap();
+        identity
+            .private_key()
+            .store_secret_pemfile(&tls.key)
+            .await
+            .unwrap();
+
+        set_modified(&tls.cert, modified);
+        set_modified(&tls.key, modified);
+    }
+
+    fn set_modified(path: &Path, modified: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    /// Connects to the endpoint, accepting only certificates with the hash.
+    /// Returns the hash of the certificate served.
+    async fn served_hash(
+        endpoint: &Endpoint<Server>,
+        hash: &CertificateHash,
+    ) -> Option<CertificateHash> {
+        let port = endpoint.local_addr().unwrap().port();
+
+        let config = ClientConfig::builder()
+            .with_bind_default()
+            .with_server_certificate_hashes([Sha256Digest::new(hash.sha256_digest().unwrap())])
+            .build();
+        let client = Endpoint::client(config).unwrap();
+
+        let accept = async {
+            let request = endpoint.accept().await.await.ok()?;
+            request.accept().await.ok()
+        };
+        let connect = client.connect(format!("https://127.0.0.1:{}", port));
+
+        let (connection, _) = tokio::join!(connect, accept);
+        let connection = connection.ok()?;
+
+        let served = connection.peer_identity()?;
+        let leaf = &served.as_slice()[0];
+        Some(CertificateHash::sha256(leaf.hash().as_ref()))
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = temp_dir("reload");
+        let tls = TlsOptions {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+        let address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+
+        let first = self_signed(address, "localhost");
+        store_identity(&tls, &first, SystemTime::UNIX_EPOCH).await;
+
+        let loaded = load_identity(&tls).await.unwrap();
+        let endpoint = Endpoint::server(server_config(address, &loaded)).unwrap();
+        let mut watcher = CertificateWatcher::new(&tls);
+
+        assert!(watcher.poll().await.unwrap().is_none());
+        assert_eq!(
+            served_hash(&endpoint, &certificate_hash(&first)).await,
+            Some(certificate_hash(&first))
+        );
+
+        let second = self_signed(address, "localhost");
+        store_identity(&tls, &second, SystemTime::now()).await;
+
+        let reloaded = watcher.poll().await.unwrap().unwrap();
+        assert_eq!(certificate_hash(&reloaded), certificate_hash(&second));
+        assert!(watcher.poll().await.unwrap().is_none());
+
+        endpoint
+            .reload_config(server_config(address, &reloaded), false)
+            .unwrap();
+
+        assert_eq!(
+            served_hash(&endpoint, &certificate_hash(&second)).await,
+            Some(certificate_hash(&second))
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_pem() {
+        let dir = temp_dir("invalid");
+        let tls = TlsOptions {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+        let address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+
+        let identity = self_signed(address, "localhost");
+        store_identity(&tls, &identity, SystemTime::UNIX_EPOCH).await;
+
+        let mut watcher = CertificateWatcher::new(&tls);
+
+        // Partially written.
+        std::fs::write(&tls.cert, "-----BEGIN CERTIFICATE-----\n").unwrap();
+        assert_eq!(
+            load_identity(&tls).await.err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidData)
+        );
+        assert!(watcher.poll().await.is_err());
+        assert!(watcher.poll().await.is_err());
+
+        // Loaded once the write completes.
+        store_identity(&tls, &identity, SystemTime::now()).await;
+        let loaded = watcher.poll().await.unwrap().unwrap();
+        assert_eq!(certificate_hash(&loaded), certificate_hash(&identity));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bevy::prelude::*;
+use bevy_async_task::{AsyncTaskPool, AsyncTaskStatus};
+use dwn::{
+    actor::{Actor, MessageBuilder, ProcessMessageError},
+    message::{
+        descriptor::{records::RecordsFilter, Descriptor},
+        Data,
+    },
+    store::{DataStore, MessageStore},
+};
+use thiserror::Error;
+use unavi_dwn::UserActor;
+use wired_social::protocols::world_host::{
+    world_host_protocol_url, CertificateHash, CERTIFICATE_HASHES_PATH, WORLD_HOST_PROTOCOL_VERSION,
+};
+
+use crate::{InstanceRecord, InstanceServerCertificates};
+
+/// Fetches the instance server's certificate hashes again, before reconnecting.
+/// Servers using self-signed certificates rotate them, so the hashes from joining may be stale.
+#[derive(Component)]
+pub struct RefreshCertificates;
+
+#[derive(Error, Debug)]
+pub enum FetchCertificatesError {
+    #[error(transparent)]
+    Process(#[from] ProcessMessageError),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    #[error(transparent)]
+    Decode(#[from] base64::DecodeError),
+}
+
+/// Reads the certificate hashes published by a world host.
+/// Only published by servers using self-signed certificates.
+pub(crate) async fn fetch_certificate_hashes<D: DataStore, M: MessageStore>(
+    actor: &Actor<D, M>,
+    world_host: &str,
+) -> Result<Vec<CertificateHash>, FetchCertificatesError> {
+    let hash_msgs = actor
+        .query_records(RecordsFilter {
+            data_format: Some("application/json".to_string()),
+            protocol: Some(world_host_protocol_url()),
+            protocol_version: Some(WORLD_HOST_PROTOCOL_VERSION),
+            ..Default::default()
+        })
+        .target(world_host.to_string())
+        .send(world_host)
+        .await?;
+
+    let hash_msg = hash_msgs.entries.iter().find(|m| {
+        if let Descriptor::RecordsWrite(desc) = &m.descriptor {
+            desc.protocol_path.as_deref() == Some(CERTIFICATE_HASHES_PATH)
+        } else {
+            false
+        }
+    });
+
+    let Some(hash_msg) = hash_msg else {
+        return Ok(Vec::new());
+    };
+
+    let reply = actor
+        .read_record(hash_msg.record_id.clone())
+        .target(world_host.to_string())
+        .send(world_host)
+        .await?;
+
+    match &reply.record.data {
+        Some(Data::Base64(encoded)) => {
+            let data = URL_SAFE_NO_PAD.decode(encoded)?;
+            Ok(serde_json::from_slice(&data)?)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+pub fn refresh_certificates(
+    actor: Res<UserActor>,
+    mut commands: Commands,
+    mut pool: AsyncTaskPool<(Entity, Result<Vec<CertificateHash>, FetchCertificatesError>)>,
+    to_refresh: Query<(Entity, &InstanceRecord), Added<RefreshCertificates>>,
+) {
+    for (entity, record) in to_refresh.iter() {
+        let actor = actor.0.clone();
+        let world_host = record.0.did.clone();
+
+        pool.spawn(async move {
+            let res = fetch_certificate_hashes(&actor, &world_host).await;
+            (entity, res)
+        });
+    }
+
+    for task in pool.iter_poll() {
+        if let AsyncTaskStatus::Finished((entity, res)) = task {
+            let Some(mut entity) = commands.get_entity(entity) else {
+                continue;
+            };
+
+            match res {
+                Ok(hashes) => {
+                    entity.insert(InstanceServerCertificates(hashes));
+                }
+                Err(e) => {
+                    // Reconnect with the hashes we have, which may still be valid.
+                    warn!("Failed to refresh certificate hashes: {}", e);
+                }
+            }
+
+            entity.remove::<RefreshCertificates>();
+        }
+    }
+}
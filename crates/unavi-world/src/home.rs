@@ -12,7 +12,9 @@ use dwn::{
 use thiserror::Error;
 use unavi_dwn::{world_host::world_host_did, UserActor};
 use wired_social::{
-    protocols::world_host::{world_host_protocol_url, WORLD_HOST_PROTOCOL_VERSION},
+    protocols::world_host::{
+        world_host_protocol_url, CertificateHash, WORLD_HOST_PROTOCOL_VERSION,
+    },
     schemas::{
         common::RecordLink,
         home::{home_schema_url, Home},
@@ -21,7 +23,10 @@ use wired_social::{
     },
 };
 
-use crate::{InstanceRecord, InstanceServer, WorldRecord};
+use crate::{
+    certificates::{fetch_certificate_hashes, FetchCertificatesError},
+    InstanceRecord, InstanceServer, InstanceServerCertificates, WorldRecord,
+};
 
 #[derive(Event, Default)]
 pub struct JoinHome;
@@ -40,11 +45,14 @@ pub enum JoinHomeError {
     Serde(#[from] serde_json::Error),
     #[error(transparent)]
     Decode(#[from] base64::DecodeError),
+    #[error(transparent)]
+    Certificates(#[from] FetchCertificatesError),
 }
 
 pub struct JoinHomeResult {
     instance: RecordLink,
     instance_server: String,
+    instance_server_certificates: Vec<CertificateHash>,
     world: RecordLink,
 }
 
@@ -174,9 +182,14 @@ pub fn handle_join_home(
                         }
                     };
 
+                    let instance_server_certificates =
+                        fetch_certificate_hashes(&actor, world_host).await?;
+
                     // Create instance.
+                    // Owned by the actor, as the author of the record.
                     let data = Instance {
                         world: home.world.clone(),
+                        ..Default::default()
                     };
 
                     let instance_reply = actor
@@ -202,6 +215,7 @@ pub fn handle_join_home(
                             did: world_host.to_string(),
                         },
                         instance_server: connect_url,
+                        instance_server_certificates,
                         world: home.world,
                     })
                 });
@@ -213,11 +227,13 @@ pub fn handle_join_home(
                 Ok(JoinHomeResult {
                     instance,
                     instance_server,
+                    instance_server_certificates,
                     world,
                 }) => {
                     commands.spawn((
                         InstanceRecord(instance),
                         InstanceServer(instance_server),
+                        InstanceServerCertificates(instance_server_certificates),
                         WorldRecord(world),
                     ));
                 }
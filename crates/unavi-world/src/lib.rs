@@ -1,11 +1,14 @@
 use bevy::prelude::*;
 use home::JoinHome;
-use wired_social::schemas::common::RecordLink;
+use wired_social::{protocols::world_host::CertificateHash, schemas::common::RecordLink};
 
+mod certificates;
 mod home;
 mod loading;
 mod scene;
 
+pub use certificates::RefreshCertificates;
+
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
@@ -20,6 +23,7 @@ impl Plugin for WorldPlugin {
             .add_systems(
                 Update,
                 (
+                    certificates::refresh_certificates,
                     home::handle_join_home,
                     scene::create_world_scene,
                     loading::set_loading_state,
@@ -46,6 +50,10 @@ pub struct InstanceRecord(pub RecordLink);
 #[derive(Component)]
 pub struct InstanceServer(pub String);
 
+/// Hashes of the instance server's self-signed certificates, if it publishes any.
+#[derive(Component, Default)]
+pub struct InstanceServerCertificates(pub Vec<CertificateHash>);
+
 #[cfg(not(target_family = "wasm"))]
 fn add_atmosphere_cameras(
     mut commands: Commands,
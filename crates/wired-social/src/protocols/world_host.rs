@@ -1,5 +1,8 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use dwn::message::descriptor::protocols::ProtocolDefinition;
 use semver::Version;
+use serde::{Deserialize, Serialize};
 
 use crate::util::get_protocol_url;
 
@@ -8,6 +11,18 @@ const WORLD_HOST_PROTOCOL_DEFINITION: &[u8] =
 
 pub const WORLD_HOST_PROTOCOL_VERSION: Version = Version::new(0, 0, 1);
 
+/// Protocol path of the record listing the world server's certificate hashes,
+/// as a JSON array of [`CertificateHash`].
+pub const CERTIFICATE_HASHES_PATH: &str = "certificate-hashes";
+
+/// Protocol path of the record listing banned DIDs, as JSON [`Bans`].
+/// Private to the world host.
+pub const BANS_PATH: &str = "bans";
+
+/// Protocol path of the record listing muted DIDs, as JSON [`Mutes`].
+/// Private to the world host.
+pub const MUTES_PATH: &str = "mutes";
+
 pub fn world_host_definition() -> ProtocolDefinition {
     serde_json::from_slice(WORLD_HOST_PROTOCOL_DEFINITION).unwrap()
 }
@@ -16,10 +31,139 @@ pub fn world_host_protocol_url() -> String {
     get_protocol_url(WORLD_HOST_PROTOCOL_DEFINITION).unwrap()
 }
 
+/// Hash of a certificate the world server may present.
+/// Matches the format of WebTransport `serverCertificateHashes`, allowing browsers to connect
+/// to servers with self-signed certificates.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateHash {
+    pub algorithm: String,
+    /// Hex encoded digest.
+    pub value: String,
+}
+
+impl CertificateHash {
+    pub const SHA_256: &'static str = "sha-256";
+
+    pub fn sha256(digest: &[u8; 32]) -> Self {
+        Self {
+            algorithm: Self::SHA_256.to_string(),
+            value: digest.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+
+    /// Decodes a SHA-256 digest.
+    /// Returns `None` for other algorithms, or an invalid value.
+    pub fn sha256_digest(&self) -> Option<[u8; 32]> {
+        if self.algorithm != Self::SHA_256 || self.value.len() != 64 {
+            return None;
+        }
+
+        let mut digest = [0; 32];
+
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(self.value.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+
+        Some(digest)
+    }
+}
+
+/// DIDs listed for every instance on the world host, or for specific instances.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidList {
+    /// Listed for every instance.
+    pub host: BTreeSet<String>,
+    /// Listed for specific instances, keyed by instance record id.
+    pub instances: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// DIDs banned from joining instances on the world host.
+pub type Bans = DidList;
+
+/// DIDs whose chat and voice are not relayed in instances on the world host.
+pub type Mutes = DidList;
+
+impl DidList {
+    pub fn contains(&self, did: &str, instance: &str) -> bool {
+        self.host.contains(did)
+            || self
+                .instances
+                .get(instance)
+                .is_some_and(|dids| dids.contains(did))
+    }
+
+    /// Lists a DID for an instance, or the whole host if `instance` is `None`.
+    /// Returns whether the list changed.
+    pub fn insert(&mut self, did: &str, instance: Option<&str>) -> bool {
+        match instance {
+            Some(instance) => self
+                .instances
+                .entry(instance.to_string())
+                .or_default()
+                .insert(did.to_string()),
+            None => self.host.insert(did.to_string()),
+        }
+    }
+
+    /// Reverses [`DidList::insert`].
+    /// Returns whether the list changed.
+    pub fn remove(&mut self, did: &str, instance: Option<&str>) -> bool {
+        match instance {
+            Some(instance) => {
+                let Some(dids) = self.instances.get_mut(instance) else {
+                    return false;
+                };
+
+                let removed = dids.remove(did);
+
+                if dids.is_empty() {
+                    self.instances.remove(instance);
+                }
+
+                removed
+            }
+            None => self.host.remove(did),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_did_list() {
+        let mut list = DidList::default();
+
+        assert!(list.insert("did:example:a", Some("instance")));
+        assert!(!list.insert("did:example:a", Some("instance")));
+        assert!(list.contains("did:example:a", "instance"));
+        assert!(!list.contains("did:example:a", "other"));
+
+        assert!(list.insert("did:example:b", None));
+        assert!(list.contains("did:example:b", "other"));
+
+        assert!(list.remove("did:example:a", Some("instance")));
+        assert!(!list.contains("did:example:a", "instance"));
+        assert!(list.instances.is_empty());
+        assert!(!list.remove("did:example:a", None));
+    }
+
+    #[test]
+    fn test_certificate_hash() {
+        let digest = std::array::from_fn(|i| i as u8 * 7);
+
+        let hash = CertificateHash::sha256(&digest);
+        assert_eq!(hash.value.len(), 64);
+        assert_eq!(hash.sha256_digest(), Some(digest));
+
+        let invalid = CertificateHash {
+            value: "zz".repeat(32),
+            ..hash
+        };
+        assert_eq!(invalid.sha256_digest(), None);
+    }
+
     #[test]
     fn test_definition() {
         let definition = world_host_definition();
@@ -10,6 +10,40 @@ const INSTANCE_SCHEMA: &[u8] =
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Instance {
     pub world: RecordLink,
+    #[serde(default, skip_serializing_if = "Access::is_public")]
+    pub access: Access,
+}
+
+impl Instance {
+    /// Whether a player with the given DID may join the instance.
+    /// The owner is the author of the instance record, so cannot be claimed by the record itself.
+    pub fn can_join(&self, owner: &str, did: &str) -> bool {
+        let is_owner = owner == did;
+
+        match &self.access {
+            Access::Public => true,
+            Access::Invite { dids } => is_owner || dids.iter().any(|d| d == did),
+            Access::Owner => is_owner,
+        }
+    }
+}
+
+/// Who is allowed to join an instance.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Access {
+    #[default]
+    Public,
+    /// Only the owner and the listed DIDs.
+    Invite { dids: Vec<String> },
+    /// Only the owner.
+    Owner,
+}
+
+impl Access {
+    pub fn is_public(&self) -> bool {
+        *self == Self::Public
+    }
 }
 
 pub fn instance_schema_url() -> String {
@@ -29,6 +63,7 @@ mod tests {
                 did: "did:example:123".to_string(),
                 record_id: "abcde".to_string(),
             },
+            ..Default::default()
         };
 
         let serialized = serde_json::to_vec(&instance).unwrap();
@@ -42,6 +77,31 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_access() {
+        let owner = "did:example:owner";
+        let guest = "did:example:guest";
+        let stranger = "did:example:stranger";
+
+        let mut instance = Instance::default();
+        assert!(instance.can_join(owner, stranger));
+
+        instance.access = Access::Invite {
+            dids: vec![guest.to_string()],
+        };
+        assert!(instance.can_join(owner, owner));
+        assert!(instance.can_join(owner, guest));
+        assert!(!instance.can_join(owner, stranger));
+
+        instance.access = Access::Owner;
+        assert!(instance.can_join(owner, owner));
+        assert!(!instance.can_join(owner, guest));
+
+        let serialized = serde_json::to_string(&instance).unwrap();
+        let deserialized: Instance = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.access, Access::Owner);
+    }
+
     #[test]
     fn test_schema_url() {
         let url = instance_schema_url();
@@ -1,4 +1,4 @@
-const SCHEMAS: &str = "../../wired-protocol/spatial/capnp";
+const SCHEMAS: &str = "schema";
 
 fn main() {
     capnpc::CompilerCommand::new()
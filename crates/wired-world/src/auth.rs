@@ -0,0 +1,73 @@
+//! Join authentication.
+//!
+//! The server sends a random nonce, which the client signs to prove control of its DID.
+//! The signed payload names the world host the client is joining, so a server cannot pass
+//! another server's challenge on to a client, and use the signature to authenticate as them.
+
+/// Length of the server nonce, in bytes.
+pub const NONCE_LEN: usize = 32;
+
+const PAYLOAD_PREFIX: &[u8] = b"wired-world-auth:";
+
+/// Bytes to sign for a challenge from the server of `world_host_did`.
+/// Prefixed so that a signature cannot be reused outside of this protocol.
+pub fn challenge_payload(world_host_did: &str, nonce: &[u8]) -> Vec<u8> {
+    // DIDs cannot contain a null byte, so the nonce cannot be mistaken for part of the DID.
+    [PAYLOAD_PREFIX, world_host_did.as_bytes(), b"\0", nonce].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use didkit::{
+        ssi::jws::{sign_bytes, verify_bytes},
+        JWK,
+    };
+
+    use super::*;
+
+    const WORLD_HOST: &str = "did:web:example.com";
+    const OTHER_HOST: &str = "did:web:other.example.com";
+
+    fn sign(key: &JWK, world_host_did: &str, nonce: &[u8]) -> Vec<u8> {
+        let algorithm = key.get_algorithm().unwrap();
+        sign_bytes(algorithm, &challenge_payload(world_host_did, nonce), key).unwrap()
+    }
+
+    fn verify(key: &JWK, world_host_did: &str, nonce: &[u8], signature: &[u8]) -> bool {
+        let algorithm = key.get_algorithm().unwrap();
+        let payload = challenge_payload(world_host_did, nonce);
+        verify_bytes(algorithm, &payload, &key.to_public(), signature).is_ok()
+    }
+
+    #[test]
+    fn test_challenge() {
+        let key = JWK::generate_ed25519().unwrap();
+        let nonce = [1; NONCE_LEN];
+
+        let signature = sign(&key, WORLD_HOST, &nonce);
+        assert!(verify(&key, WORLD_HOST, &nonce, &signature));
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let key = JWK::generate_ed25519().unwrap();
+        let other = JWK::generate_ed25519().unwrap();
+        let nonce = [1; NONCE_LEN];
+
+        let signature = sign(&other, WORLD_HOST, &nonce);
+        assert!(!verify(&key, WORLD_HOST, &nonce, &signature));
+    }
+
+    #[test]
+    fn test_reused_signature() {
+        let key = JWK::generate_ed25519().unwrap();
+        let nonce = [1; NONCE_LEN];
+        let signature = sign(&key, WORLD_HOST, &nonce);
+
+        // Nonces are single use, so the next challenge has a new one.
+        assert!(!verify(&key, WORLD_HOST, &[2; NONCE_LEN], &signature));
+
+        // Relayed from another server.
+        assert!(!verify(&key, OTHER_HOST, &nonce, &signature));
+    }
+}
@@ -7,3 +7,6 @@ pub mod world_server_capnp {
 pub mod datagram_capnp {
     include!(concat!(env!("OUT_DIR"), "/datagram_capnp.rs"));
 }
+
+pub mod auth;
+pub mod quantize;
@@ -0,0 +1,238 @@
+//! Quantization of transforms, for compact snapshot datagrams.
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// Quantized translation units per meter.
+pub const TRANSLATION_PRECISION: f32 = 1000.0;
+/// Quantized velocity units per meter per second.
+pub const VELOCITY_PRECISION: f32 = 100.0;
+
+const ROTATION_BITS: u32 = 10;
+const ROTATION_MASK: u32 = (1 << ROTATION_BITS) - 1;
+/// One less than the mask, so that zero can be represented exactly.
+const ROTATION_MAX: u32 = ROTATION_MASK - 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuantizedTransform {
+    /// Translation relative to an origin, in units of 1 / [TRANSLATION_PRECISION] meters.
+    pub translation: [i32; 3],
+    /// Smallest-three encoded rotation. See [pack_rotation].
+    pub rotation: u32,
+}
+
+impl QuantizedTransform {
+    pub fn new(translation: [f32; 3], rotation: [f32; 4], origin: [f32; 3]) -> Self {
+        Self {
+            translation: quantize_translation(translation, origin),
+            rotation: pack_rotation(rotation),
+        }
+    }
+
+    pub fn translation(&self, origin: [f32; 3]) -> [f32; 3] {
+        dequantize_translation(self.translation, origin)
+    }
+
+    pub fn rotation(&self) -> [f32; 4] {
+        unpack_rotation(self.rotation)
+    }
+
+    /// Encodes `self` as a delta against `baseline`.
+    /// Unchanged values encode to zero, which compresses well with packed serialization.
+    pub fn delta(&self, baseline: &Self) -> Self {
+        Self {
+            translation: [
+                self.translation[0].wrapping_sub(baseline.translation[0]),
+                self.translation[1].wrapping_sub(baseline.translation[1]),
+                self.translation[2].wrapping_sub(baseline.translation[2]),
+            ],
+            rotation: self.rotation ^ baseline.rotation,
+        }
+    }
+
+    /// Inverse of [Self::delta].
+    pub fn apply_delta(&self, baseline: &Self) -> Self {
+        Self {
+            translation: [
+                self.translation[0].wrapping_add(baseline.translation[0]),
+                self.translation[1].wrapping_add(baseline.translation[1]),
+                self.translation[2].wrapping_add(baseline.translation[2]),
+            ],
+            rotation: self.rotation ^ baseline.rotation,
+        }
+    }
+}
+
+pub fn quantize_translation(translation: [f32; 3], origin: [f32; 3]) -> [i32; 3] {
+    let q = |i: usize| ((translation[i] - origin[i]) * TRANSLATION_PRECISION).round() as i32;
+    [q(0), q(1), q(2)]
+}
+
+pub fn dequantize_translation(translation: [i32; 3], origin: [f32; 3]) -> [f32; 3] {
+    let d = |i: usize| origin[i] + translation[i] as f32 / TRANSLATION_PRECISION;
+    [d(0), d(1), d(2)]
+}
+
+/// Quantizes a small offset, such as a hand relative to the player, saturating at ±32 meters.
+pub fn quantize_offset(offset: [f32; 3]) -> [i16; 3] {
+    offset.map(|v| (v * TRANSLATION_PRECISION).round() as i16)
+}
+
+pub fn dequantize_offset(offset: [i16; 3]) -> [f32; 3] {
+    offset.map(|v| v as f32 / TRANSLATION_PRECISION)
+}
+
+/// Quantizes a velocity, saturating at ±327 meters per second.
+pub fn quantize_velocity(velocity: [f32; 3]) -> [i16; 3] {
+    velocity.map(|v| (v * VELOCITY_PRECISION).round() as i16)
+}
+
+pub fn dequantize_velocity(velocity: [i16; 3]) -> [f32; 3] {
+    velocity.map(|v| v as f32 / VELOCITY_PRECISION)
+}
+
+/// Packs a quaternion (x, y, z, w) into 32 bits, using smallest-three compression.
+///
+/// The largest component is dropped, as it can be recomputed from the other three.
+/// The top 2 bits store its index, and each remaining component uses 10 bits.
+pub fn pack_rotation(rotation: [f32; 4]) -> u32 {
+    let mut rotation = rotation;
+    let mut len = rotation.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+    if !len.is_finite() || len == 0.0 {
+        rotation = [0.0, 0.0, 0.0, 1.0];
+        len = 1.0;
+    }
+
+    let mut largest = 0;
+    for i in 1..4 {
+        if rotation[i].abs() > rotation[largest].abs() {
+            largest = i;
+        }
+    }
+
+    // q and -q are the same rotation, so the largest component can always be positive.
+    let sign = if rotation[largest] < 0.0 { -1.0 } else { 1.0 };
+
+    let mut packed = (largest as u32) << (ROTATION_BITS * 3);
+    let mut shift = ROTATION_BITS * 2;
+
+    for (i, value) in rotation.iter().enumerate() {
+        if i == largest {
+            continue;
+        }
+
+        let value = value * sign / len;
+        let normalized = ((value + FRAC_1_SQRT_2) / (2.0 * FRAC_1_SQRT_2)).clamp(0.0, 1.0);
+        let quantized = (normalized * ROTATION_MAX as f32).round() as u32;
+
+        packed |= quantized << shift;
+        shift = shift.saturating_sub(ROTATION_BITS);
+    }
+
+    packed
+}
+
+/// Inverse of [pack_rotation].
+pub fn unpack_rotation(packed: u32) -> [f32; 4] {
+    let largest = (packed >> (ROTATION_BITS * 3)) as usize & 0b11;
+
+    let mut rotation = [0.0; 4];
+    let mut shift = ROTATION_BITS * 2;
+    let mut sum = 0.0;
+
+    for (i, value) in rotation.iter_mut().enumerate() {
+        if i == largest {
+            continue;
+        }
+
+        let quantized = ((packed >> shift) & ROTATION_MASK).min(ROTATION_MAX);
+        let normalized = quantized as f32 / ROTATION_MAX as f32;
+        *value = normalized * (2.0 * FRAC_1_SQRT_2) - FRAC_1_SQRT_2;
+        sum += *value * *value;
+
+        shift = shift.saturating_sub(ROTATION_BITS);
+    }
+
+    rotation[largest] = (1.0 - sum).max(0.0).sqrt();
+
+    rotation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compares rotations, accounting for q == -q.
+    fn assert_rotation_eq(a: [f32; 4], b: [f32; 4]) {
+        let dot = a.iter().zip(b.iter()).map(|(a, b)| a * b).sum::<f32>();
+        assert!(dot.abs() > 0.9999, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_translation() {
+        let origin = [100.0, 0.0, -50.0];
+        let translation = [101.25, -3.5, -49.0];
+
+        let q = quantize_translation(translation, origin);
+        assert_eq!(q, [1250, -3500, 1000]);
+
+        let d = dequantize_translation(q, origin);
+        for i in 0..3 {
+            assert!((d[i] - translation[i]).abs() <= 0.5 / TRANSLATION_PRECISION);
+        }
+    }
+
+    #[test]
+    fn test_offset() {
+        let q = quantize_offset([0.25, -1.5, 100.0]);
+        assert_eq!(q, [250, -1500, i16::MAX]);
+        assert_eq!(dequantize_offset(q)[..2], [0.25, -1.5]);
+    }
+
+    #[test]
+    fn test_velocity() {
+        let q = quantize_velocity([4.25, -9.81, f32::NAN]);
+        assert_eq!(q, [425, -981, 0]);
+        assert_eq!(dequantize_velocity(q), [4.25, -9.81, 0.0]);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let rotations = [
+            [0.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0, -1.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.5, 0.5, 0.5, 0.5],
+            [0.5, -0.5, 0.5, -0.5],
+            [0.0, FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2],
+            [0.1825742, 0.3651484, 0.5477226, 0.7302967],
+            [-0.7302967, 0.1825742, -0.3651484, 0.5477226],
+        ];
+
+        for rotation in rotations {
+            let unpacked = unpack_rotation(pack_rotation(rotation));
+            assert_rotation_eq(rotation, unpacked);
+        }
+    }
+
+    #[test]
+    fn test_identity_rotation() {
+        let identity = [0.0, 0.0, 0.0, 1.0];
+        assert_eq!(unpack_rotation(pack_rotation(identity)), identity);
+        assert_eq!(unpack_rotation(pack_rotation([f32::NAN, 0.0, 0.0, 0.0])), identity);
+    }
+
+    #[test]
+    fn test_delta() {
+        let origin = [0.0; 3];
+        let baseline = QuantizedTransform::new([1.0, 2.0, 3.0], [0.0, 0.0, 0.0, 1.0], origin);
+        let current = QuantizedTransform::new([1.5, 2.0, 2.0], [0.5, 0.5, 0.5, 0.5], origin);
+
+        let delta = current.delta(&baseline);
+        assert_eq!(delta.translation, [500, 0, -1000]);
+        assert_eq!(delta.apply_delta(&baseline), current);
+
+        let unchanged = current.delta(&current);
+        assert_eq!(unchanged, QuantizedTransform::default());
+    }
+}
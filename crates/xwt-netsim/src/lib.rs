@@ -0,0 +1,297 @@
+//! Network condition simulation for [xwt](https://github.com/MOZGIII/xwt) sessions.
+//!
+//! [SimulatedSession] adds latency, jitter, loss, reordering, and a bandwidth cap to datagrams,
+//! for reproducing bad networks locally.
+//! Streams are reliable and ordered, so only [Conditions::latency] applies to them.
+//!
+//! Delayed datagrams and stream data are sent from tasks spawned with [tokio::task::spawn_local],
+//! so sessions with non-ideal conditions must be used within a [tokio::task::LocalSet].
+
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+use tracing::warn;
+use xwt_core::{
+    session::{
+        datagram::{Receive, ReceiveInto, Send},
+        stream::{
+            AcceptBi, AcceptUni, OpenBi, OpenUni, OpeningBi, OpeningUni, RecvSpec, SendSpec,
+            TupleFor,
+        },
+    },
+    utils::maybe,
+};
+
+use link::{DelayQueue, Link};
+
+mod link;
+mod stream;
+
+pub use link::Conditions;
+pub use stream::{SimulatedRecvStream, SimulatedSendStream, StreamError};
+
+pub struct SimulatedSession<S: Receive> {
+    inner: Arc<S>,
+    /// Delay added to stream data.
+    latency: Duration,
+    /// `None` when conditions are ideal, and datagrams are passed through.
+    links: Option<Links<S::Datagram>>,
+}
+
+struct Links<T> {
+    incoming: Mutex<Incoming<T>>,
+    outgoing: Mutex<Link>,
+}
+
+struct Incoming<T> {
+    link: Link,
+    queue: DelayQueue<T>,
+}
+
+impl<S: Receive> SimulatedSession<S> {
+    pub fn new(inner: S, conditions: Conditions) -> Self {
+        let links = if conditions.is_ideal() {
+            None
+        } else {
+            Some(Links {
+                incoming: Mutex::new(Incoming {
+                    link: Link::new(conditions.clone(), StdRng::from_entropy()),
+                    queue: DelayQueue::default(),
+                }),
+                outgoing: Mutex::new(Link::new(conditions.clone(), StdRng::from_entropy())),
+            })
+        };
+
+        Self {
+            inner: Arc::new(inner),
+            latency: conditions.latency,
+            links,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Receive + SendSpec + maybe::Sync> SendSpec for SimulatedSession<S>
+where
+    S::SendStream: 'static,
+{
+    type SendStream = SimulatedSendStream<S::SendStream>;
+}
+
+impl<S: Receive + RecvSpec + maybe::Sync> RecvSpec for SimulatedSession<S>
+where
+    S::RecvStream: 'static,
+{
+    type RecvStream = SimulatedRecvStream<S::RecvStream>;
+}
+
+/// A bi stream being opened on a [SimulatedSession].
+pub struct SimulatedOpeningBi<S: OpenBi> {
+    inner: S::Opening,
+    latency: Duration,
+    _session: PhantomData<fn() -> S>,
+}
+
+impl<S: Receive + OpenBi + maybe::Sync> OpeningBi for SimulatedOpeningBi<S>
+where
+    S::SendStream: 'static,
+    S::RecvStream: 'static,
+{
+    type Streams = SimulatedSession<S>;
+    type Error = <S::Opening as OpeningBi>::Error;
+
+    async fn wait_bi(self) -> Result<TupleFor<Self::Streams>, Self::Error> {
+        let (send, recv) = self.inner.wait_bi().await?;
+
+        Ok((
+            SimulatedSendStream::new(send, self.latency),
+            SimulatedRecvStream::new(recv, self.latency),
+        ))
+    }
+}
+
+impl<S: Receive + OpenBi + maybe::Sync> OpenBi for SimulatedSession<S>
+where
+    S::SendStream: 'static,
+    S::RecvStream: 'static,
+{
+    type Opening = SimulatedOpeningBi<S>;
+    type Error = <S as OpenBi>::Error;
+
+    async fn open_bi(&self) -> Result<Self::Opening, Self::Error> {
+        Ok(SimulatedOpeningBi {
+            inner: self.inner.open_bi().await?,
+            latency: self.latency,
+            _session: PhantomData,
+        })
+    }
+}
+
+/// A uni stream being opened on a [SimulatedSession].
+pub struct SimulatedOpeningUni<S: OpenUni> {
+    inner: S::Opening,
+    latency: Duration,
+    _session: PhantomData<fn() -> S>,
+}
+
+impl<S: Receive + OpenUni + maybe::Sync> OpeningUni for SimulatedOpeningUni<S>
+where
+    S::SendStream: 'static,
+{
+    type Streams = SimulatedSession<S>;
+    type Error = <S::Opening as OpeningUni>::Error;
+
+    async fn wait_uni(self) -> Result<SimulatedSendStream<S::SendStream>, Self::Error> {
+        let send = self.inner.wait_uni().await?;
+        Ok(SimulatedSendStream::new(send, self.latency))
+    }
+}
+
+impl<S: Receive + OpenUni + maybe::Sync> OpenUni for SimulatedSession<S>
+where
+    S::SendStream: 'static,
+{
+    type Opening = SimulatedOpeningUni<S>;
+    type Error = <S as OpenUni>::Error;
+
+    async fn open_uni(&self) -> Result<Self::Opening, Self::Error> {
+        Ok(SimulatedOpeningUni {
+            inner: self.inner.open_uni().await?,
+            latency: self.latency,
+            _session: PhantomData,
+        })
+    }
+}
+
+impl<S: Receive + AcceptBi + maybe::Sync> AcceptBi for SimulatedSession<S>
+where
+    S::SendStream: 'static,
+    S::RecvStream: 'static,
+{
+    type Error = <S as AcceptBi>::Error;
+
+    async fn accept_bi(&self) -> Result<TupleFor<Self>, Self::Error> {
+        let (send, recv) = self.inner.accept_bi().await?;
+
+        Ok((
+            SimulatedSendStream::new(send, self.latency),
+            SimulatedRecvStream::new(recv, self.latency),
+        ))
+    }
+}
+
+impl<S: Receive + AcceptUni + maybe::Sync> AcceptUni for SimulatedSession<S>
+where
+    S::RecvStream: 'static,
+{
+    type Error = <S as AcceptUni>::Error;
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::Error> {
+        let recv = self.inner.accept_uni().await?;
+        Ok(SimulatedRecvStream::new(recv, self.latency))
+    }
+}
+
+impl<S: Receive + Send + maybe::Sync + 'static> Send for SimulatedSession<S> {
+    type Error = <S as Send>::Error;
+
+    async fn send_datagram<D>(&self, payload: D) -> Result<(), Self::Error>
+    where
+        D: maybe::Send + AsRef<[u8]>,
+    {
+        let Some(links) = &self.links else {
+            return self.inner.send_datagram(payload).await;
+        };
+
+        let now = Instant::now();
+        let len = payload.as_ref().len();
+
+        let Some(at) = links.outgoing.lock().unwrap().schedule(now, len) else {
+            return Ok(());
+        };
+
+        if at <= now {
+            return self.inner.send_datagram(payload).await;
+        }
+
+        let inner = self.inner.clone();
+        let data = payload.as_ref().to_vec();
+
+        tokio::task::spawn_local(async move {
+            tokio::time::sleep_until(at.into()).await;
+
+            if let Err(e) = inner.send_datagram(data).await {
+                warn!("Failed to send delayed datagram: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl<S: Receive + maybe::Sync> Receive for SimulatedSession<S> {
+    type Datagram = S::Datagram;
+    type Error = <S as Receive>::Error;
+
+    async fn receive_datagram(&self) -> Result<Self::Datagram, Self::Error> {
+        let Some(links) = &self.links else {
+            return self.inner.receive_datagram().await;
+        };
+
+        loop {
+            let next_at = {
+                let mut incoming = links.incoming.lock().unwrap();
+
+                if let Some(datagram) = incoming.queue.pop_ready(Instant::now()) {
+                    return Ok(datagram);
+                }
+
+                incoming.queue.next_at()
+            };
+
+            let delivery = async {
+                match next_at {
+                    Some(at) => tokio::time::sleep_until(at.into()).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                datagram = self.inner.receive_datagram() => {
+                    let datagram = datagram?;
+                    let now = Instant::now();
+
+                    let mut incoming = links.incoming.lock().unwrap();
+
+                    match incoming.link.schedule(now, datagram.as_ref().len()) {
+                        Some(at) if at <= now && incoming.queue.is_empty() => return Ok(datagram),
+                        Some(at) => incoming.queue.push(at, datagram),
+                        None => {}
+                    }
+                }
+                _ = delivery => {}
+            }
+        }
+    }
+}
+
+impl<S: Receive + maybe::Sync> ReceiveInto for SimulatedSession<S> {
+    type Error = <S as Receive>::Error;
+
+    async fn receive_datagram_into(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let datagram = self.receive_datagram().await?;
+        let data = datagram.as_ref();
+
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+}
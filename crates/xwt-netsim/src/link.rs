@@ -0,0 +1,263 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng};
+
+/// Extra delay of reordered datagrams, so datagrams sent after them arrive first.
+const REORDER_DELAY: Duration = Duration::from_millis(20);
+/// Longest a datagram can wait for bandwidth before being dropped, like a full router queue.
+const MAX_QUEUE_DELAY: Duration = Duration::from_millis(500);
+
+/// Network conditions to simulate, applied to datagrams in each direction.
+///
+/// Only [Conditions::latency] applies to streams.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Conditions {
+    /// One-way delay added to each datagram.
+    pub latency: Duration,
+    /// Maximum random variation in latency, in either direction.
+    pub jitter: Duration,
+    /// Chance of dropping each datagram, from 0 to 1.
+    pub loss: f32,
+    /// Chance of delaying a datagram so later ones overtake it, from 0 to 1.
+    pub reorder: f32,
+    /// Maximum throughput, in bytes per second.
+    pub bandwidth: Option<u32>,
+}
+
+impl Conditions {
+    /// Whether datagrams pass through unchanged.
+    pub fn is_ideal(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// One direction of a simulated link.
+pub(crate) struct Link {
+    /// When the link finishes transmitting datagrams queued for bandwidth.
+    busy_until: Option<Instant>,
+    conditions: Conditions,
+    rng: StdRng,
+}
+
+impl Link {
+    pub fn new(conditions: Conditions, rng: StdRng) -> Self {
+        Self {
+            busy_until: None,
+            conditions,
+            rng,
+        }
+    }
+
+    /// When a datagram of `len` bytes sent at `now` arrives, or `None` if it is lost.
+    pub fn schedule(&mut self, now: Instant, len: usize) -> Option<Instant> {
+        if self
+            .rng
+            .gen_bool(self.conditions.loss.clamp(0.0, 1.0) as f64)
+        {
+            return None;
+        }
+
+        let mut departure = now;
+
+        if let Some(bandwidth) = self.conditions.bandwidth {
+            let start = self.busy_until.map_or(now, |busy| busy.max(now));
+
+            if start - now > MAX_QUEUE_DELAY {
+                return None;
+            }
+
+            departure = start + Duration::from_secs_f64(len as f64 / bandwidth.max(1) as f64);
+            self.busy_until = Some(departure);
+        }
+
+        let mut delay = self.conditions.latency;
+
+        if !self.conditions.jitter.is_zero() {
+            let jitter = self.conditions.jitter.as_secs_f64() * self.rng.gen_range(-1.0..=1.0);
+            delay = Duration::from_secs_f64((delay.as_secs_f64() + jitter).max(0.0));
+        }
+
+        if self
+            .rng
+            .gen_bool(self.conditions.reorder.clamp(0.0, 1.0) as f64)
+        {
+            delay += REORDER_DELAY;
+        }
+
+        Some(departure + delay)
+    }
+}
+
+/// Datagrams waiting to be delivered, ordered by arrival time.
+pub(crate) struct DelayQueue<T> {
+    entries: BinaryHeap<Entry<T>>,
+    /// Keeps datagrams arriving at the same time in the order they were sent.
+    next_sequence: u64,
+}
+
+impl<T> Default for DelayQueue<T> {
+    fn default() -> Self {
+        Self {
+            entries: BinaryHeap::new(),
+            next_sequence: 0,
+        }
+    }
+}
+
+impl<T> DelayQueue<T> {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, at: Instant, value: T) {
+        self.entries.push(Entry {
+            at,
+            sequence: self.next_sequence,
+            value,
+        });
+        self.next_sequence += 1;
+    }
+
+    /// Arrival time of the next datagram.
+    pub fn next_at(&self) -> Option<Instant> {
+        self.entries.peek().map(|entry| entry.at)
+    }
+
+    /// Removes the next datagram, if it has arrived by `now`.
+    pub fn pop_ready(&mut self, now: Instant) -> Option<T> {
+        if self.next_at()? > now {
+            return None;
+        }
+
+        self.entries.pop().map(|entry| entry.value)
+    }
+}
+
+struct Entry<T> {
+    at: Instant,
+    sequence: u64,
+    value: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    // Reversed, so the heap pops the earliest entry first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .at
+            .cmp(&self.at)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn link(conditions: Conditions) -> Link {
+        Link::new(conditions, StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn test_ideal() {
+        let mut link = link(Conditions::default());
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert_eq!(link.schedule(now, 1200), Some(now));
+        }
+    }
+
+    #[test]
+    fn test_latency_and_jitter() {
+        let mut link = link(Conditions {
+            latency: Duration::from_millis(100),
+            jitter: Duration::from_millis(10),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            let delay = link.schedule(now, 1200).unwrap() - now;
+            assert!(delay >= Duration::from_millis(90));
+            assert!(delay <= Duration::from_millis(110));
+        }
+    }
+
+    #[test]
+    fn test_loss() {
+        let mut link = link(Conditions {
+            loss: 0.25,
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        let delivered = (0..10_000)
+            .filter(|_| link.schedule(now, 1200).is_some())
+            .count();
+        assert!((7_000..8_000).contains(&delivered));
+    }
+
+    #[test]
+    fn test_bandwidth() {
+        let mut link = link(Conditions {
+            bandwidth: Some(10_000),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        // Sent at once, so each waits for the ones before it.
+        assert_eq!(
+            link.schedule(now, 1000),
+            Some(now + Duration::from_millis(100))
+        );
+        assert_eq!(
+            link.schedule(now, 1000),
+            Some(now + Duration::from_millis(200))
+        );
+
+        // Dropped once the queue is full.
+        let delivered = (0..10)
+            .filter(|_| link.schedule(now, 1000).is_some())
+            .count();
+        assert_eq!(delivered, 4);
+    }
+
+    #[test]
+    fn test_delay_queue() {
+        let mut queue = DelayQueue::default();
+        let now = Instant::now();
+
+        queue.push(now + Duration::from_millis(20), 1);
+        queue.push(now + Duration::from_millis(10), 2);
+        queue.push(now + Duration::from_millis(10), 3);
+
+        assert_eq!(queue.pop_ready(now), None);
+        assert_eq!(queue.next_at(), Some(now + Duration::from_millis(10)));
+
+        let later = now + Duration::from_millis(30);
+        assert_eq!(queue.pop_ready(later), Some(2));
+        assert_eq!(queue.pop_ready(later), Some(3));
+        assert_eq!(queue.pop_ready(later), Some(1));
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    fmt::{Debug, Display},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use xwt_core::stream::{Read, Write};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Send stream that delivers writes after the simulated latency.
+///
+/// Writes are queued and return immediately, so errors from the inner stream
+/// are reported by the write after the one that failed.
+pub struct SimulatedSendStream<W: Write> {
+    inner: SendInner<W>,
+}
+
+enum SendInner<W: Write> {
+    Direct(W),
+    Delayed {
+        error: Arc<Mutex<Option<W::Error>>>,
+        latency: Duration,
+        sender: UnboundedSender<(Instant, Vec<u8>)>,
+    },
+}
+
+impl<W: Write + 'static> SimulatedSendStream<W> {
+    pub(crate) fn new(mut inner: W, latency: Duration) -> Self {
+        if latency.is_zero() {
+            return Self {
+                inner: SendInner::Direct(inner),
+            };
+        }
+
+        let error = Arc::new(Mutex::new(None));
+        let (sender, mut receiver) = unbounded_channel::<(Instant, Vec<u8>)>();
+
+        let task_error = error.clone();
+        tokio::task::spawn_local(async move {
+            while let Some((at, data)) = receiver.recv().await {
+                tokio::time::sleep_until(at.into()).await;
+
+                let mut written = 0;
+
+                while written < data.len() {
+                    match inner.write(&data[written..]).await {
+                        Ok(len) => written += len,
+                        Err(e) => {
+                            *task_error.lock().unwrap() = Some(e);
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            inner: SendInner::Delayed {
+                error,
+                latency,
+                sender,
+            },
+        }
+    }
+}
+
+impl<W: Write> Write for SimulatedSendStream<W> {
+    type Error = StreamError<W::Error>;
+
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        match &mut self.inner {
+            SendInner::Direct(inner) => inner.write(buf).await.map_err(StreamError::Inner),
+            SendInner::Delayed {
+                error,
+                latency,
+                sender,
+            } => {
+                if let Some(e) = error.lock().unwrap().take() {
+                    return Err(StreamError::Inner(e));
+                }
+
+                let at = Instant::now() + *latency;
+
+                sender
+                    .send((at, buf.to_vec()))
+                    .map_err(|_| StreamError::Closed)?;
+
+                Ok(buf.len())
+            }
+        }
+    }
+}
+
+/// Receive stream that holds read data back for the simulated latency.
+pub struct SimulatedRecvStream<R: Read> {
+    inner: RecvInner<R>,
+}
+
+enum RecvInner<R: Read> {
+    Direct(R),
+    Delayed {
+        /// Data that has been read but not yet returned, and when it arrives.
+        pending: Option<(Instant, Vec<u8>)>,
+        receiver: UnboundedReceiver<Result<(Instant, Vec<u8>), R::Error>>,
+    },
+}
+
+impl<R: Read + 'static> SimulatedRecvStream<R> {
+    pub(crate) fn new(mut inner: R, latency: Duration) -> Self {
+        if latency.is_zero() {
+            return Self {
+                inner: RecvInner::Direct(inner),
+            };
+        }
+
+        let (sender, receiver) = unbounded_channel();
+
+        // Reads ahead, so data keeps arriving while earlier data is held back.
+        tokio::task::spawn_local(async move {
+            let mut buf = vec![0; READ_BUFFER_SIZE];
+
+            loop {
+                let chunk = match inner.read(&mut buf).await {
+                    Ok(Some(len)) => Ok((Instant::now() + latency, buf[..len].to_vec())),
+                    Ok(None) => return,
+                    Err(e) => Err(e),
+                };
+
+                let failed = chunk.is_err();
+
+                if sender.send(chunk).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            inner: RecvInner::Delayed {
+                pending: None,
+                receiver,
+            },
+        }
+    }
+}
+
+impl<R: Read> Read for SimulatedRecvStream<R> {
+    type Error = StreamError<R::Error>;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+        match &mut self.inner {
+            RecvInner::Direct(inner) => inner.read(buf).await.map_err(StreamError::Inner),
+            RecvInner::Delayed { pending, receiver } => {
+                // State is kept in `pending` between polls, as readers may drop this future.
+                if pending.is_none() {
+                    match receiver.recv().await {
+                        Some(chunk) => *pending = Some(chunk.map_err(StreamError::Inner)?),
+                        None => return Ok(None),
+                    }
+                }
+
+                let Some((at, data)) = pending else {
+                    return Ok(None);
+                };
+
+                tokio::time::sleep_until((*at).into()).await;
+
+                let len = buf.len().min(data.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                data.drain(..len);
+
+                if data.is_empty() {
+                    *pending = None;
+                }
+
+                Ok(Some(len))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamError<E> {
+    Inner(E),
+    Closed,
+}
+
+impl<E: Display> Display for StreamError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inner(e) => e.fmt(f),
+            Self::Closed => f.write_str("Stream closed"),
+        }
+    }
+}
+
+impl<E: Debug + Display> std::error::Error for StreamError<E> {}
+
+#[cfg(test)]
+mod tests {
+    use tokio::task::LocalSet;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct PipeError;
+
+    impl Display for PipeError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("Pipe error")
+        }
+    }
+
+    impl std::error::Error for PipeError {}
+
+    struct PipeWrite(UnboundedSender<Vec<u8>>);
+
+    impl Write for PipeWrite {
+        type Error = PipeError;
+
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.send(buf.to_vec()).map_err(|_| PipeError)?;
+            Ok(buf.len())
+        }
+    }
+
+    struct PipeRead(UnboundedReceiver<Vec<u8>>);
+
+    impl Read for PipeRead {
+        type Error = PipeError;
+
+        async fn read(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+            Ok(self.0.recv().await.map(|data| {
+                buf[..data.len()].copy_from_slice(&data);
+                data.len()
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_latency() {
+        LocalSet::new()
+            .run_until(async {
+                let latency = Duration::from_millis(50);
+                let (sender, receiver) = unbounded_channel();
+
+                let mut send = SimulatedSendStream::new(PipeWrite(sender), latency);
+                let mut recv = SimulatedRecvStream::new(PipeRead(receiver), latency);
+
+                let start = Instant::now();
+                send.write(b"hello").await.unwrap();
+                send.write(b"world").await.unwrap();
+
+                // Read in small chunks, to split the delayed writes.
+                let mut buf = [0; 3];
+                let mut data = Vec::new();
+
+                while data.len() < 10 {
+                    let len = recv.read(&mut buf).await.unwrap().unwrap();
+                    data.extend_from_slice(&buf[..len]);
+                }
+
+                assert_eq!(data, b"helloworld");
+                assert!(start.elapsed() >= latency * 2);
+
+                drop(send);
+                assert_eq!(recv.read(&mut buf).await.unwrap(), None);
+            })
+            .await;
+    }
+}